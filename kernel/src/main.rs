@@ -1,78 +1,54 @@
 #![no_std]
 #![no_main]
 
-#![feature(naked_functions)]
-#![feature(extern_types)]
-
 use core::arch::asm;
+use core::fmt::Write;
+use bootinfo::handoff;
 
 #[panic_handler]
 fn panic_handler(_info: &core::panic::PanicInfo) -> ! {
-    loop {}
+    halt_forever();
 }
 
-static STR: [u8; 12] = *b"Hello World!";
-static mut MUT: [u8; 11] = *b"This is MUT";
+fn halt_forever() -> ! {
+    loop {
+        unsafe {
+            asm!("hlt", options(nostack, nomem));
+        }
+    }
+}
 
+/// Entry point called by `uefi_wrapper`, see the handoff protocol in
+/// `bootinfo`
 #[no_mangle]
-#[naked]
-pub unsafe extern "sysv64" fn _start() -> ! {
-    asm!("
-    42:
-        hlt
-        jmp 42b
-
-        # Check if there is an additional value from the interrupt
-        test sp, 15
-        jnz 4f
-
-        pop rax
-
-    4:
-        # Pop the old ip
-        pop rax
-
-        # Pop the code segment
-        pop rax
-
-        # Pop flags
-        pop rbx
-
-        # Clear all the flags
-        xor rbx, rbx
-        push rbx
-
-        # Push the previous code segment
-        push rax
-
-        # Well, x86 needs that special rip + SYMBOL notation for PIC
-        # What it really means is just `lea rbx [SYMBOL]`, but in a position-
-        # independent fashion
-        lea rbx, [rip + {}]
-        push rbx
-
-        # This kernel should be jumped into via page fault
-        iretq
-
-        # This exists just for testing purposes
-        lea rax, [rip + __KERNEL_BASE]
-        lea rax, [rip + .text]
-        ",
-        sym kmain,
-        options(noreturn),
-    )
+pub extern "sysv64" fn _start(header: &'static handoff::Header) -> ! {
+    // SAFETY: the loader promises the whole blob is mapped
+    let Ok(bootinfo) = (unsafe { handoff::Reader::from_header(header) }) else {
+        // Mismatched loader, there is nowhere to report it
+        halt_forever();
+    };
+
+    kmain(bootinfo);
 }
 
-extern "sysv64" fn kmain() -> ! {
-    unsafe {
-        asm!("xchg rax, rax", in("rax") &STR, options(nostack, nomem));
-        MUT[0] = b'b';
-        asm!("xchg rax, rax", in("rax") core::ptr::addr_of!(MUT), options(nostack, nomem));
+fn kmain(bootinfo: handoff::Reader<'static>) -> ! {
+    let Some(info) = bootinfo.framebuffer() else {
+        halt_forever();
+    };
+
+    let mut fb = fb::Framebuffer::new(
+        info.base as *mut u8,
+        info.memsize as usize,
+        info.scanline_width as usize,
+        info.width as usize,
+        info.height as usize,
+        info.format,
+    );
+
+    let _ = writeln!(fb, "Hello from the kernel!");
+    if let Some(image) = bootinfo.kernel_image() {
+        let _ = writeln!(fb, "Running at {:#x}, {} bytes", image.virt_start, image.size);
     }
 
-    loop {
-        unsafe {
-            asm!("hlt", options(nostack, nomem));
-        }
-    }
+    halt_forever();
 }
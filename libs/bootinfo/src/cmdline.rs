@@ -0,0 +1,87 @@
+//! Kernel command line: tokens separated by spaces, either flags like
+//! `memtest` or `key=value` pairs like `fb=1024x768`. Values can be quoted
+//! to contain spaces, `key="a b"`.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Token<'a> {
+    Flag(&'a str),
+
+    /// Key and value, without the quotes
+    Pair(&'a str, &'a str),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Cmdline<'a>(&'a str);
+
+impl<'a> Cmdline<'a> {
+    pub const fn new(s: &'a str) -> Self {
+        Self(s)
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.0
+    }
+
+    pub fn tokens(&self) -> Tokens<'a> {
+        Tokens { rest: self.0 }
+    }
+
+    /// Value of the last `key=value`, so that later ones override earlier
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.tokens()
+            .filter_map(|t| match t {
+                Token::Pair(k, v) if k == key => Some(v),
+                _ => None,
+            })
+            .last()
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.tokens().any(|t| t == Token::Flag(name))
+    }
+}
+
+#[derive(Clone)]
+pub struct Tokens<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let s = self.rest.trim_start_matches(|c: char| c.is_ascii_whitespace());
+        if s.is_empty() {
+            self.rest = s;
+            return None;
+        }
+
+        // Whitespace inside quotes doesn't end the token
+        let mut quoted = false;
+        let end = s
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    quoted = !quoted;
+                }
+                !quoted && c.is_ascii_whitespace()
+            })
+            .map_or(s.len(), |(i, _)| i);
+
+        let (token, rest) = s.split_at(end);
+        self.rest = rest;
+
+        let token = match token.split_once('=') {
+            Some((key, value)) => Token::Pair(key, unquote(value)),
+            None => Token::Flag(token),
+        };
+        return Some(token);
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
@@ -0,0 +1,660 @@
+//! Self-describing format of the information passed to the kernel.
+//!
+//! The blob starts with a `Header`, followed by `tag_count` tags. Every tag
+//! is a `TagHeader` and `size` bytes of payload, padded with zeros to a
+//! multiple of 8 bytes. Unknown tags can be skipped, and tags holding a
+//! single struct may grow at the end in newer versions without breaking
+//! older readers. Elements of array tags can't grow, older readers would
+//! reject the tag, so a new element layout needs a new tag type.
+//!
+//! The checksum is chosen so that the wrapping sum of all the 32-bit words
+//! of the blob is zero.
+
+use crate::FreeMemory;
+use uefi::memory::Descriptor;
+use uefi::protocols::pci_io::PciFunction;
+
+pub const MAGIC: u64 = u64::from_le_bytes(*b"SOVOSBI\0");
+pub const VERSION: u32 = 1;
+
+const HEADER_WORDS: usize = core::mem::size_of::<Header>() / 8;
+
+#[repr(C)]
+pub struct Header {
+    pub magic:     u64,
+    pub version:   u32,
+
+    /// Size of the whole blob in bytes, including the header
+    pub length:    u32,
+    pub checksum:  u32,
+    pub tag_count: u32,
+}
+
+#[repr(C)]
+pub struct TagHeader {
+    pub typ:  u32,
+
+    /// Size of the payload in bytes, without the padding
+    pub size: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum TagType {
+    /// Array of `uefi::memory::Descriptor`, sorted by `phys_start`.
+    /// Describes the memory at the time of ExitBootServices, so the loader's
+    /// own allocations are still marked as conventional memory. Runtime
+    /// regions have `virt_start` set to where the loader mapped them.
+    MemoryMap = 1,
+    Framebuffer,
+
+    /// Physical address of the ACPI RSDP
+    AcpiRsdp,
+
+    /// Physical address of the SMBIOS (3.0 if possible) entry point
+    Smbios,
+    KernelImage,
+
+    /// UTF-8 string, not null-terminated
+    CommandLine,
+
+    /// Array of `FreeMemory`, what is left after the loader's allocations
+    FreeMemory,
+
+    /// How the kernel's base address was randomized
+    Kaslr,
+
+    /// Initial ramdisk loaded from the boot volume
+    Initrd,
+
+    /// Runtime services after SetVirtualAddressMap, missing if it failed
+    UefiRuntime,
+
+    /// Physical address of the flattened device tree
+    DeviceTree,
+
+    /// `uefi::RtSupported` bits, which runtime services still work. Without
+    /// the tag all of them should.
+    RtProperties,
+
+    /// Array of `PciFunction`, what the firmware enumerated before
+    /// ExitBootServices
+    PciFunctions,
+}
+
+impl TagType {
+    pub fn from_int(x: u32) -> Option<Self> {
+        let x = match x {
+            1 => Self::MemoryMap,
+            2 => Self::Framebuffer,
+            3 => Self::AcpiRsdp,
+            4 => Self::Smbios,
+            5 => Self::KernelImage,
+            6 => Self::CommandLine,
+            7 => Self::FreeMemory,
+            8 => Self::Kaslr,
+            9 => Self::Initrd,
+            10 => Self::UefiRuntime,
+            11 => Self::DeviceTree,
+            12 => Self::RtProperties,
+            13 => Self::PciFunctions,
+            _ => return None,
+        };
+
+        return Some(x);
+    }
+}
+
+/// Where the KASLR seed came from, from the best to the worst
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum EntropySource {
+    /// Set at build time to reproduce a boot
+    Pinned = 1,
+    RngProtocol,
+    Rdseed,
+    Rdrand,
+
+    /// Only the time since reset, easy to guess
+    Tsc,
+}
+
+impl EntropySource {
+    pub fn from_int(x: u32) -> Option<Self> {
+        let x = match x {
+            1 => Self::Pinned,
+            2 => Self::RngProtocol,
+            3 => Self::Rdseed,
+            4 => Self::Rdrand,
+            5 => Self::Tsc,
+            _ => return None,
+        };
+
+        return Some(x);
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FramebufferInfo {
+    /// Virtual address of the first pixel
+    pub base:            u64,
+    pub memsize:         u64,
+
+    /// In pixels
+    pub scanline_width:  u32,
+    pub width:           u32,
+    pub height:          u32,
+    pub bytes_per_pixel: u32,
+    pub format:          fb::PixelFormat,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KernelImage {
+    pub phys_start: u64,
+    pub virt_start: u64,
+
+    /// In bytes, multiple of the page size
+    pub size:       u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KaslrInfo {
+    /// The kernel is at `KERNEL_BASE + slide`
+    pub slide:    u64,
+
+    /// Pass it to `xtask --kaslr-seed` to get the same slide again
+    pub seed:     u64,
+
+    /// `EntropySource` of the seed
+    pub source:   u32,
+    pub reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Initrd {
+    pub phys_start: u64,
+
+    /// In bytes, the rest of the last page is zeroed
+    pub size:       u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct UefiRuntime {
+    /// Virtual address of `uefi::SystemTable`
+    pub system_table:     u64,
+
+    /// Virtual address of `uefi::RuntimeServices`
+    pub runtime_services: u64,
+
+    /// Pages mapped writable and executable, because the Memory Attributes
+    /// Table is missing, doesn't cover them or leaves them unprotected.
+    /// Zero if every runtime page has W^X.
+    pub rwx_pages:        u64,
+}
+
+impl KaslrInfo {
+    pub fn source(&self) -> Option<EntropySource> {
+        EntropySource::from_int(self.source)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Tag<'a> {
+    MemoryMap(&'a [Descriptor]),
+    Framebuffer(&'a FramebufferInfo),
+    AcpiRsdp(u64),
+    Smbios(u64),
+    KernelImage(&'a KernelImage),
+    CommandLine(&'a str),
+    FreeMemory(&'a [FreeMemory]),
+    Kaslr(&'a KaslrInfo),
+    Initrd(&'a Initrd),
+    UefiRuntime(&'a UefiRuntime),
+    DeviceTree(u64),
+    RtProperties(u32),
+    PciFunctions(&'a [PciFunction]),
+    Unknown { typ: u32, data: &'a [u8] },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// Writer's buffer is too small
+    OutOfSpace,
+
+    /// Buffer is smaller than the header or the length in it
+    TooShort,
+    BadMagic,
+    UnsupportedVersion(u32),
+
+    /// Length is smaller than the header or is not a multiple of 8
+    BadLength,
+    BadChecksum,
+
+    /// Tag with the given type doesn't fit in the blob or its payload is
+    /// malformed
+    BadTag(u32),
+}
+
+fn checksum(words: &[u64]) -> u32 {
+    words
+        .iter()
+        .fold(0u32, |sum, &w| sum.wrapping_add(w as u32).wrapping_add((w >> 32) as u32))
+}
+
+fn as_bytes(words: &[u64], size: usize) -> &[u8] {
+    debug_assert!(size <= words.len() * 8);
+    // SAFETY: u8 has no alignment or validity requirements
+    unsafe { core::slice::from_raw_parts(words.as_ptr().cast(), size) }
+}
+
+/// Reinterprets the beginning of `words` as `T`.
+/// SAFETY: every bit pattern must be valid for `T`
+unsafe fn cast<T>(words: &[u64], size: usize) -> Option<&T> {
+    debug_assert!(core::mem::align_of::<T>() <= 8);
+    if size < core::mem::size_of::<T>() {
+        return None;
+    }
+    return Some(unsafe { &*words.as_ptr().cast::<T>() });
+}
+
+/// Reinterprets `size` bytes of `words` as a slice of `T`.
+/// SAFETY: every bit pattern must be valid for `T`
+unsafe fn cast_slice<T>(words: &[u64], size: usize) -> Option<&[T]> {
+    debug_assert!(core::mem::align_of::<T>() <= 8);
+    if !size.is_multiple_of(core::mem::size_of::<T>()) {
+        return None;
+    }
+    let len = size / core::mem::size_of::<T>();
+    return Some(unsafe { core::slice::from_raw_parts(words.as_ptr().cast(), len) });
+}
+
+fn parse_tag(typ: u32, payload: &[u64], size: usize) -> Option<Tag<'_>> {
+    let Some(known) = TagType::from_int(typ) else {
+        return Some(Tag::Unknown { typ, data: as_bytes(payload, size) });
+    };
+
+    // SAFETY: all the payload types are plain integers
+    let tag = unsafe {
+        match known {
+            TagType::MemoryMap => Tag::MemoryMap(cast_slice(payload, size)?),
+            TagType::Framebuffer => Tag::Framebuffer(cast(payload, size)?),
+            TagType::AcpiRsdp => Tag::AcpiRsdp(*cast(payload, size)?),
+            TagType::Smbios => Tag::Smbios(*cast(payload, size)?),
+            TagType::KernelImage => Tag::KernelImage(cast(payload, size)?),
+            TagType::CommandLine => Tag::CommandLine(core::str::from_utf8(as_bytes(payload, size)).ok()?),
+            TagType::FreeMemory => Tag::FreeMemory(cast_slice(payload, size)?),
+            TagType::Kaslr => Tag::Kaslr(cast(payload, size)?),
+            TagType::Initrd => Tag::Initrd(cast(payload, size)?),
+            TagType::UefiRuntime => Tag::UefiRuntime(cast(payload, size)?),
+            TagType::DeviceTree => Tag::DeviceTree(*cast(payload, size)?),
+            TagType::RtProperties => Tag::RtProperties(*cast(payload, size)?),
+            TagType::PciFunctions => Tag::PciFunctions(cast_slice(payload, size)?),
+        }
+    };
+
+    return Some(tag);
+}
+
+/// Iterator over the tags of a blob
+#[derive(Clone)]
+pub struct Tags<'a> {
+    words:     &'a [u64],
+    remaining: u32,
+}
+
+impl<'a> Tags<'a> {
+    /// Splits off the next tag, `Err` carries the type of a malformed one
+    fn next_tag(&mut self) -> Result<Tag<'a>, u32> {
+        let (&raw, rest) = self.words.split_first().ok_or(0u32)?;
+        let typ = raw as u32;
+        let size = (raw >> 32) as usize;
+
+        let words = size.div_ceil(8);
+        if words > rest.len() {
+            return Err(typ);
+        }
+
+        let (payload, rest) = rest.split_at(words);
+        self.words = rest;
+        return parse_tag(typ, payload, size).ok_or(typ);
+    }
+}
+
+impl<'a> Iterator for Tags<'a> {
+    type Item = Tag<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        self.remaining -= 1;
+        // Reader validates all the tags upfront, so this can't fail
+        return self.next_tag().ok();
+    }
+}
+
+/// Validated view of a blob
+#[derive(Clone, Copy)]
+pub struct Reader<'a> {
+    words:     &'a [u64],
+    tag_count: u32,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(words: &'a [u64]) -> Result<Self, Error> {
+        if words.len() < HEADER_WORDS {
+            return Err(Error::TooShort);
+        }
+
+        // SAFETY: Header consists of plain integers and fits in `words`
+        let header = unsafe { &*words.as_ptr().cast::<Header>() };
+        if header.magic != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        if header.version != VERSION {
+            return Err(Error::UnsupportedVersion(header.version));
+        }
+
+        let length = header.length as usize;
+        if !length.is_multiple_of(8) || length / 8 < HEADER_WORDS {
+            return Err(Error::BadLength);
+        }
+        if length / 8 > words.len() {
+            return Err(Error::TooShort);
+        }
+
+        let words = &words[..length / 8];
+        if checksum(words) != 0 {
+            return Err(Error::BadChecksum);
+        }
+
+        let reader = Self { words, tag_count: header.tag_count };
+        let mut tags = reader.tags();
+        while tags.remaining > 0 {
+            tags.remaining -= 1;
+            tags.next_tag().map_err(Error::BadTag)?;
+        }
+
+        return Ok(reader);
+    }
+
+    /// # Safety
+    /// `header` must be followed by at least `header.length` bytes of
+    /// readable memory, if the magic number is correct.
+    pub unsafe fn from_header(header: &'a Header) -> Result<Self, Error> {
+        if header.magic != MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        let len = header.length as usize / 8;
+        let words = unsafe { core::slice::from_raw_parts((header as *const Header).cast::<u64>(), len) };
+        return Self::new(words);
+    }
+
+    pub fn header(&self) -> &'a Header {
+        // SAFETY: checked in `new`
+        unsafe { &*self.words.as_ptr().cast::<Header>() }
+    }
+
+    pub fn tags(&self) -> Tags<'a> {
+        Tags { words: &self.words[HEADER_WORDS..], remaining: self.tag_count }
+    }
+
+    pub fn memory_map(&self) -> Option<&'a [Descriptor]> {
+        self.tags().find_map(|t| match t {
+            Tag::MemoryMap(x) => Some(x),
+            _ => None,
+        })
+    }
+
+    pub fn framebuffer(&self) -> Option<&'a FramebufferInfo> {
+        self.tags().find_map(|t| match t {
+            Tag::Framebuffer(x) => Some(x),
+            _ => None,
+        })
+    }
+
+    pub fn acpi_rsdp(&self) -> Option<u64> {
+        self.tags().find_map(|t| match t {
+            Tag::AcpiRsdp(x) => Some(x),
+            _ => None,
+        })
+    }
+
+    pub fn smbios(&self) -> Option<u64> {
+        self.tags().find_map(|t| match t {
+            Tag::Smbios(x) => Some(x),
+            _ => None,
+        })
+    }
+
+    pub fn kernel_image(&self) -> Option<&'a KernelImage> {
+        self.tags().find_map(|t| match t {
+            Tag::KernelImage(x) => Some(x),
+            _ => None,
+        })
+    }
+
+    pub fn command_line(&self) -> Option<&'a str> {
+        self.tags().find_map(|t| match t {
+            Tag::CommandLine(x) => Some(x),
+            _ => None,
+        })
+    }
+
+    pub fn free_memory(&self) -> Option<&'a [FreeMemory]> {
+        self.tags().find_map(|t| match t {
+            Tag::FreeMemory(x) => Some(x),
+            _ => None,
+        })
+    }
+
+    pub fn kaslr(&self) -> Option<&'a KaslrInfo> {
+        self.tags().find_map(|t| match t {
+            Tag::Kaslr(x) => Some(x),
+            _ => None,
+        })
+    }
+
+    pub fn initrd(&self) -> Option<&'a Initrd> {
+        self.tags().find_map(|t| match t {
+            Tag::Initrd(x) => Some(x),
+            _ => None,
+        })
+    }
+
+    pub fn uefi_runtime(&self) -> Option<&'a UefiRuntime> {
+        self.tags().find_map(|t| match t {
+            Tag::UefiRuntime(x) => Some(x),
+            _ => None,
+        })
+    }
+
+    pub fn device_tree(&self) -> Option<u64> {
+        self.tags().find_map(|t| match t {
+            Tag::DeviceTree(x) => Some(x),
+            _ => None,
+        })
+    }
+
+    pub fn rt_properties(&self) -> Option<u32> {
+        self.tags().find_map(|t| match t {
+            Tag::RtProperties(x) => Some(x),
+            _ => None,
+        })
+    }
+
+    pub fn pci_functions(&self) -> Option<&'a [PciFunction]> {
+        self.tags().find_map(|t| match t {
+            Tag::PciFunctions(x) => Some(x),
+            _ => None,
+        })
+    }
+}
+
+/// Builds a blob in a caller-provided buffer
+pub struct Writer<'a> {
+    buf:       &'a mut [u64],
+    len:       usize,
+    tag_count: u32,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u64]) -> Result<Self, Error> {
+        if buf.len() < HEADER_WORDS {
+            return Err(Error::OutOfSpace);
+        }
+
+        return Ok(Self { buf, len: HEADER_WORDS, tag_count: 0 });
+    }
+
+    /// Bytes needed for a blob with tags of the given payload sizes,
+    /// including the header and the padding
+    pub const fn size_for(payloads: &[usize]) -> usize {
+        let mut words = HEADER_WORDS;
+        let mut i = 0;
+        while i < payloads.len() {
+            words += 1 + payloads[i].div_ceil(8);
+            i += 1;
+        }
+        return words * 8;
+    }
+
+    /// Appends a tag header and returns zeroed space for `size` bytes
+    fn reserve(&mut self, typ: TagType, size: usize) -> Result<&mut [u64], Error> {
+        let size32 = u32::try_from(size).map_err(|_| Error::OutOfSpace)?;
+        let words = 1 + size.div_ceil(8);
+        let tag = self.buf.get_mut(self.len..).and_then(|b| b.get_mut(..words)).ok_or(Error::OutOfSpace)?;
+
+        tag.fill(0);
+        tag[0] = (size32 as u64) << 32 | typ as u64;
+        self.len += words;
+        self.tag_count += 1;
+        return Ok(&mut tag[1..]);
+    }
+
+    fn push<T: Copy>(&mut self, typ: TagType, value: &T) -> Result<(), Error> {
+        self.push_slice(typ, core::slice::from_ref(value))
+    }
+
+    fn push_slice<T: Copy>(&mut self, typ: TagType, values: &[T]) -> Result<(), Error> {
+        debug_assert!(core::mem::align_of::<T>() <= 8);
+        let size = core::mem::size_of_val(values);
+        let payload = self.reserve(typ, size)?;
+        // SAFETY: `payload` has room for `size` bytes and is 8-byte aligned
+        unsafe {
+            core::ptr::copy_nonoverlapping(values.as_ptr(), payload.as_mut_ptr().cast::<T>(), values.len());
+        }
+        return Ok(());
+    }
+
+    /// Copies the descriptors and sorts them by `phys_start`. Returns the
+    /// copy, so that the caller can fill in virtual addresses.
+    pub fn memory_map<'d, I>(&mut self, map: I) -> Result<&mut [Descriptor], Error>
+    where
+        I: Iterator<Item = &'d Descriptor> + Clone,
+    {
+        let count = map.clone().count();
+        let size = count * core::mem::size_of::<Descriptor>();
+        let payload = self.reserve(TagType::MemoryMap, size)?;
+        let ptr = payload.as_mut_ptr().cast::<Descriptor>();
+
+        // SAFETY: `payload` has room for `count` descriptors and is aligned
+        let out = unsafe { core::slice::from_raw_parts_mut(ptr, count) };
+        for (slot, desc) in out.iter_mut().zip(map) {
+            *slot = *desc;
+        }
+
+        out.sort_unstable_by_key(|d| d.phys_start);
+        return Ok(out);
+    }
+
+    pub fn framebuffer(&mut self, info: &FramebufferInfo) -> Result<(), Error> {
+        self.push(TagType::Framebuffer, info)
+    }
+
+    pub fn acpi_rsdp(&mut self, addr: u64) -> Result<(), Error> {
+        self.push(TagType::AcpiRsdp, &addr)
+    }
+
+    pub fn smbios(&mut self, addr: u64) -> Result<(), Error> {
+        self.push(TagType::Smbios, &addr)
+    }
+
+    pub fn kernel_image(&mut self, image: &KernelImage) -> Result<(), Error> {
+        self.push(TagType::KernelImage, image)
+    }
+
+    pub fn command_line(&mut self, cmdline: &str) -> Result<(), Error> {
+        self.push_slice(TagType::CommandLine, cmdline.as_bytes())
+    }
+
+    pub fn free_memory(&mut self, free: &[FreeMemory]) -> Result<(), Error> {
+        self.push_slice(TagType::FreeMemory, free)
+    }
+
+    pub fn kaslr(&mut self, info: &KaslrInfo) -> Result<(), Error> {
+        self.push(TagType::Kaslr, info)
+    }
+
+    pub fn initrd(&mut self, initrd: &Initrd) -> Result<(), Error> {
+        self.push(TagType::Initrd, initrd)
+    }
+
+    pub fn uefi_runtime(&mut self, runtime: &UefiRuntime) -> Result<(), Error> {
+        self.push(TagType::UefiRuntime, runtime)
+    }
+
+    pub fn device_tree(&mut self, addr: u64) -> Result<(), Error> {
+        self.push(TagType::DeviceTree, &addr)
+    }
+
+    pub fn rt_properties(&mut self, supported: u32) -> Result<(), Error> {
+        self.push(TagType::RtProperties, &supported)
+    }
+
+    pub fn pci_functions(&mut self, functions: &[PciFunction]) -> Result<(), Error> {
+        self.push_slice(TagType::PciFunctions, functions)
+    }
+
+    /// Address of the blob, it doesn't change after `finish`
+    pub fn as_ptr(&self) -> *const Header {
+        self.buf.as_ptr().cast()
+    }
+
+    /// Size of the whole buffer in bytes
+    pub fn capacity(&self) -> usize {
+        self.buf.len() * 8
+    }
+
+    /// Tags written so far
+    pub fn tags(&self) -> Tags<'_> {
+        Tags { words: &self.buf[HEADER_WORDS..self.len], remaining: self.tag_count }
+    }
+
+    /// Fills in the header and returns the finished blob
+    pub fn finish(self) -> &'a [u64] {
+        let blob = &mut self.buf[..self.len];
+        let header = Header {
+            magic:     MAGIC,
+            version:   VERSION,
+            length:    (self.len * 8) as u32,
+            checksum:  0,
+            tag_count: self.tag_count,
+        };
+
+        // SAFETY: the buffer has room for the header, see `new`
+        unsafe { blob.as_mut_ptr().cast::<Header>().write(header) };
+        let sum = checksum(blob);
+        // SAFETY: as above
+        unsafe { (*blob.as_mut_ptr().cast::<Header>()).checksum = 0u32.wrapping_sub(sum) };
+
+        return blob;
+    }
+}
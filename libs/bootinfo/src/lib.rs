@@ -1,3 +1,47 @@
+//! Information passed from `uefi_wrapper` to the kernel.
+//!
+//! # Handoff protocol
+//! The loader enters the kernel at `_start` as if it was called with the
+//! sysv64 ABI:
+//!
+//! ```ignore
+//! extern "sysv64" fn _start(handoff: &'static handoff::Header) -> !;
+//! ```
+//!
+//! * `rdi` holds the virtual address of a blob in the `handoff` format,
+//!   `rbp` is zero and the return address on the stack is zero too.
+//! * `rsp` points to the top of a stack of `KERNEL_STACK_SIZE` bytes,
+//!   aligned like after a `call`.
+//! * Interrupts are disabled. The GDT is `Bootinfo::gdt`, CS is
+//!   `CODE_DESCRIPTOR_OFFSET`, DS and SS are `DATA_DESCRIPTOR_OFFSET`,
+//!   ES, FS and GS are null. The IDT is `Bootinfo::idt` and none of its
+//!   entries are present.
+//! * CR3 points to a 4-level hierarchy of tables, which maps the kernel
+//!   image at `KERNEL_BASE + slide`, the handoff blob, `Bootinfo`, the stack
+//!   and the framebuffer at `phys + VIRT_OFFSET` and all the usable physical
+//!   memory at `phys + DIRECT_MAP`. The lower half contains only the
+//!   loader's trampoline and should be discarded.
+//! * UEFI runtime regions are packed at `UEFI_RUNTIME_BASE`, in the order of
+//!   their physical addresses, and the firmware was already told about it
+//!   with SetVirtualAddressMap, see the `UefiRuntime` tag. Where the Memory
+//!   Attributes Table says so, code is read-only and data is not
+//!   executable. Code it doesn't describe holds data too, so it is writable
+//!   and executable, the tag says how much of it there is. Everything else
+//!   is not executable.
+//! * Kernel segments are mapped with their own permissions, no page is both
+//!   writable and executable and `PT_GNU_RELRO` is read-only. Relocations
+//!   are already applied. The slide is a random multiple of
+//!   `KERNEL_SLIDE_ALIGN` below `KERNEL_SLIDE_RANGE`, see the `Kaslr` tag.
+//! * CR0 has PE, MP, NE, WP and PG set, EM, TS, AM, NW and CD clear.
+//! * CR4 has PAE and PGE set, LA57, SMEP and SMAP clear. Other bits are left
+//!   as the firmware set them.
+//! * EFER has LME, LMA and NXE set, SCE clear.
+//!
+//! The framebuffer tag has the framebuffer's virtual address, other
+//! addresses in the handoff are physical. `Bootinfo` itself is the loader's
+//! working memory, it stays mapped, but the kernel should not rely on
+//! anything in it.
+
 #![no_std]
 
 use arrayvec::ArrayVecSized;
@@ -6,7 +50,36 @@ use uefi;
 use fb;
 use core::num::NonZeroU64;
 
+pub mod cmdline;
+pub mod handoff;
+pub mod loader;
+
+/// Where `Bootinfo`, the stack and the framebuffer are mapped
+pub const VIRT_OFFSET: u64 = 0xFFFF_C000_0000_0000;
+
+/// Where the UEFI runtime regions are mapped, up to `KERNEL_BASE`
+pub const UEFI_RUNTIME_BASE: u64 = 0xFFFF_FFFF_0000_0000;
+
+/// Lowest address of the kernel image, the last 2GiB of the address space
+pub const KERNEL_BASE: u64 = 0xFFFF_FFFF_8000_0000;
+
+/// The whole kernel image fits in `KERNEL_BASE..KERNEL_BASE + KERNEL_SLIDE_RANGE`
+pub const KERNEL_SLIDE_RANGE: u64 = 1 << 30;
+pub const KERNEL_SLIDE_ALIGN: u64 = 2 << 20;
+
+/// Where all the usable physical memory is mapped
+pub const DIRECT_MAP: u64 = 0xFFFF_8000_0000_0000;
+
+pub const KERNEL_STACK_SIZE: u64 = 64 << 10;
+
+/// Capacity of `Bootinfo::free_memory`
+pub const FREE_MEMORY_SLOTS: usize = 32;
+
+/// Capacity of `Bootinfo::cmdline`
+pub const CMDLINE_SIZE: usize = 256;
+
 #[repr(C)]
+#[derive(Clone, Copy, Debug)]
 pub struct FreeMemory {
     pub phys_start: u64,
     pub pages: u64,
@@ -17,11 +90,19 @@ pub struct Bootinfo {
     pub buf:           [u64; 1024],
     pub idt:           cpu::interrupt::Table,
     pub gdt:           cpu::segmentation::GlobalDescriptorTable,
-    pub free_memory:   ArrayVecSized<FreeMemory, 32>,
+    pub free_memory:   ArrayVecSized<FreeMemory, FREE_MEMORY_SLOTS>,
 
     pub free_memory_at_null: Option<NonZeroU64>,
 
     pub fb:            fb::Framebuffer,
-    pub uefi_meminfo:  ArrayVecSized<uefi::memory::Descriptor, 128>,
+
+    /// Kernel command line, printable ASCII
+    pub cmdline:       ArrayVecSized<u8, CMDLINE_SIZE>,
     pub uefi_systable: Option<&'static uefi::SystemTable>,
 }
+
+impl Bootinfo {
+    pub fn command_line(&self) -> cmdline::Cmdline<'_> {
+        cmdline::Cmdline::new(core::str::from_utf8(self.cmdline.as_slice()).unwrap_or(""))
+    }
+}
@@ -0,0 +1,161 @@
+//! Decisions the loader makes that don't need real firmware, kept out of
+//! the wrapper so that they can be tested on the host against `uefi::mock`
+
+use core::fmt::Write;
+use core::num::NonZeroU64;
+
+use arrayvec::ArrayVec;
+use cpu::paging::PAGE_SIZE;
+use uefi::memory::{Descriptor, Type};
+use uefi::protocols::gop::{GraphicsOutput, PixelFormat};
+use uefi::protocols::pci_io::PciFunction;
+
+use crate::cmdline::Cmdline;
+use crate::{handoff, FreeMemory, CMDLINE_SIZE, FREE_MEMORY_SLOTS, KERNEL_BASE, UEFI_RUNTIME_BASE};
+
+/// "WIDTHxHEIGHT"
+pub fn parse_resolution(s: &str) -> Option<(u32, u32)> {
+    let (width, height) = s.split_once('x')?;
+    return Some((width.parse().ok()?, height.parse().ok()?));
+}
+
+/// `fb=WIDTHxHEIGHT` from the command line, otherwise `fallback`
+pub fn preferred_resolution(
+    cmdline: &Cmdline,
+    fallback: Option<(u32, u32)>,
+    out: &mut impl Write,
+) -> Option<(u32, u32)> {
+    if let Some(resolution) = cmdline.get("fb") {
+        match parse_resolution(resolution) {
+            Some(resolution) => return Some(resolution),
+            None => {
+                let _ = writeln!(out, "Ignoring fb={}, it's not WIDTHxHEIGHT", resolution);
+            },
+        }
+    }
+
+    return fallback;
+}
+
+/// The preferred resolution if there is such a mode, otherwise the biggest
+/// one, preferring modes with a linear framebuffer
+pub fn choose_mode(gop: &GraphicsOutput, preferred: Option<(u32, u32)>, out: &mut impl Write) -> u32 {
+    let modes = (0..gop.mode().max_mode).filter_map(|i| Some((i, gop.query_mode(i).ok()?)));
+
+    if let Some((width, height)) = preferred {
+        let preferred = modes.clone().find(|(_, m)| m.horizontal_res == width && m.vertical_res == height);
+        match preferred {
+            Some((i, _)) => return i,
+            None => {
+                let _ = writeln!(out, "No {}x{} mode, using the biggest one", width, height);
+            },
+        }
+    }
+
+    return modes
+        .max_by_key(|(_, m)| {
+            let linear = m.pixel_format() != Some(PixelFormat::BltOnly);
+            (linear, m.horizontal_res * m.vertical_res)
+        })
+        .map_or(gop.mode().mode, |(i, _)| i);
+}
+
+/// Puts the conventional memory from the final memory map into
+/// `free_memory`. Memory at address 0 is kept apart, as a null pointer can't
+/// point to it, and its size is returned.
+///
+/// Adjacent regions are merged. If there are still more than fit, the
+/// smallest ones are left out, losing a little memory beats not booting.
+pub fn collect_free_memory(memmap: &[Descriptor], free_memory: &mut ArrayVec<FreeMemory>) -> Option<NonZeroU64> {
+    let mut at_null = None;
+    for map in memmap {
+        if map.phys_start == 0 {
+            assert_eq!(at_null, None);
+            at_null = NonZeroU64::new(map.pages);
+            continue;
+        }
+
+        // Anything the kernel can't map write-back is not plain RAM to it
+        if Type::from_int(map.typ) != Some(Type::Conventional) || !map.attributes.write_back() {
+            continue;
+        }
+
+        let adjacent = free_memory
+            .iter_mut()
+            .find(|m| m.phys_start + m.pages * PAGE_SIZE == map.phys_start);
+        if let Some(m) = adjacent {
+            m.pages += map.pages;
+            continue;
+        }
+
+        let region = FreeMemory { phys_start: map.phys_start, pages: map.pages };
+        if !free_memory.is_full() {
+            free_memory.push(region);
+            continue;
+        }
+
+        let smallest = free_memory.iter_mut().min_by_key(|m| m.pages);
+        if let Some(smallest) = smallest.filter(|m| m.pages < region.pages) {
+            *smallest = region;
+        }
+    }
+
+    return at_null;
+}
+
+/// Upper bound of the handoff's size, with every tag the loader can write.
+/// Nothing can be allocated after ExitBootServices, so it can't run out.
+pub fn handoff_size(memmap: &[Descriptor], pci: &[PciFunction]) -> usize {
+    use core::mem::{size_of, size_of_val};
+
+    return handoff::Writer::size_for(&[
+        size_of_val(memmap),
+        size_of::<handoff::FramebufferInfo>(),
+        size_of::<u64>(), // AcpiRsdp
+        size_of::<u64>(), // Smbios
+        size_of::<handoff::KernelImage>(),
+        CMDLINE_SIZE,
+        FREE_MEMORY_SLOTS * size_of::<FreeMemory>(),
+        size_of::<handoff::KaslrInfo>(),
+        size_of::<handoff::Initrd>(),
+        size_of::<handoff::UefiRuntime>(),
+        size_of::<u64>(), // DeviceTree
+        size_of::<u32>(), // RtProperties
+        size_of_val(pci),
+    ]);
+}
+
+/// Packs the runtime regions one after another at `UEFI_RUNTIME_BASE`.
+/// `memory_map` has to be sorted, so the order of the regions is kept.
+pub fn assign_runtime_addresses(memory_map: &mut [Descriptor]) {
+    let mut next = UEFI_RUNTIME_BASE;
+    for d in memory_map.iter_mut().filter(|d| d.attributes.runtime()) {
+        d.virt_start = next;
+        next += d.pages * PAGE_SIZE;
+    }
+
+    assert!(next <= KERNEL_BASE, "UEFI runtime regions don't fit below the kernel");
+}
+
+/// Starts the handoff in `buf`, which should be `handoff_size` bytes, with
+/// what is known right after ExitBootServices. The memory map is sorted and
+/// has the runtime regions at their new addresses.
+pub fn start_handoff<'a>(
+    buf: &'a mut [u64],
+    memmap: &[Descriptor],
+    pci: &[PciFunction],
+    initrd: Option<&handoff::Initrd>,
+    cmdline: &str,
+) -> Result<handoff::Writer<'a>, handoff::Error> {
+    let mut handoff = handoff::Writer::new(buf)?;
+    assign_runtime_addresses(handoff.memory_map(memmap.iter())?);
+    handoff.pci_functions(pci)?;
+    if let Some(initrd) = initrd {
+        handoff.initrd(initrd)?;
+    }
+    if !cmdline.is_empty() {
+        handoff.command_line(cmdline)?;
+    }
+
+    return Ok(handoff);
+}
@@ -0,0 +1,37 @@
+use bootinfo::cmdline::*;
+
+#[test]
+fn tokens() {
+    let cmdline = Cmdline::new("  log=serial memtest\tfb=1024x768 root=\"my disk\" empty= ");
+    let tokens: Vec<Token> = cmdline.tokens().collect();
+    assert_eq!(tokens, [
+        Token::Pair("log", "serial"),
+        Token::Flag("memtest"),
+        Token::Pair("fb", "1024x768"),
+        Token::Pair("root", "my disk"),
+        Token::Pair("empty", ""),
+    ]);
+
+    assert_eq!(Cmdline::new("").tokens().count(), 0);
+    assert_eq!(Cmdline::new("   ").tokens().count(), 0);
+}
+
+#[test]
+fn lookups() {
+    let cmdline = Cmdline::new("log=fb memtest log=serial a=b=c");
+    assert_eq!(cmdline.get("log"), Some("serial"));
+    assert_eq!(cmdline.get("a"), Some("b=c"));
+    assert_eq!(cmdline.get("memtest"), None);
+    assert_eq!(cmdline.get("fb"), None);
+
+    assert!(cmdline.flag("memtest"));
+    assert!(!cmdline.flag("log"));
+    assert!(!cmdline.flag("mem"));
+}
+
+#[test]
+fn unterminated_quote() {
+    let cmdline = Cmdline::new("title=\"a b c quiet");
+    let tokens: Vec<Token> = cmdline.tokens().collect();
+    assert_eq!(tokens, [Token::Pair("title", "\"a b c quiet")]);
+}
@@ -0,0 +1,242 @@
+use bootinfo::handoff::*;
+use bootinfo::FreeMemory;
+use uefi::memory::{Attributes, Descriptor, Type};
+use uefi::protocols::pci_io::{Bar, BarFlags, PciFunction};
+
+fn descriptor(typ: Type, phys_start: u64, pages: u64) -> Descriptor {
+    Descriptor::new(typ, phys_start, pages, Attributes::new().set_write_back())
+}
+
+fn fb_info() -> FramebufferInfo {
+    FramebufferInfo {
+        base:            0xFFFF_C000_8000_0000,
+        memsize:         1024 * 768 * 4,
+        scanline_width:  1024,
+        width:           1024,
+        height:          768,
+        bytes_per_pixel: 4,
+        format:          fb::PixelFormat::BGR,
+    }
+}
+
+fn kaslr_info() -> KaslrInfo {
+    KaslrInfo {
+        slide:    0x1260_0000,
+        seed:     42,
+        source:   EntropySource::Pinned as u32,
+        reserved: 0,
+    }
+}
+
+fn ahci() -> PciFunction {
+    let mut bars = [Bar::NONE; 6];
+    bars[5] = Bar { base: 0xFEBD_5000, size: 0x1000, attributes: 0, flags: BarFlags::new(), _reserved: 0 };
+    bars[4] = Bar { base: 0xC040, size: 0x20, attributes: 0, flags: BarFlags::new().set_io(), _reserved: 0 };
+
+    PciFunction {
+        segment: 0,
+        bus: 0,
+        device: 0x1F,
+        function: 2,
+        header_type: 0,
+        vendor_id: 0x8086,
+        device_id: 0x2922,
+        class: 0x01,
+        subclass: 0x06,
+        prog_if: 0x01,
+        revision: 0x02,
+        _reserved: 0,
+        attributes: 0x100,
+        bars,
+    }
+}
+
+/// Recomputes the checksum after the test messes with the blob
+fn fix_checksum(blob: &mut [u64]) {
+    blob[2] &= !0xFFFF_FFFF;
+    let sum = blob
+        .iter()
+        .fold(0u32, |sum, &w| sum.wrapping_add(w as u32).wrapping_add((w >> 32) as u32));
+    blob[2] |= 0u32.wrapping_sub(sum) as u64;
+}
+
+fn write_all(buf: &mut [u64]) -> &[u64] {
+    let map = [
+        descriptor(Type::Conventional, 0x10_0000, 16),
+        descriptor(Type::AcpiReclaim, 0x8000, 2),
+        descriptor(Type::Mmio, 0xFEC0_0000, 1),
+        descriptor(Type::RuntimeServicesData, 0x9000, 4),
+    ];
+
+    let mut w = Writer::new(buf).unwrap();
+    let copy = w.memory_map(map.iter()).unwrap();
+    copy[1].virt_start = 0xFFFF_FFFF_0000_0000;
+    w.framebuffer(&fb_info()).unwrap();
+    w.acpi_rsdp(0xE_0000).unwrap();
+    w.smbios(0xF_0000).unwrap();
+    w.kernel_image(&KernelImage { phys_start: 0x20_0000, virt_start: 0xFFFF_C000_0020_0000, size: 0x3000 }).unwrap();
+    w.command_line("log=debug quiet").unwrap();
+    w.free_memory(&[FreeMemory { phys_start: 0x10_4000, pages: 12 }]).unwrap();
+    w.kaslr(&kaslr_info()).unwrap();
+    w.initrd(&Initrd { phys_start: 0x4000_0000, size: 12345 }).unwrap();
+    w.uefi_runtime(&UefiRuntime {
+        system_table:     0xFFFF_FFFF_0000_0018,
+        runtime_services: 0xFFFF_FFFF_0000_0100,
+        rwx_pages:        0,
+    })
+    .unwrap();
+    w.device_tree(0x4800_0000).unwrap();
+    w.rt_properties(0x3FFF).unwrap();
+    w.pci_functions(&[ahci()]).unwrap();
+    w.finish()
+}
+
+#[test]
+fn roundtrip() {
+    let mut buf = vec![0u64; 128];
+    let blob = write_all(&mut buf);
+    let r = Reader::new(blob).unwrap();
+
+    assert_eq!(r.header().magic, MAGIC);
+    assert_eq!(r.header().version, VERSION);
+    assert_eq!(r.header().length as usize, blob.len() * 8);
+    assert_eq!(r.tags().count(), 13);
+
+    let starts: Vec<u64> = r.memory_map().unwrap().iter().map(|d| d.phys_start).collect();
+    assert_eq!(starts, [0x8000, 0x9000, 0x10_0000, 0xFEC0_0000]);
+    assert_eq!(r.memory_map().unwrap()[1].virt_start, 0xFFFF_FFFF_0000_0000);
+    assert_eq!(r.memory_map().unwrap()[0].memory_type(), Some(Type::AcpiReclaim));
+
+    assert_eq!(r.framebuffer(), Some(&fb_info()));
+    assert_eq!(r.acpi_rsdp(), Some(0xE_0000));
+    assert_eq!(r.smbios(), Some(0xF_0000));
+    assert_eq!(r.kernel_image().unwrap().size, 0x3000);
+    assert_eq!(r.command_line(), Some("log=debug quiet"));
+    assert_eq!(r.free_memory().unwrap()[0].pages, 12);
+    assert_eq!(r.kaslr(), Some(&kaslr_info()));
+    assert_eq!(r.kaslr().unwrap().source(), Some(EntropySource::Pinned));
+    assert_eq!(r.initrd().unwrap().size, 12345);
+    assert_eq!(r.uefi_runtime().unwrap().runtime_services, 0xFFFF_FFFF_0000_0100);
+    assert_eq!(r.device_tree(), Some(0x4800_0000));
+    assert_eq!(r.rt_properties(), Some(0x3FFF));
+
+    let pci = r.pci_functions().unwrap();
+    assert_eq!(pci.len(), 1);
+    assert_eq!((pci[0].vendor_id, pci[0].device_id, pci[0].class), (0x8086, 0x2922, 0x01));
+    assert!(pci[0].bars[4].flags.io());
+    assert_eq!(pci[0].bars[5].base, 0xFEBD_5000);
+}
+
+#[test]
+fn missing_tags() {
+    let mut buf = vec![0u64; 8];
+    let blob = Writer::new(&mut buf).unwrap().finish();
+    let r = Reader::new(blob).unwrap();
+
+    assert_eq!(r.tags().count(), 0);
+    assert!(r.memory_map().is_none());
+    assert!(r.command_line().is_none());
+}
+
+#[test]
+fn writer_out_of_space() {
+    let mut buf = vec![0u64; 2];
+    assert_eq!(Writer::new(&mut buf).err(), Some(Error::OutOfSpace));
+
+    let mut buf = vec![0u64; 5];
+    let mut w = Writer::new(&mut buf).unwrap();
+    w.acpi_rsdp(1).unwrap();
+    assert_eq!(w.smbios(2), Err(Error::OutOfSpace));
+
+    // The failed tag must not be counted
+    assert_eq!(w.tags().count(), 1);
+    assert!(Reader::new(w.finish()).is_ok());
+}
+
+#[test]
+fn size_for() {
+    let cmdline = "console=fb quiet";
+    let size = Writer::size_for(&[8, cmdline.len(), 3 * core::mem::size_of::<FreeMemory>()]);
+    assert_eq!(size, 24 + 16 + 24 + 56);
+
+    let free = [FreeMemory { phys_start: 0x1000, pages: 1 }; 3];
+    let mut buf = vec![0u64; size / 8];
+    let mut w = Writer::new(&mut buf).unwrap();
+    w.acpi_rsdp(1).unwrap();
+    w.command_line(cmdline).unwrap();
+    w.free_memory(&free).unwrap();
+    assert_eq!(w.finish().len() * 8, size);
+
+    let mut buf = vec![0u64; size / 8 - 1];
+    let mut w = Writer::new(&mut buf).unwrap();
+    w.acpi_rsdp(1).unwrap();
+    w.command_line(cmdline).unwrap();
+    assert_eq!(w.free_memory(&free), Err(Error::OutOfSpace));
+}
+
+#[test]
+fn corrupted_blobs() {
+    let mut buf = vec![0u64; 128];
+    let blob = write_all(&mut buf).to_vec();
+
+    let mut bad = blob.clone();
+    bad[0] ^= 1;
+    assert_eq!(Reader::new(&bad).err(), Some(Error::BadMagic));
+
+    let mut bad = blob.clone();
+    bad[1] += 1;
+    assert_eq!(Reader::new(&bad).err(), Some(Error::UnsupportedVersion(VERSION + 1)));
+
+    let mut bad = blob.clone();
+    bad[1] += 1 << 32;
+    assert_eq!(Reader::new(&bad).err(), Some(Error::BadLength));
+
+    assert_eq!(Reader::new(&blob[..blob.len() - 1]).err(), Some(Error::TooShort));
+    assert_eq!(Reader::new(&blob[..2]).err(), Some(Error::TooShort));
+
+    let mut bad = blob.clone();
+    *bad.last_mut().unwrap() ^= 0x100;
+    assert_eq!(Reader::new(&bad).err(), Some(Error::BadChecksum));
+}
+
+#[test]
+fn malformed_tag() {
+    let mut buf = vec![0u64; 16];
+    let mut w = Writer::new(&mut buf).unwrap();
+    w.command_line("ok").unwrap();
+    let blob = w.finish().to_vec();
+
+    // Invalid UTF-8: 'o' -> 0xFF
+    let mut bad = blob.clone();
+    bad[4] = bad[4] - 0x6F + 0xFF;
+    fix_checksum(&mut bad);
+    assert_eq!(Reader::new(&bad).err(), Some(Error::BadTag(TagType::CommandLine as u32)));
+
+    // Payload bigger than the blob
+    let mut bad = blob.clone();
+    bad[3] += 100 << 32;
+    fix_checksum(&mut bad);
+    assert_eq!(Reader::new(&bad).err(), Some(Error::BadTag(TagType::CommandLine as u32)));
+}
+
+#[test]
+fn unknown_tags_are_skipped() {
+    let mut buf = vec![0u64; 16];
+    let mut w = Writer::new(&mut buf).unwrap();
+    w.acpi_rsdp(0x1234).unwrap();
+    let mut blob = w.finish().to_vec();
+
+    // Pretend the RSDP tag is something from the future
+    blob[3] += 100;
+    fix_checksum(&mut blob);
+
+    let r = Reader::new(&blob).unwrap();
+    assert!(r.acpi_rsdp().is_none());
+    match r.tags().next().unwrap() {
+        Tag::Unknown { typ, data } => {
+            assert_eq!(typ, TagType::AcpiRsdp as u32 + 100);
+            assert_eq!(data, 0x1234u64.to_le_bytes());
+        },
+        tag => panic!("unexpected tag {:?}", tag),
+    }
+}
@@ -0,0 +1,172 @@
+use arrayvec::ArrayVecSized;
+use bootinfo::cmdline::Cmdline;
+use bootinfo::handoff::{self, Reader};
+use bootinfo::loader::*;
+use bootinfo::{FreeMemory, CMDLINE_SIZE, FREE_MEMORY_SLOTS, UEFI_RUNTIME_BASE};
+use uefi::memory::{Attributes, Descriptor, Type};
+use uefi::mock::MockFirmware;
+use uefi::protocols::gop::{GraphicsOutput, PixelFormat};
+
+const TYPICAL_MEMORY: Attributes = Attributes::new()
+    .set_noncacheable()
+    .set_write_combine()
+    .set_write_through()
+    .set_write_back();
+
+fn choose(fw: &mut MockFirmware, preferred: Option<(u32, u32)>) -> (u32, String) {
+    let bs = fw.system_table().boot_services().unwrap();
+    let gop = bs.locate_protocol::<GraphicsOutput>().unwrap();
+    let mut out = String::new();
+    let mode = choose_mode(gop, preferred, &mut out);
+    return (mode, out);
+}
+
+#[test]
+fn resolution() {
+    assert_eq!(parse_resolution("1024x768"), Some((1024, 768)));
+    assert_eq!(parse_resolution("1024"), None);
+    assert_eq!(parse_resolution("x768"), None);
+    assert_eq!(parse_resolution("1024x-1"), None);
+
+    let mut out = String::new();
+    let cmdline = Cmdline::new("quiet fb=800x600");
+    assert_eq!(preferred_resolution(&cmdline, Some((640, 480)), &mut out), Some((800, 600)));
+    assert_eq!(preferred_resolution(&Cmdline::new("quiet"), Some((640, 480)), &mut out), Some((640, 480)));
+    assert_eq!(preferred_resolution(&Cmdline::new(""), None, &mut out), None);
+    assert_eq!(out, "");
+
+    let cmdline = Cmdline::new("fb=big");
+    assert_eq!(preferred_resolution(&cmdline, Some((640, 480)), &mut out), Some((640, 480)));
+    assert_eq!(out, "Ignoring fb=big, it's not WIDTHxHEIGHT\n");
+}
+
+#[test]
+fn modes() {
+    let mut fw = MockFirmware::new();
+    fw.add_gop_mode(640, 480, PixelFormat::Bgrr8bpc);
+    fw.add_gop_mode(1920, 1080, PixelFormat::BltOnly);
+    fw.add_gop_mode(1024, 768, PixelFormat::Rgbr8bpc);
+    fw.add_gop_mode(800, 600, PixelFormat::Bgrr8bpc);
+
+    // The biggest one can only be drawn to with Blt
+    assert_eq!(choose(&mut fw, None), (2, String::new()));
+    assert_eq!(choose(&mut fw, Some((800, 600))), (3, String::new()));
+    assert_eq!(choose(&mut fw, Some((1920, 1080))), (1, String::new()));
+
+    let (mode, out) = choose(&mut fw, Some((1280, 1024)));
+    assert_eq!(mode, 2);
+    assert_eq!(out, "No 1280x1024 mode, using the biggest one\n");
+}
+
+#[test]
+fn only_blt_modes() {
+    let mut fw = MockFirmware::new();
+    fw.add_gop_mode(640, 480, PixelFormat::BltOnly);
+    fw.add_gop_mode(1024, 768, PixelFormat::BltOnly);
+    assert_eq!(choose(&mut fw, None).0, 1);
+}
+
+#[test]
+fn handoff_after_exit() {
+    let runtime = TYPICAL_MEMORY.set_runtime();
+    let map = vec![
+        Descriptor::new(Type::Conventional, 0x10_0000, 0x100, TYPICAL_MEMORY),
+        Descriptor::new(Type::RuntimeServicesData, 0x8_0000, 4, runtime),
+        Descriptor::new(Type::Conventional, 0, 0x10, TYPICAL_MEMORY),
+        Descriptor::new(Type::RuntimeServicesCode, 0x7_0000, 2, runtime),
+        Descriptor::new(Type::LoaderData, 0x20_0000, 0x40, TYPICAL_MEMORY),
+        Descriptor::new(Type::Conventional, 0x30_0000, 0x200, TYPICAL_MEMORY),
+    ];
+    let mut fw = MockFirmware::new();
+    fw.on_memory_map(move || Ok((map.clone(), 1)));
+    let handle = fw.image_handle();
+    let memmap = fw.system_table().exit_boot_services_with_map(handle).unwrap();
+
+    let mut free_memory = ArrayVecSized::<FreeMemory, FREE_MEMORY_SLOTS>::default();
+    let at_null = collect_free_memory(&memmap, &mut free_memory);
+    assert_eq!(at_null.map(|pages| pages.get()), Some(0x10));
+    let free: Vec<_> = free_memory.iter().map(|m| (m.phys_start, m.pages)).collect();
+    assert_eq!(free, [(0x10_0000, 0x100), (0x30_0000, 0x200)]);
+
+    let size = handoff_size(&memmap, &[]);
+    assert_eq!(size % 8, 0);
+    let mut buf = vec![0u64; size / 8];
+    let initrd = handoff::Initrd { phys_start: 0x20_0000, size: 0x3F000 };
+    // As big as it can be, like everything the loader writes later
+    let cmdline = "x".repeat(CMDLINE_SIZE);
+    let mut w = start_handoff(&mut buf, &memmap, &[], Some(&initrd), &cmdline).unwrap();
+
+    let free = [FreeMemory { phys_start: 0, pages: 0 }; FREE_MEMORY_SLOTS];
+    w.framebuffer(&handoff::FramebufferInfo {
+        base:            0,
+        memsize:         0,
+        scanline_width:  0,
+        width:           0,
+        height:          0,
+        bytes_per_pixel: 4,
+        format:          fb::PixelFormat::BGR,
+    })
+    .unwrap();
+    w.acpi_rsdp(0xE_0000).unwrap();
+    w.smbios(0xF_0000).unwrap();
+    w.kernel_image(&handoff::KernelImage { phys_start: 0, virt_start: 0, size: 0 }).unwrap();
+    w.free_memory(&free).unwrap();
+    w.kaslr(&handoff::KaslrInfo { slide: 0, seed: 0, source: 0, reserved: 0 }).unwrap();
+    w.uefi_runtime(&handoff::UefiRuntime { system_table: 0, runtime_services: 0, rwx_pages: 0 }).unwrap();
+    w.device_tree(0).unwrap();
+    w.rt_properties(0).unwrap();
+    let blob = w.finish();
+    assert!(blob.len() <= size / 8);
+
+    let r = Reader::new(blob).unwrap();
+    let map = r.memory_map().unwrap();
+    let starts: Vec<_> = map.iter().map(|d| d.phys_start).collect();
+    assert_eq!(starts, [0, 0x7_0000, 0x8_0000, 0x10_0000, 0x20_0000, 0x30_0000]);
+    // Runtime regions are packed in the order of their physical addresses
+    assert_eq!(map[1].virt_start, UEFI_RUNTIME_BASE);
+    assert_eq!(map[2].virt_start, UEFI_RUNTIME_BASE + 2 * 4096);
+    assert_eq!(map[3].virt_start, 0);
+    assert_eq!(r.initrd(), Some(&initrd));
+    assert_eq!(r.command_line(), Some(cmdline.as_str()));
+    assert_eq!(r.pci_functions().map(|f| f.len()), Some(0));
+}
+
+#[test]
+fn more_free_regions_than_slots() {
+    let unusual = Attributes::new().set_write_back().set_exec_protect().set_more_reliable();
+    let mut map = Vec::new();
+    for k in 1..=40u64 {
+        let attributes = if k % 2 == 0 { TYPICAL_MEMORY } else { unusual };
+        map.push(Descriptor::new(Type::Conventional, k << 24, k, attributes));
+    }
+    // Merged into the region before it, which then survives
+    map.push(Descriptor::new(Type::Conventional, (1 << 24) + 4096, 100, TYPICAL_MEMORY));
+    // Not write-back, so not for the kernel
+    map.push(Descriptor::new(Type::Conventional, 50 << 24, 1000, Attributes::new().set_noncacheable()));
+
+    let mut fw = MockFirmware::new();
+    fw.on_memory_map(move || Ok((map.clone(), 1)));
+    let handle = fw.image_handle();
+    let memmap = fw.system_table().exit_boot_services_with_map(handle).unwrap();
+
+    let mut free_memory = ArrayVecSized::<FreeMemory, FREE_MEMORY_SLOTS>::default();
+    assert_eq!(collect_free_memory(&memmap, &mut free_memory), None);
+    assert!(free_memory.is_full());
+
+    let mut free: Vec<_> = free_memory.iter().map(|m| (m.phys_start, m.pages)).collect();
+    free.sort();
+    let mut expected = vec![(1 << 24, 101)];
+    expected.extend((10..=40u64).map(|k| (k << 24, k)));
+    assert_eq!(free, expected);
+}
+
+#[test]
+fn handoff_without_extras() {
+    let memmap = [Descriptor::new(Type::Conventional, 0x1000, 1, TYPICAL_MEMORY)];
+    let mut buf = vec![0u64; handoff_size(&memmap, &[]) / 8];
+    let w = start_handoff(&mut buf, &memmap, &[], None, "").unwrap();
+    let r = Reader::new(w.finish()).unwrap();
+    assert_eq!(r.initrd(), None);
+    assert_eq!(r.command_line(), None);
+    assert_eq!(r.memory_map().unwrap().len(), 1);
+}
@@ -112,3 +112,86 @@ pub unsafe fn rep_insb(port: u16, ptr: *mut u8, sz: usize) {
         options(nostack),
     );
 }
+
+#[derive(Clone, Copy, Debug)]
+pub struct CpuidResult {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+#[inline(always)]
+pub fn cpuid(leaf: u32, subleaf: u32) -> CpuidResult {
+    let (eax, ebx, ecx, edx): (u32, u64, u32, u32);
+
+    // LLVM uses rbx internally, so it can't be an operand
+    unsafe {
+        asm!(
+            "mov {0}, rbx",
+            "cpuid",
+            "xchg {0}, rbx",
+            out(reg) ebx,
+            inout("eax") leaf => eax,
+            inout("ecx") subleaf => ecx,
+            out("edx") edx,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+
+    return CpuidResult { eax, ebx: ebx as u32, ecx, edx };
+}
+
+#[inline(always)]
+pub fn rdtsc() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+    return (high as u64) << 32 | low as u64;
+}
+
+/// Intel recommends giving up after 10 failed attempts in a row
+const RANDOM_RETRIES: usize = 10;
+
+/// Random number from the CPU's DRBG, `None` if there is no RDRAND or it
+/// keeps failing
+pub fn rdrand() -> Option<u64> {
+    if cpuid(1, 0).ecx & (1 << 30) == 0 {
+        return None;
+    }
+
+    for _ in 0..RANDOM_RETRIES {
+        let (value, ok): (u64, u8);
+        // SAFETY: CPUID says the instruction exists
+        unsafe {
+            asm!("rdrand {}", "setc {}", out(reg) value, out(reg_byte) ok, options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+
+    return None;
+}
+
+/// Random number straight from the CPU's entropy source, `None` if there is
+/// no RDSEED or it keeps failing
+pub fn rdseed() -> Option<u64> {
+    if cpuid(0, 0).eax < 7 || cpuid(7, 0).ebx & (1 << 18) == 0 {
+        return None;
+    }
+
+    for _ in 0..RANDOM_RETRIES {
+        let (value, ok): (u64, u8);
+        // SAFETY: CPUID says the instruction exists
+        unsafe {
+            asm!("rdseed {}", "setc {}", out(reg) value, out(reg_byte) ok, options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+
+    return None;
+}
@@ -15,6 +15,7 @@ use impl_bits::impl_bits;
 
 pub mod acpi;
 pub mod interrupt;
+pub mod paging;
 pub mod segmentation;
 pub mod port;
 
@@ -0,0 +1,353 @@
+use super::*;
+
+/// Bits that are shared by entries of every level
+const PRESENT: u64 = 1 << 0;
+const WRITABLE: u64 = 1 << 1;
+const USER: u64 = 1 << 2;
+
+/// `giant_page`/`huge_page` in PDPT and PD, PAT in the page table
+const HUGE: u64 = 1 << 7;
+
+/// PAT bit of giant and huge pages
+const HUGE_PAT: u64 = 1 << 12;
+
+/// Source of zeroable, page-aligned physical frames for new page tables.
+///
+/// # Safety
+/// Returned frames must be 4KiB aligned, unused by anyone else and accessible
+/// at `phys + phys_offset` of the `Mapper` they are given to.
+pub unsafe trait FrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysAddr>;
+}
+
+unsafe impl<A: FrameAllocator + ?Sized> FrameAllocator for &mut A {
+    fn allocate_frame(&mut self) -> Option<PhysAddr> {
+        (**self).allocate_frame()
+    }
+}
+
+/// Level-independent permissions of a mapping. Bits are on the same positions
+/// as in the entries themselves.
+#[repr(transparent)]
+#[derive(PartialEq, Eq)]
+pub struct Flags(u64);
+
+impl_bits!(Flags = {
+    writable = 1,
+    user = 2,
+    write_through = 3,
+    cache_disable = 4,
+    global = 8,
+    no_execute = 63,
+});
+
+impl Flags {
+    const MASK: u64 = Self::__with_all_flags().0;
+
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    const fn from_entry(raw: u64) -> Self {
+        Self(raw & Self::MASK)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapError {
+    /// Address or size is not a multiple of `PAGE_SIZE`
+    Unaligned,
+
+    /// Some part of the range is already mapped
+    AlreadyMapped,
+
+    /// Some part of the range is not mapped
+    NotMapped,
+
+    /// `FrameAllocator` could not give us a new table
+    OutOfFrames,
+
+    /// Range wraps around the end of the address space
+    Overflow,
+}
+
+#[derive(Clone, Copy)]
+pub struct Translation {
+    pub phys:      PhysAddr,
+    /// Size of the page that maps the address, one of `PAGE_SIZE`,
+    /// `HUGE_PAGE_SIZE` or `GIANT_PAGE_SIZE`
+    pub page_size: u64,
+    pub flags:     Flags,
+}
+
+const fn level_size(level: u8) -> u64 {
+    1 << (12 + 9 * (level as u64 - 1))
+}
+
+const fn level_index(virt: u64, level: u8) -> usize {
+    ((virt >> (12 + 9 * (level as u64 - 1))) & 0x1FF) as usize
+}
+
+const fn leaf_addr_mask(level: u8) -> u64 {
+    match level {
+        1 => ADDR_MASK,
+        2 => HUGE_ADDR_MASK,
+        _ => GIANT_ADDR_MASK,
+    }
+}
+
+/// Manipulates a 4-level hierarchy of page tables.
+///
+/// Tables are reached through `phys + phys_offset`, so the same code works
+/// with identity mapping in the loader, a direct map in the kernel and plain
+/// heap memory in tests.
+/// The mapper does not flush the TLB, it is up to the caller to `invlpg` or
+/// reload CR3 if the tables are active.
+pub struct Mapper<'a, A: FrameAllocator> {
+    pml4:        &'a mut Table<PML4Entry>,
+    allocator:   A,
+    phys_offset: u64,
+    giant_pages: bool,
+}
+
+impl<'a, A: FrameAllocator> Mapper<'a, A> {
+    /// # Safety
+    /// Every table reachable from `pml4` must be accessible at
+    /// `phys + phys_offset` and must not be modified by anyone else
+    /// while the mapper lives.
+    pub unsafe fn new(pml4: &'a mut Table<PML4Entry>, allocator: A, phys_offset: u64) -> Self {
+        Self { pml4, allocator, phys_offset, giant_pages: false }
+    }
+
+    /// Allows `map` to use 1GiB pages. Check CPUID before enabling it.
+    pub fn allow_giant_pages(mut self, allow: bool) -> Self {
+        self.giant_pages = allow;
+        self
+    }
+
+    pub fn allocator_mut(&mut self) -> &mut A {
+        &mut self.allocator
+    }
+
+    fn root(&mut self) -> *mut [u64; ENTRIES] {
+        let root: *mut Table<PML4Entry> = &mut *self.pml4;
+        root.cast()
+    }
+
+    fn table_ptr(&self, entry: u64) -> *mut [u64; ENTRIES] {
+        let addr = (entry & ADDR_MASK) + self.phys_offset;
+        core::ptr::with_exposed_provenance_mut(addr as usize)
+    }
+
+    fn new_table(&mut self) -> Result<u64, MapError> {
+        let frame = self.allocator.allocate_frame().ok_or(MapError::OutOfFrames)?;
+        let frame = frame.as_u64();
+        debug_assert!(frame & !ADDR_MASK == 0);
+
+        // SAFETY: FrameAllocator promises the frame is ours and reachable
+        unsafe { self.table_ptr(frame).write([0u64; ENTRIES]) };
+        return Ok(frame);
+    }
+
+    /// Returns the entry on `level` that maps `virt`, creating tables on the
+    /// way down. Permissions on the upper levels are as loose as possible,
+    /// the leaf entry decides.
+    fn entry_for_map(&mut self, virt: u64, level: u8, user: bool) -> Result<*mut u64, MapError> {
+        let mut table = self.root();
+
+        for current in (level + 1..=4).rev() {
+            // SAFETY: all the tables are valid, see `new`
+            let entry = unsafe { &mut (*table)[level_index(virt, current)] };
+
+            if *entry & PRESENT == 0 {
+                *entry = self.new_table()? | PRESENT | WRITABLE;
+            } else if current < 4 && *entry & HUGE != 0 {
+                return Err(MapError::AlreadyMapped);
+            }
+
+            if user {
+                *entry |= USER;
+            }
+
+            table = self.table_ptr(*entry);
+        }
+
+        // SAFETY: as above
+        return Ok(unsafe { &mut (*table)[level_index(virt, level)] });
+    }
+
+    /// Returns the present entry that maps `virt` and its level.
+    /// Writing through the pointer is only allowed if `root` was mutable.
+    fn leaf(&self, root: *mut [u64; ENTRIES], virt: u64) -> Option<(*mut u64, u8)> {
+        let mut table = root;
+        let mut level = 4;
+
+        loop {
+            // SAFETY: all the tables are valid, see `new`
+            let entry = unsafe { core::ptr::addr_of_mut!((*table)[level_index(virt, level)]) };
+            let value = unsafe { *entry };
+
+            if value & PRESENT == 0 {
+                return None;
+            }
+            if level == 1 || (level < 4 && value & HUGE != 0) {
+                return Some((entry, level));
+            }
+
+            table = self.table_ptr(value);
+            level -= 1;
+        }
+    }
+
+    /// Replaces a huge or giant page with a table of smaller pages that
+    /// map the same memory with the same permissions.
+    fn split(&mut self, entry: *mut u64, level: u8) -> Result<(), MapError> {
+        debug_assert!(level > 1);
+
+        // SAFETY: `entry` comes from `leaf`
+        let old = unsafe { *entry };
+        let addr = old & leaf_addr_mask(level);
+        let flags = old & Flags::MASK;
+        let pat = old & HUGE_PAT != 0;
+
+        let frame = self.new_table()?;
+        let table = self.table_ptr(frame);
+        let child_level = level - 1;
+
+        for i in 0..ENTRIES {
+            let child_addr = addr + i as u64 * level_size(child_level);
+            let child = match (child_level, pat) {
+                (1, false) => child_addr | flags | PRESENT,
+                (1, true) => child_addr | flags | PRESENT | HUGE,
+                (_, false) => child_addr | flags | PRESENT | HUGE,
+                (_, true) => child_addr | flags | PRESENT | HUGE | HUGE_PAT,
+            };
+
+            // SAFETY: the table was just allocated
+            unsafe { (*table)[i] = child };
+        }
+
+        // SAFETY: as above
+        unsafe { *entry = frame | PRESENT | WRITABLE | (old & USER) };
+        return Ok(());
+    }
+
+    fn pick_level(&self, virt: u64, phys: u64, remaining: u64) -> u8 {
+        let both = virt | phys;
+
+        if self.giant_pages && both.is_multiple_of(GIANT_PAGE_SIZE) && remaining >= GIANT_PAGE_SIZE {
+            return 3;
+        }
+        if both.is_multiple_of(HUGE_PAGE_SIZE) && remaining >= HUGE_PAGE_SIZE {
+            return 2;
+        }
+        return 1;
+    }
+
+    /// Maps `size` bytes at `virt` to `phys`, using the biggest pages that
+    /// the alignment of both addresses allows.
+    ///
+    /// On error, the part of the range before the failing page stays mapped.
+    pub fn map(&mut self, virt: VirtAddr, phys: PhysAddr, size: u64, flags: Flags) -> Result<(), MapError> {
+        let (mut virt, mut phys) = (virt.as_u64(), phys.as_u64());
+        if !(virt | phys | size).is_multiple_of(PAGE_SIZE) {
+            return Err(MapError::Unaligned);
+        }
+        if size == 0 {
+            return Ok(());
+        }
+        // The range may end at the very top, but not wrap around
+        if virt.checked_add(size - 1).is_none() || phys.checked_add(size - 1).is_none() {
+            return Err(MapError::Overflow);
+        }
+
+        let mut remaining = size;
+        loop {
+            let level = self.pick_level(virt, phys, remaining);
+            let entry = self.entry_for_map(virt, level, flags.user())?;
+
+            // SAFETY: pointer comes from `entry_for_map`
+            let entry = unsafe { &mut *entry };
+            if *entry & PRESENT != 0 {
+                return Err(MapError::AlreadyMapped);
+            }
+
+            let huge = if level > 1 { HUGE } else { 0 };
+            *entry = phys | flags.0 | huge | PRESENT;
+
+            let page_size = level_size(level);
+            remaining -= page_size;
+            if remaining == 0 {
+                break;
+            }
+
+            // Checked above, the next page still belongs to the range
+            virt = virt.checked_add(page_size).ok_or(MapError::Overflow)?;
+            phys = phys.checked_add(page_size).ok_or(MapError::Overflow)?;
+        }
+
+        return Ok(());
+    }
+
+    /// Calls `f` on every leaf entry in the range, splitting bigger pages
+    /// that are only partially covered by it.
+    fn for_each_leaf(&mut self, virt: VirtAddr, size: u64, mut f: impl FnMut(&mut u64)) -> Result<(), MapError> {
+        let mut virt = virt.as_u64();
+        if !(virt | size).is_multiple_of(PAGE_SIZE) {
+            return Err(MapError::Unaligned);
+        }
+        if size == 0 {
+            return Ok(());
+        }
+
+        // Inclusive, so that a range ending at the top doesn't overflow
+        let last = virt.checked_add(size - 1).ok_or(MapError::Overflow)?;
+        loop {
+            let root = self.root();
+            let (entry, level) = self.leaf(root, virt).ok_or(MapError::NotMapped)?;
+            let page_size = level_size(level);
+
+            if !virt.is_multiple_of(page_size) || last - virt < page_size - 1 {
+                self.split(entry, level)?;
+                continue;
+            }
+
+            // SAFETY: pointer comes from `leaf`
+            f(unsafe { &mut *entry });
+            match virt.checked_add(page_size) {
+                Some(next) if next <= last => virt = next,
+                _ => break,
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Removes the mappings in the range. Empty tables are not freed.
+    pub fn unmap(&mut self, virt: VirtAddr, size: u64) -> Result<(), MapError> {
+        self.for_each_leaf(virt, size, |entry| *entry = 0)
+    }
+
+    /// Replaces permissions of every page in the range with `flags`.
+    pub fn update_flags(&mut self, virt: VirtAddr, size: u64, flags: Flags) -> Result<(), MapError> {
+        self.for_each_leaf(virt, size, |entry| *entry = (*entry & !Flags::MASK) | flags.0)
+    }
+
+    pub fn translate(&self, virt: VirtAddr) -> Option<Translation> {
+        let root: *const Table<PML4Entry> = &*self.pml4;
+        let (entry, level) = self.leaf(root.cast_mut().cast(), virt.as_u64())?;
+        // SAFETY: pointer comes from `leaf`
+        let entry = unsafe { *entry };
+
+        let page_size = level_size(level);
+        let offset = virt.as_u64() % page_size;
+        let phys = (entry & leaf_addr_mask(level)) + offset;
+
+        return Some(Translation {
+            // SAFETY: the mask makes the address fit in 52 bits
+            phys: unsafe { PhysAddr::new_unchecked(phys) },
+            page_size,
+            flags: Flags::from_entry(entry),
+        });
+    }
+}
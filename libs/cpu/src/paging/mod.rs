@@ -0,0 +1,307 @@
+//! Typed x86-64 page tables.
+//!
+//! Every level of the hierarchy has its own entry type, so that it is not
+//! possible to accidentally put a page table where a page directory should be.
+//! With 4-level paging CR3 points at a `Table<PML4Entry>`, with 5-level paging
+//! (`Cr4::la57`) it points at a `Table<PML5Entry>` instead.
+
+use core::ops::{Deref, DerefMut};
+
+use crate::{impl_bits, PhysAddr, VirtAddr};
+
+mod mapper;
+pub use mapper::*;
+
+/// Number of entries in a table of any level
+pub const ENTRIES: usize = 512;
+
+/// Size of a regular page, mapped by `PTEntry`
+pub const PAGE_SIZE: u64 = 1 << 12;
+
+/// Size of a huge page, mapped by `PDEntry` with `huge_page` set
+pub const HUGE_PAGE_SIZE: u64 = 1 << 21;
+
+/// Size of a giant page, mapped by `PDPTEntry` with `giant_page` set
+pub const GIANT_PAGE_SIZE: u64 = 1 << 30;
+
+/// Bits 12..52 of an entry hold the physical address of the next table or
+/// of the 4KiB page.
+const ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// For huge and giant pages, bit 12 is PAT, so the address starts higher.
+const HUGE_ADDR_MASK: u64 = 0x000F_FFFF_FFE0_0000;
+const GIANT_ADDR_MASK: u64 = 0x000F_FFFF_C000_0000;
+
+pub trait Entry: Copy {
+    /// 1 for the page table, 5 for PML5
+    const LEVEL: u8;
+
+    /// Entry with all bits cleared - not present and pointing to nothing
+    const UNUSED: Self;
+
+    fn from_u64(raw: u64) -> Self;
+    fn as_u64(self) -> u64;
+
+    /// Index into a table of this level that translates `virt`
+    fn index_of(virt: VirtAddr) -> usize {
+        let shift = 12 + 9 * (Self::LEVEL as u64 - 1);
+        ((virt.as_u64() >> shift) & 0x1FF) as usize
+    }
+
+    /// Size of the virtual memory region covered by a single entry
+    fn region_size() -> u64 {
+        1 << (12 + 9 * (Self::LEVEL as u64 - 1))
+    }
+}
+
+/// Entries that (can) point to a lower-level table
+pub trait TableEntry: Entry {
+    type Next: Entry;
+
+    fn table_addr(self) -> PhysAddr<Table<Self::Next>> {
+        // SAFETY: the mask makes the address fit in 52 bits
+        unsafe { PhysAddr::new_unchecked(self.as_u64() & ADDR_MASK) }
+    }
+
+    fn with_table_addr(self, addr: PhysAddr<Table<Self::Next>>) -> Self {
+        debug_assert!(addr.as_u64() & !ADDR_MASK == 0);
+        Self::from_u64((self.as_u64() & !ADDR_MASK) | addr.as_u64())
+    }
+}
+
+macro_rules! impl_entry {
+    ($name:ident, $level:literal) => {
+        impl $name {
+            pub const fn new() -> Self {
+                Self(0)
+            }
+
+            pub const fn is_unused(self) -> bool {
+                self.0 == 0
+            }
+        }
+
+        impl Entry for $name {
+            const LEVEL: u8 = $level;
+            const UNUSED: Self = Self(0);
+
+            fn from_u64(raw: u64) -> Self {
+                Self(raw)
+            }
+
+            fn as_u64(self) -> u64 {
+                self.0
+            }
+        }
+    };
+}
+
+/// Entry of the top-level table when 5-level paging is enabled.
+/// Each one covers 256TiB of virtual memory.
+#[repr(transparent)]
+pub struct PML5Entry(u64);
+
+impl_bits!(PML5Entry = {
+    present = 0,
+    writable = 1,
+    user = 2,
+    write_through = 3,
+    cache_disable = 4,
+    accessed = 5,
+    no_execute = 63,
+});
+
+impl_entry!(PML5Entry, 5);
+
+impl TableEntry for PML5Entry {
+    type Next = PML4Entry;
+}
+
+/// Each entry covers 512GiB of virtual memory.
+#[repr(transparent)]
+pub struct PML4Entry(u64);
+
+impl_bits!(PML4Entry = {
+    present = 0,
+    writable = 1,
+    user = 2,
+    write_through = 3,
+    cache_disable = 4,
+    accessed = 5,
+    no_execute = 63,
+});
+
+impl_entry!(PML4Entry, 4);
+
+impl TableEntry for PML4Entry {
+    type Next = PDPTEntry;
+}
+
+/// Each entry covers 1GiB of virtual memory, either by pointing to a page
+/// directory or by mapping a giant page directly.
+#[repr(transparent)]
+pub struct PDPTEntry(u64);
+
+impl_bits!(PDPTEntry = {
+    present = 0,
+    writable = 1,
+    user = 2,
+    write_through = 3,
+    cache_disable = 4,
+    accessed = 5,
+
+    /// Only valid for giant pages
+    dirty = 6,
+
+    /// Entry maps a 1GiB page instead of pointing to a page directory.
+    /// Support is indicated by CPUID.80000001h:EDX[26]
+    giant_page = 7,
+
+    /// Only valid for giant pages
+    global = 8,
+
+    /// Only valid for giant pages
+    pat = 12,
+
+    no_execute = 63,
+});
+
+impl_entry!(PDPTEntry, 3);
+
+impl TableEntry for PDPTEntry {
+    type Next = PDEntry;
+}
+
+impl PDPTEntry {
+    pub const fn page_addr(self) -> PhysAddr {
+        debug_assert!(self.giant_page());
+        // SAFETY: the mask makes the address fit in 52 bits
+        unsafe { PhysAddr::new_unchecked(self.0 & GIANT_ADDR_MASK) }
+    }
+
+    pub const fn with_page_addr(self, addr: PhysAddr) -> Self {
+        debug_assert!(addr.as_u64() & !GIANT_ADDR_MASK == 0);
+        Self((self.0 & !GIANT_ADDR_MASK) | addr.as_u64()).set_giant_page()
+    }
+}
+
+/// Each entry covers 2MiB of virtual memory, either by pointing to a page
+/// table or by mapping a huge page directly.
+#[repr(transparent)]
+pub struct PDEntry(u64);
+
+impl_bits!(PDEntry = {
+    present = 0,
+    writable = 1,
+    user = 2,
+    write_through = 3,
+    cache_disable = 4,
+    accessed = 5,
+
+    /// Only valid for huge pages
+    dirty = 6,
+
+    /// Entry maps a 2MiB page instead of pointing to a page table
+    huge_page = 7,
+
+    /// Only valid for huge pages
+    global = 8,
+
+    /// Only valid for huge pages
+    pat = 12,
+
+    no_execute = 63,
+});
+
+impl_entry!(PDEntry, 2);
+
+impl TableEntry for PDEntry {
+    type Next = PTEntry;
+}
+
+impl PDEntry {
+    pub const fn page_addr(self) -> PhysAddr {
+        debug_assert!(self.huge_page());
+        // SAFETY: the mask makes the address fit in 52 bits
+        unsafe { PhysAddr::new_unchecked(self.0 & HUGE_ADDR_MASK) }
+    }
+
+    pub const fn with_page_addr(self, addr: PhysAddr) -> Self {
+        debug_assert!(addr.as_u64() & !HUGE_ADDR_MASK == 0);
+        Self((self.0 & !HUGE_ADDR_MASK) | addr.as_u64()).set_huge_page()
+    }
+}
+
+/// Each entry maps a single 4KiB page.
+#[repr(transparent)]
+pub struct PTEntry(u64);
+
+impl_bits!(PTEntry = {
+    present = 0,
+    writable = 1,
+    user = 2,
+    write_through = 3,
+    cache_disable = 4,
+    accessed = 5,
+    dirty = 6,
+    pat = 7,
+    global = 8,
+    no_execute = 63,
+});
+
+impl_entry!(PTEntry, 1);
+
+impl PTEntry {
+    pub const fn page_addr(self) -> PhysAddr {
+        // SAFETY: the mask makes the address fit in 52 bits
+        unsafe { PhysAddr::new_unchecked(self.0 & ADDR_MASK) }
+    }
+
+    pub const fn with_page_addr(self, addr: PhysAddr) -> Self {
+        debug_assert!(addr.as_u64() & !ADDR_MASK == 0);
+        Self((self.0 & !ADDR_MASK) | addr.as_u64())
+    }
+}
+
+/// Whether `PDPTEntry::giant_page` can be used, CPUID.80000001h:EDX[26]
+pub fn giant_pages_supported() -> bool {
+    let max_extended_leaf = crate::cpuid(0x8000_0000, 0).eax;
+    max_extended_leaf >= 0x8000_0001 && (crate::cpuid(0x8000_0001, 0).edx >> 26) & 1 == 1
+}
+
+/// A single, page-sized and page-aligned table of any level.
+#[repr(C, align(4096))]
+pub struct Table<E: Entry> {
+    entries: [E; ENTRIES],
+}
+
+impl<E: Entry> Table<E> {
+    pub const fn new() -> Self {
+        Self { entries: [E::UNUSED; ENTRIES] }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(E::UNUSED);
+    }
+
+    pub fn entry(&self, virt: VirtAddr) -> &E {
+        &self.entries[E::index_of(virt)]
+    }
+
+    pub fn entry_mut(&mut self, virt: VirtAddr) -> &mut E {
+        &mut self.entries[E::index_of(virt)]
+    }
+}
+
+impl<E: Entry> Deref for Table<E> {
+    type Target = [E; ENTRIES];
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+impl<E: Entry> DerefMut for Table<E> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.entries
+    }
+}
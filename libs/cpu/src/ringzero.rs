@@ -64,6 +64,9 @@ impl_bits!(Cr4 = {
     os_simd_float_exceptions = 10,
     usermode_instruction_prevention = 11,
 
+    /// 5-level paging, CR3 points to PML5 instead of PML4
+    la57 = 12,
+
     // Only in Intel manual
     intel_vmx = 13,
     intel_smx = 14,
@@ -224,3 +227,64 @@ impl Cr3 {
         );
     }
 }
+
+/// SAFETY: `msr` must exist on this CPU
+#[inline(always)]
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let (lo, hi): (u32, u32);
+
+    asm!(
+        "rdmsr",
+        in("ecx") msr,
+        out("eax") lo,
+        out("edx") hi,
+        options(nomem, nostack, preserves_flags),
+    );
+
+    return (hi as u64) << 32 | lo as u64;
+}
+
+/// SAFETY: `msr` must exist on this CPU and `value` must be valid for it
+#[inline(always)]
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    asm!(
+        "wrmsr",
+        in("ecx") msr,
+        in("eax") value as u32,
+        in("edx") (value >> 32) as u32,
+        options(nostack, preserves_flags),
+    );
+}
+
+/// Extended Feature Enable Register
+#[repr(transparent)]
+pub struct Efer(u64);
+
+impl_bits!(Efer = {
+    syscall_enable = 0,
+    long_mode_enable = 8,
+    long_mode_active = 10,
+
+    /// Without it, setting bit 63 in a page table entry is a reserved bit
+    /// violation
+    no_execute_enable = 11,
+
+    // Only in AMD manual
+    secure_virtual_machine = 12,
+    long_mode_segment_limit = 13,
+    fast_fxsave_fxrstor = 14,
+    translation_cache_extension = 15,
+});
+
+impl Efer {
+    const MSR: u32 = 0xC000_0080;
+
+    pub fn get() -> Self {
+        // SAFETY: EFER exists on every CPU that can run in long mode
+        Self(unsafe { rdmsr(Self::MSR) })
+    }
+
+    pub unsafe fn set(efer: Self) {
+        wrmsr(Self::MSR, efer.0);
+    }
+}
@@ -0,0 +1,203 @@
+use cpu::paging::*;
+use cpu::{PhysAddr, VirtAddr};
+
+/// Hands out heap-allocated tables, "physical" addresses are just host pointers
+struct VecAllocator {
+    frames: Vec<*mut Table<PTEntry>>,
+    limit:  usize,
+}
+
+impl VecAllocator {
+    fn new(limit: usize) -> Self {
+        Self { frames: Vec::new(), limit }
+    }
+}
+
+unsafe impl FrameAllocator for VecAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysAddr> {
+        if self.frames.len() == self.limit {
+            return None;
+        }
+
+        let frame = Box::into_raw(Box::new(Table::<PTEntry>::new()));
+        self.frames.push(frame);
+        PhysAddr::new(frame.expose_provenance() as u64)
+    }
+}
+
+impl Drop for VecAllocator {
+    fn drop(&mut self) {
+        for frame in self.frames.drain(..) {
+            drop(unsafe { Box::from_raw(frame) });
+        }
+    }
+}
+
+fn virt(x: u64) -> VirtAddr {
+    VirtAddr::new(x)
+}
+
+fn phys(x: u64) -> PhysAddr {
+    PhysAddr::new(x).unwrap()
+}
+
+fn data() -> Flags {
+    Flags::new().set_writable().set_no_execute()
+}
+
+#[test]
+fn map_and_translate_small() {
+    let mut pml4 = Box::new(Table::<PML4Entry>::new());
+    let mut mapper = unsafe { Mapper::new(&mut pml4, VecAllocator::new(16), 0) };
+
+    mapper.map(virt(0xFFFF_8000_0000_1000), phys(0x5000), 2 * PAGE_SIZE, data()).unwrap();
+
+    let t = mapper.translate(virt(0xFFFF_8000_0000_1234)).unwrap();
+    assert_eq!(t.phys.as_u64(), 0x5234);
+    assert_eq!(t.page_size, PAGE_SIZE);
+    assert!(t.flags.writable());
+    assert!(t.flags.no_execute());
+
+    let t = mapper.translate(virt(0xFFFF_8000_0000_2FFF)).unwrap();
+    assert_eq!(t.phys.as_u64(), 0x6FFF);
+
+    assert!(mapper.translate(virt(0xFFFF_8000_0000_0000)).is_none());
+    assert!(mapper.translate(virt(0xFFFF_8000_0000_3000)).is_none());
+}
+
+#[test]
+fn picks_page_size_by_alignment() {
+    let mut pml4 = Box::new(Table::<PML4Entry>::new());
+    let mut mapper = unsafe { Mapper::new(&mut pml4, VecAllocator::new(16), 0) }.allow_giant_pages(true);
+
+    // 4KiB head, one 2MiB page, one 1GiB page, 2MiB and 4KiB tail
+    let start = GIANT_PAGE_SIZE - HUGE_PAGE_SIZE - PAGE_SIZE;
+    let size = PAGE_SIZE + HUGE_PAGE_SIZE + GIANT_PAGE_SIZE + HUGE_PAGE_SIZE + PAGE_SIZE;
+    mapper.map(virt(start), phys(start), size, data()).unwrap();
+
+    let page_size = |x| mapper.translate(virt(x)).unwrap().page_size;
+    assert_eq!(page_size(start), PAGE_SIZE);
+    assert_eq!(page_size(start + PAGE_SIZE), HUGE_PAGE_SIZE);
+    assert_eq!(page_size(GIANT_PAGE_SIZE), GIANT_PAGE_SIZE);
+    assert_eq!(page_size(2 * GIANT_PAGE_SIZE), HUGE_PAGE_SIZE);
+    assert_eq!(page_size(2 * GIANT_PAGE_SIZE + HUGE_PAGE_SIZE), PAGE_SIZE);
+    assert!(mapper.translate(virt(start + size)).is_none());
+}
+
+#[test]
+fn giant_pages_are_opt_in() {
+    let mut pml4 = Box::new(Table::<PML4Entry>::new());
+    let mut mapper = unsafe { Mapper::new(&mut pml4, VecAllocator::new(16), 0) };
+
+    mapper.map(virt(0), phys(0), GIANT_PAGE_SIZE, data()).unwrap();
+    assert_eq!(mapper.translate(virt(0)).unwrap().page_size, HUGE_PAGE_SIZE);
+}
+
+#[test]
+fn errors() {
+    let mut pml4 = Box::new(Table::<PML4Entry>::new());
+    let mut mapper = unsafe { Mapper::new(&mut pml4, VecAllocator::new(3), 0) };
+
+    assert_eq!(mapper.map(virt(0x1001), phys(0x1000), PAGE_SIZE, data()), Err(MapError::Unaligned));
+    assert_eq!(mapper.map(virt(0x1000), phys(0x1000), 0x800, data()), Err(MapError::Unaligned));
+
+    mapper.map(virt(0x1000), phys(0x1000), PAGE_SIZE, data()).unwrap();
+    assert_eq!(mapper.map(virt(0x1000), phys(0x8000), PAGE_SIZE, data()), Err(MapError::AlreadyMapped));
+    assert_eq!(mapper.unmap(virt(0x2000), PAGE_SIZE), Err(MapError::NotMapped));
+
+    assert_eq!(mapper.map(virt(0), phys(0), 0, data()), Ok(()));
+    assert_eq!(
+        mapper.map(virt(0xFFFF_FFFF_FFFF_F000), phys(0), 2 * PAGE_SIZE, data()),
+        Err(MapError::Overflow)
+    );
+    assert_eq!(mapper.unmap(virt(0xFFFF_FFFF_FFFF_F000), 2 * PAGE_SIZE), Err(MapError::Overflow));
+
+    // PDPT, PD and PT are already used up
+    assert_eq!(
+        mapper.map(virt(GIANT_PAGE_SIZE), phys(0), PAGE_SIZE, data()),
+        Err(MapError::OutOfFrames)
+    );
+}
+
+#[test]
+fn unmap_splits_huge_pages() {
+    let mut pml4 = Box::new(Table::<PML4Entry>::new());
+    let mut mapper = unsafe { Mapper::new(&mut pml4, VecAllocator::new(16), 0) };
+
+    mapper.map(virt(0), phys(HUGE_PAGE_SIZE), HUGE_PAGE_SIZE, data()).unwrap();
+    mapper.unmap(virt(0x3000), PAGE_SIZE).unwrap();
+
+    assert!(mapper.translate(virt(0x3000)).is_none());
+    let t = mapper.translate(virt(0x4000)).unwrap();
+    assert_eq!(t.page_size, PAGE_SIZE);
+    assert_eq!(t.phys.as_u64(), HUGE_PAGE_SIZE + 0x4000);
+    assert!(t.flags.writable());
+}
+
+#[test]
+fn update_flags() {
+    let mut pml4 = Box::new(Table::<PML4Entry>::new());
+    let mut mapper = unsafe { Mapper::new(&mut pml4, VecAllocator::new(16), 0) }.allow_giant_pages(true);
+
+    mapper.map(virt(0), phys(0), GIANT_PAGE_SIZE, data()).unwrap();
+    mapper.update_flags(virt(HUGE_PAGE_SIZE), HUGE_PAGE_SIZE, Flags::new()).unwrap();
+
+    let t = mapper.translate(virt(HUGE_PAGE_SIZE + 0x10)).unwrap();
+    assert_eq!(t.page_size, HUGE_PAGE_SIZE);
+    assert_eq!(t.phys.as_u64(), HUGE_PAGE_SIZE + 0x10);
+    assert!(!t.flags.writable());
+    assert!(!t.flags.no_execute());
+
+    let t = mapper.translate(virt(0)).unwrap();
+    assert!(t.flags.writable());
+}
+
+#[test]
+fn odd_memory_sizes() {
+    let mut pml4 = Box::new(Table::<PML4Entry>::new());
+    let mut mapper = unsafe { Mapper::new(&mut pml4, VecAllocator::new(16), 0) }.allow_giant_pages(true);
+
+    // Like a `-m 512M` guest with a few extra pages at the end
+    let base = 0xFFFF_8000_0000_0000;
+    let size = (512 << 20) + 3 * PAGE_SIZE;
+    mapper.map(virt(base), phys(0), size, data()).unwrap();
+
+    let page_size = |x| mapper.translate(virt(base + x)).unwrap().page_size;
+    assert_eq!(page_size(0), HUGE_PAGE_SIZE);
+    assert_eq!(page_size((512 << 20) - 1), HUGE_PAGE_SIZE);
+    assert_eq!(page_size(512 << 20), PAGE_SIZE);
+    assert_eq!(page_size(size - 1), PAGE_SIZE);
+    assert!(mapper.translate(virt(base + size)).is_none());
+
+    // 3.5GiB starting above 1GiB
+    let start = GIANT_PAGE_SIZE;
+    let size = 3 * GIANT_PAGE_SIZE + (GIANT_PAGE_SIZE >> 1);
+    mapper.map(virt(base + start), phys(start), size, data()).unwrap();
+
+    let t = mapper.translate(virt(base + 3 * GIANT_PAGE_SIZE + 5)).unwrap();
+    assert_eq!(t.page_size, GIANT_PAGE_SIZE);
+    assert_eq!(t.phys.as_u64(), 3 * GIANT_PAGE_SIZE + 5);
+    assert_eq!(mapper.translate(virt(base + 4 * GIANT_PAGE_SIZE)).unwrap().page_size, HUGE_PAGE_SIZE);
+    assert!(mapper.translate(virt(base + start + size)).is_none());
+}
+
+#[test]
+fn top_of_address_space() {
+    let mut pml4 = Box::new(Table::<PML4Entry>::new());
+    let mut mapper = unsafe { Mapper::new(&mut pml4, VecAllocator::new(16), 0) };
+
+    // Last 2MiB page and the 4KiB page right below it
+    let start = 0u64.wrapping_sub(HUGE_PAGE_SIZE + PAGE_SIZE);
+    mapper.map(virt(start), phys(HUGE_PAGE_SIZE - PAGE_SIZE), HUGE_PAGE_SIZE + PAGE_SIZE, data()).unwrap();
+
+    let t = mapper.translate(virt(u64::MAX)).unwrap();
+    assert_eq!(t.page_size, HUGE_PAGE_SIZE);
+    assert_eq!(t.phys.as_u64(), 2 * HUGE_PAGE_SIZE - 1);
+
+    mapper.update_flags(virt(start), HUGE_PAGE_SIZE + PAGE_SIZE, Flags::new()).unwrap();
+    assert!(!mapper.translate(virt(u64::MAX)).unwrap().flags.writable());
+
+    mapper.unmap(virt(0u64.wrapping_sub(PAGE_SIZE)), PAGE_SIZE).unwrap();
+    assert!(mapper.translate(virt(u64::MAX)).is_none());
+    assert_eq!(mapper.translate(virt(u64::MAX - PAGE_SIZE)).unwrap().page_size, PAGE_SIZE);
+}
@@ -0,0 +1,44 @@
+use cpu::paging::*;
+use cpu::{PhysAddr, VirtAddr};
+
+#[test]
+fn table_is_one_page() {
+    assert_eq!(core::mem::size_of::<Table<PML4Entry>>(), PAGE_SIZE as usize);
+    assert_eq!(core::mem::align_of::<Table<PTEntry>>(), PAGE_SIZE as usize);
+}
+
+#[test]
+fn indices() {
+    let virt = VirtAddr::new(0xFFFF_8123_4567_8000);
+    assert_eq!(PML4Entry::index_of(virt), 258);
+    assert_eq!(PDPTEntry::index_of(virt), 141);
+    assert_eq!(PDEntry::index_of(virt), 43);
+    assert_eq!(PTEntry::index_of(virt), 120);
+
+    assert_eq!(PTEntry::region_size(), PAGE_SIZE);
+    assert_eq!(PDEntry::region_size(), HUGE_PAGE_SIZE);
+    assert_eq!(PDPTEntry::region_size(), GIANT_PAGE_SIZE);
+}
+
+#[test]
+fn table_addr_keeps_flags() {
+    let addr = PhysAddr::new(0x1234_5000).unwrap();
+    let e = PML4Entry::new().set_present().set_no_execute().with_table_addr(addr);
+
+    assert!(e.present());
+    assert!(e.no_execute());
+    assert!(!e.writable());
+    assert_eq!(e.table_addr().as_u64(), 0x1234_5000);
+}
+
+#[test]
+fn giant_and_huge_pages() {
+    let e = PDPTEntry::new().set_present().with_page_addr(PhysAddr::new(3 << 30).unwrap());
+    assert!(e.giant_page());
+    assert_eq!(e.page_addr().as_u64(), 3 << 30);
+
+    let e = PDEntry::new().set_present().set_pat().with_page_addr(PhysAddr::new(5 << 21).unwrap());
+    assert!(e.huge_page());
+    assert!(e.pat());
+    assert_eq!(e.page_addr().as_u64(), 5 << 21);
+}
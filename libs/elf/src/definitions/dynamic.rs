@@ -52,10 +52,133 @@ pub enum Tag {
     GnuHash = 0x6fff_fef5,
 }
 
+/// One record of the `PT_DYNAMIC` segment. `d_val` is either a value or an
+/// address, depending on the tag.
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct Entry {
-    pub tag: Tag,
-    pub val: u64,
+    pub d_tag: u64,
+    pub d_val: u64,
+}
+
+impl Entry {
+    pub const fn tag(&self) -> Option<Tag> {
+        Tag::from_integer(self.d_tag)
+    }
+}
+
+impl Tag {
+    pub const fn from_integer(x: u64) -> Option<Self> {
+        let tag = match x {
+            0 => Self::Null,
+            1 => Self::Needed,
+            2 => Self::PltRelSz,
+            3 => Self::PltGot,
+            4 => Self::Hash,
+            5 => Self::StrTab,
+            6 => Self::SymTab,
+            7 => Self::Rela,
+            8 => Self::RelaSz,
+            9 => Self::RelaEnt,
+            10 => Self::StrSz,
+            11 => Self::SymEnt,
+            12 => Self::Init,
+            13 => Self::Fini,
+            14 => Self::SoName,
+            15 => Self::RPath,
+            16 => Self::Symbolic,
+            17 => Self::Rel,
+            18 => Self::RelSz,
+            19 => Self::RelEnt,
+            20 => Self::PltRel,
+            21 => Self::Debug,
+            22 => Self::TextRel,
+            23 => Self::JmpRel,
+            24 => Self::BindNow,
+            25 => Self::InitArray,
+            26 => Self::FiniArray,
+            27 => Self::InitArraySz,
+            28 => Self::FiniArraySz,
+            29 => Self::RunPath,
+            30 => Self::Flags,
+            31 => Self::Encoding,
+            32 => Self::PreinitArray,
+            33 => Self::PreinitArraySz,
+            34 => Self::SymtabShndx,
+            35 => Self::RelrSz,
+            36 => Self::Relr,
+            37 => Self::RelrEnt,
+
+            0x7000_0000 => Self::X86_64Plt,
+            0x7000_0001 => Self::X86_64PltSz,
+            0x7000_0003 => Self::X86_64PltEnt,
+
+            0x6fff_fffb => Self::Flags1,
+            0x6fff_fef5 => Self::GnuHash,
+            _ => return None,
+        };
+
+        return Some(tag);
+    }
+}
+
+/// Relocation with an explicit addend, from `DT_RELA` tables
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Rela {
+    pub r_offset: u64,
+    pub r_info:   u64,
+    pub r_addend: i64,
+}
+
+impl Rela {
+    pub const fn typ(&self) -> u32 {
+        self.r_info as u32
+    }
+
+    pub const fn symbol(&self) -> u32 {
+        (self.r_info >> 32) as u32
+    }
+
+    pub const fn relocation_type(&self) -> Option<RelocationType> {
+        RelocationType::from_integer(self.typ())
+    }
+}
+
+/// Relocation types of x86-64, only the ones a static executable can have
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelocationType {
+    None     = 0,
+    Abs64    = 1,
+    Pc32     = 2,
+    Got32    = 3,
+    Plt32    = 4,
+    Copy     = 5,
+    GlobDat  = 6,
+    JumpSlot = 7,
+
+    /// Base address + addend
+    Relative = 8,
+}
+
+impl RelocationType {
+    pub const fn from_integer(x: u32) -> Option<Self> {
+        let typ = match x {
+            0 => Self::None,
+            1 => Self::Abs64,
+            2 => Self::Pc32,
+            3 => Self::Got32,
+            4 => Self::Plt32,
+            5 => Self::Copy,
+            6 => Self::GlobDat,
+            7 => Self::JumpSlot,
+            8 => Self::Relative,
+            _ => return None,
+        };
+
+        return Some(typ);
+    }
 }
 
 #[repr(transparent)]
@@ -117,17 +240,27 @@ pub struct Symbol {
 
 impl core::fmt::Debug for Entry {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        if self.tag == Tag::Flags {
-            return write!(f, "({:?}: {:?})", self.tag, Flags(self.val));
+        let Some(tag) = self.tag() else {
+            return write!(f, "(0x{:x}: {})", self.d_tag, self.d_val);
+        };
+
+        if tag == Tag::Flags {
+            return write!(f, "({:?}: {:?})", tag, Flags(self.d_val));
         }
 
-        if self.tag == Tag::Flags1 {
-            return write!(f, "({:?}: {:?})", self.tag, Flags1(self.val));
+        if tag == Tag::Flags1 {
+            return write!(f, "({:?}: {:?})", tag, Flags1(self.d_val));
         }
 
-        write!(f, "({:?}: {})", self.tag, self.val)
+        write!(f, "({:?}: {})", tag, self.d_val)
     }
 }
 
 unsafe impl Zeroable for Symbol {}
 unsafe impl Pod for Symbol {}
+
+unsafe impl Zeroable for Entry {}
+unsafe impl Pod for Entry {}
+
+unsafe impl Zeroable for Rela {}
+unsafe impl Pod for Rela {}
@@ -1,10 +1,12 @@
 #![no_std]
 
 mod definitions;
+mod relocation;
 use core::mem;
 use core::num::NonZeroU64;
 
 pub use definitions::*;
+pub use relocation::*;
 
 pub struct Elf<'a, M: ElfMachine> {
     pub data: &'a [u8],
@@ -72,10 +74,10 @@ impl<'a, M: ElfMachine> Elf<'a, M> {
         if entsize != mem::size_of::<T>() {
             return Err(MemoryError::SizeMismatch);
         }
-        let len_bytes = n * mem::size_of::<T>();
+        let len_bytes = n.checked_mul(mem::size_of::<T>()).ok_or(MemoryError::UnexpectedEnd)?;
 
         let start = offset;
-        let end = start + len_bytes;
+        let end = start.checked_add(len_bytes).ok_or(MemoryError::UnexpectedEnd)?;
 
         let chunk = match data.get(start..end) {
             Some(x) => x,
@@ -111,6 +113,96 @@ impl<'a, M: ElfMachine> Elf<'a, M> {
         );
     }
 
+    /// Entries of the `PT_DYNAMIC` segment up to `DT_NULL`, empty if there
+    /// is no such segment
+    pub fn dynamic(&self) -> Result<&[dynamic::Entry], MemoryError> {
+        let Some(ph) = self
+            .program_headers()?
+            .iter()
+            .find(|ph| ph.segment_type() == Some(SegmentType::Dynamic))
+        else {
+            return Ok(&[]);
+        };
+
+        let entsize = mem::size_of::<dynamic::Entry>();
+        let n = ph.p_filesz as usize / entsize;
+        let entries: &[dynamic::Entry] = Self::get_array(self.data, NonZeroU64::new(ph.p_offset), n, entsize)?;
+        let end = entries
+            .iter()
+            .position(|e| e.tag() == Some(dynamic::Tag::Null))
+            .unwrap_or(entries.len());
+
+        return Ok(&entries[..end]);
+    }
+
+    /// Finds where `size` bytes at `vaddr` are in the file, they must come
+    /// from a single `PT_LOAD` segment
+    fn vaddr_to_offset(&self, vaddr: u64, size: u64) -> Result<Option<NonZeroU64>, MemoryError> {
+        let Some(end) = vaddr.checked_add(size) else {
+            return Ok(None);
+        };
+
+        let offset = self
+            .program_headers()?
+            .iter()
+            .filter(|ph| ph.segment_type() == Some(SegmentType::Load))
+            .find(|ph| ph.p_vaddr <= vaddr && ph.p_vaddr.checked_add(ph.p_filesz).is_some_and(|e| end <= e))
+            .and_then(|ph| ph.p_offset.checked_add(vaddr - ph.p_vaddr))
+            .and_then(NonZeroU64::new);
+
+        return Ok(offset);
+    }
+
+    fn dynamic_table<T>(&self, vaddr: u64, size: u64, entsize: u64) -> Result<&[T], RelocationError>
+    where
+        T: bytemuck::Pod,
+    {
+        if entsize != mem::size_of::<T>() as u64 {
+            return Err(MemoryError::SizeMismatch.into());
+        }
+
+        let offset = self.vaddr_to_offset(vaddr, size)?;
+        if offset.is_none() {
+            return Err(RelocationError::BadAddress(vaddr));
+        }
+
+        let n = (size / entsize) as usize;
+        return Ok(Self::get_array(self.data, offset, n, entsize as usize)?);
+    }
+
+    /// Collects relocation tables from `PT_DYNAMIC`
+    pub fn relocations(&self) -> Result<Relocations<'_>, RelocationError> {
+        use dynamic::Tag;
+
+        let mut rela = (None, 0, mem::size_of::<dynamic::Rela>() as u64);
+        let mut relr = (None, 0, mem::size_of::<u64>() as u64);
+
+        for entry in self.dynamic()? {
+            match entry.tag() {
+                Some(Tag::Rela) => rela.0 = Some(entry.d_val),
+                Some(Tag::RelaSz) => rela.1 = entry.d_val,
+                Some(Tag::RelaEnt) => rela.2 = entry.d_val,
+                Some(Tag::Relr) => relr.0 = Some(entry.d_val),
+                Some(Tag::RelrSz) => relr.1 = entry.d_val,
+                Some(Tag::RelrEnt) => relr.2 = entry.d_val,
+                Some(tag @ (Tag::Rel | Tag::JmpRel)) => {
+                    return Err(RelocationError::UnsupportedTable(tag));
+                },
+                _ => {},
+            }
+        }
+
+        let mut relocations = Relocations::default();
+        if let (Some(vaddr), size, entsize) = rela {
+            relocations.rela = self.dynamic_table(vaddr, size, entsize)?;
+        }
+        if let (Some(vaddr), size, entsize) = relr {
+            relocations.relr = self.dynamic_table(vaddr, size, entsize)?;
+        }
+
+        return Ok(relocations);
+    }
+
     pub fn header(&self) -> &Header {
         bytemuck::from_bytes(&self.data[..M::HEADER_SIZE])
     }
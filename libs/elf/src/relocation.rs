@@ -0,0 +1,124 @@
+use crate::dynamic::{self, Rela, RelocationType};
+use crate::MemoryError;
+
+#[derive(Clone, Copy, Debug)]
+pub enum RelocationError {
+    Memory(MemoryError),
+
+    /// Table address from `PT_DYNAMIC` is not backed by any `PT_LOAD` segment
+    BadAddress(u64),
+
+    /// Only `DT_RELA` and `DT_RELR` are supported, there is no use for
+    /// `DT_REL` or PLT relocations in a static executable
+    UnsupportedTable(dynamic::Tag),
+    UnsupportedType(u32),
+
+    /// Relocation would write outside of the image
+    OutOfBounds(u64),
+}
+
+impl From<MemoryError> for RelocationError {
+    fn from(e: MemoryError) -> Self {
+        Self::Memory(e)
+    }
+}
+
+/// Relocation tables of a static position-independent executable
+#[derive(Clone, Copy, Default)]
+pub struct Relocations<'a> {
+    pub rela: &'a [Rela],
+
+    /// Packed relative relocations, see `apply`
+    pub relr: &'a [u64],
+}
+
+/// What a relocation does to the word at its offset
+enum Fixup {
+    Write(u64),
+    AddBase,
+}
+
+fn slot(image: &mut [u8], offset: u64) -> Result<&mut [u8; 8], RelocationError> {
+    return usize::try_from(offset)
+        .ok()
+        .and_then(|start| image.get_mut(start..start.checked_add(8)?))
+        .and_then(|slot| slot.try_into().ok())
+        .ok_or(RelocationError::OutOfBounds(offset));
+}
+
+fn add(image: &mut [u8], offset: u64, value: u64) -> Result<(), RelocationError> {
+    let slot = slot(image, offset)?;
+    *slot = u64::from_le_bytes(*slot).wrapping_add(value).to_le_bytes();
+    return Ok(());
+}
+
+fn write(image: &mut [u8], offset: u64, value: u64) -> Result<(), RelocationError> {
+    *slot(image, offset)? = value.to_le_bytes();
+    return Ok(());
+}
+
+impl<'a> Relocations<'a> {
+    pub fn is_empty(&self) -> bool {
+        self.rela.is_empty() && self.relr.is_empty()
+    }
+
+    /// Relocates `image`, which holds the loaded segments of an executable
+    /// linked at address 0, so that it can run at `base`.
+    ///
+    /// Nothing is rolled back on error, the image should be thrown away.
+    pub fn apply(&self, image: &mut [u8], base: u64) -> Result<(), RelocationError> {
+        return self.walk(base, |offset, fixup| match fixup {
+            Fixup::Write(value) => write(image, offset, value),
+            Fixup::AddBase => add(image, offset, base),
+        });
+    }
+
+    /// Whether `apply` would succeed on an image of `size` bytes, without
+    /// touching it
+    pub fn check(&self, size: u64) -> Result<(), RelocationError> {
+        return self.walk(0, |offset, _| match offset.checked_add(8) {
+            Some(end) if end <= size => Ok(()),
+            _ => Err(RelocationError::OutOfBounds(offset)),
+        });
+    }
+
+    fn walk(
+        &self,
+        base: u64,
+        mut f: impl FnMut(u64, Fixup) -> Result<(), RelocationError>,
+    ) -> Result<(), RelocationError> {
+        for rela in self.rela {
+            match rela.relocation_type() {
+                Some(RelocationType::None) => {},
+                Some(RelocationType::Relative) => {
+                    f(rela.r_offset, Fixup::Write(base.wrapping_add_signed(rela.r_addend)))?;
+                },
+                _ => return Err(RelocationError::UnsupportedType(rela.typ())),
+            }
+        }
+
+        // An even entry is the address of the next word to relocate, an odd
+        // one is a bitmap of the 63 words that follow the last one
+        let mut next = 0u64;
+        for &entry in self.relr {
+            if entry & 1 == 0 {
+                f(entry, Fixup::AddBase)?;
+                next = entry.wrapping_add(8);
+                continue;
+            }
+
+            let mut bitmap = entry >> 1;
+            let mut offset = next;
+            while bitmap != 0 {
+                if bitmap & 1 == 1 {
+                    f(offset, Fixup::AddBase)?;
+                }
+                bitmap >>= 1;
+                offset = offset.wrapping_add(8);
+            }
+            next = next.wrapping_add(63 * 8);
+        }
+
+        return Ok(());
+    }
+}
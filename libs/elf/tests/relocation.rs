@@ -0,0 +1,154 @@
+use elf::dynamic::{Rela, RelocationType, Tag};
+use elf::{Amd64, Elf, RelocationError, Relocations};
+
+const BASE: u64 = 0xFFFF_C000_0020_0000;
+
+fn rela(r_offset: u64, typ: RelocationType, r_addend: i64) -> Rela {
+    Rela { r_offset, r_info: typ as u64, r_addend }
+}
+
+fn word(image: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(image[offset..][..8].try_into().unwrap())
+}
+
+#[test]
+fn rela_relative() {
+    let table = [
+        rela(0x00, RelocationType::Relative, 0x1000),
+        rela(0x10, RelocationType::None, 0),
+        rela(0x18, RelocationType::Relative, -8),
+    ];
+    let relocations = Relocations { rela: &table, relr: &[] };
+
+    let mut image = [0xAAu8; 0x20];
+    relocations.apply(&mut image, BASE).unwrap();
+
+    assert_eq!(word(&image, 0x00), BASE + 0x1000);
+    assert_eq!(word(&image, 0x08), 0xAAAA_AAAA_AAAA_AAAA);
+    assert_eq!(word(&image, 0x10), 0xAAAA_AAAA_AAAA_AAAA);
+    assert_eq!(word(&image, 0x18), BASE - 8);
+}
+
+#[test]
+fn relr() {
+    let mut image = vec![0u8; 0x1000];
+    for i in 0..image.len() / 8 {
+        image[i * 8..][..8].copy_from_slice(&(i as u64 * 0x100).to_le_bytes());
+    }
+
+    // Word 2, then words 3 and 5 from the bitmap, then word 3 + 63 = 66
+    let table = [2 * 8, 0b101 << 1 | 1, 1 << 1 | 1];
+    let relocations = Relocations { rela: &[], relr: &table };
+    relocations.apply(&mut image, BASE).unwrap();
+
+    for i in 0..image.len() / 8 {
+        let expected = match i {
+            2 | 3 | 5 | 66 => BASE + i as u64 * 0x100,
+            _ => i as u64 * 0x100,
+        };
+        assert_eq!(word(&image, i * 8), expected, "word {}", i);
+    }
+}
+
+#[test]
+fn unsupported_type() {
+    let table = [rela(0, RelocationType::GlobDat, 0)];
+    let relocations = Relocations { rela: &table, relr: &[] };
+
+    let mut image = [0u8; 8];
+    match relocations.apply(&mut image, BASE) {
+        Err(RelocationError::UnsupportedType(6)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn out_of_bounds() {
+    let mut image = [0u8; 0x10];
+
+    let table = [rela(0x0C, RelocationType::Relative, 0)];
+    let relocations = Relocations { rela: &table, relr: &[] };
+    match relocations.apply(&mut image, BASE) {
+        Err(RelocationError::OutOfBounds(0x0C)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    let relocations = Relocations { rela: &[], relr: &[u64::MAX - 7] };
+    match relocations.apply(&mut image, BASE) {
+        Err(RelocationError::OutOfBounds(_)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // `check` finds the same problems without an image
+    let table = [rela(0x08, RelocationType::Relative, 0)];
+    let relocations = Relocations { rela: &table, relr: &[0, 0b11] };
+    assert!(relocations.check(0x10).is_ok());
+    match relocations.check(0x0F) {
+        Err(RelocationError::OutOfBounds(0x08)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+    let relocations = Relocations { rela: &[], relr: &[0, 0b111] };
+    match relocations.check(0x10) {
+        Err(RelocationError::OutOfBounds(0x10)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+/// Header, a `PT_LOAD` of the whole file at 0 and a `PT_DYNAMIC` with the
+/// given entries, as u64 so that it's aligned
+fn dynamic_elf(load_offset: u64, entries: &[(Tag, u64)]) -> Vec<u64> {
+    const DYNAMIC: u64 = 64 + 2 * 56;
+    let dynamic_size = (entries.len() as u64 + 1) * 16;
+    let size = DYNAMIC + dynamic_size;
+
+    let mut bytes = vec![0x7F, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    bytes.extend_from_slice(&[2, 0, 0x3E, 0, 1, 0, 0, 0]);
+    for x in [0x1000u64, 64, 0] {
+        bytes.extend_from_slice(&x.to_le_bytes());
+    }
+    bytes.extend_from_slice(&[0, 0, 0, 0, 64, 0, 56, 0, 2, 0, 64, 0, 0, 0, 0, 0]);
+
+    for (typ, offset, vaddr, filesz) in [(1u32, load_offset, 0, size), (2, DYNAMIC, DYNAMIC, dynamic_size)] {
+        bytes.extend_from_slice(&typ.to_le_bytes());
+        bytes.extend_from_slice(&4u32.to_le_bytes());
+        for x in [offset, vaddr, vaddr, filesz, filesz, 0x1000] {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+
+    for &(tag, val) in entries.iter().chain([(Tag::Null, 0)].iter()) {
+        bytes.extend_from_slice(&(tag as u64).to_le_bytes());
+        bytes.extend_from_slice(&val.to_le_bytes());
+    }
+
+    assert_eq!(bytes.len() as u64, size);
+    bytes.chunks(8).map(|w| u64::from_le_bytes(w.try_into().unwrap())).collect()
+}
+
+fn relocations_of(words: &[u64]) -> Result<usize, RelocationError> {
+    // SAFETY: u8 has no alignment or validity requirements
+    let bytes = unsafe { core::slice::from_raw_parts(words.as_ptr().cast::<u8>(), words.len() * 8) };
+    let elf = Elf::<Amd64>::from_bytes(bytes).unwrap();
+    elf.relocations().map(|r| r.rela.len() + r.relr.len())
+}
+
+#[test]
+fn dynamic_table_addresses() {
+    // RELR table over the dynamic segment itself, two entries
+    let elf = dynamic_elf(0, &[(Tag::Relr, 64 + 2 * 56), (Tag::RelrSz, 16)]);
+    assert_eq!(relocations_of(&elf).unwrap(), 2);
+
+    // End of the table wraps around
+    let elf = dynamic_elf(0, &[(Tag::Rela, u64::MAX - 8), (Tag::RelaSz, 24)]);
+    match relocations_of(&elf) {
+        Err(RelocationError::BadAddress(a)) if a == u64::MAX - 8 => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // File offset of the table wraps around
+    let elf = dynamic_elf(u64::MAX - 0x10, &[(Tag::Rela, 0x20), (Tag::RelaSz, 24)]);
+    match relocations_of(&elf) {
+        Err(RelocationError::BadAddress(0x20)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+}
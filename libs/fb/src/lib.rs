@@ -6,13 +6,52 @@ use core::fmt;
 
 pub const FONT_X: usize = 9;
 pub const FONT_Y: usize = 16;
-const COLORS_BYTES: usize = 4;
 
-#[repr(transparent)]
-#[derive(Clone, Copy)]
-struct Rgba([u8; COLORS_BYTES]);
+/// The font is stored as black and white RGBA
+const FONT_PIXEL_BYTES: usize = 4;
 
-const FONT: [u8; FONT_X * FONT_Y * 256 * COLORS_BYTES] = *include_bytes!("../font.raw");
+static FONT: [u8; FONT_X * FONT_Y * 256 * FONT_PIXEL_BYTES] = *include_bytes!("../font.raw");
+
+/// Which bits of a pixel hold each color, pixels take up to 4 bytes
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct PixelFormat {
+    pub red:      u32,
+    pub green:    u32,
+    pub blue:     u32,
+    pub reserved: u32,
+}
+
+impl PixelFormat {
+    /// Red in the first byte
+    pub const RGB: Self = Self { red: 0xFF, green: 0xFF00, blue: 0xFF_0000, reserved: 0xFF00_0000 };
+
+    /// Blue in the first byte, like UEFI's `BltPixel`
+    pub const BGR: Self = Self { red: 0xFF_0000, green: 0xFF00, blue: 0xFF, reserved: 0xFF00_0000 };
+
+    pub const fn bytes_per_pixel(&self) -> usize {
+        let all = self.red | self.green | self.blue | self.reserved;
+        return (32 - all.leading_zeros() as usize).div_ceil(8);
+    }
+
+    /// Scales 8-bit colors to the sizes of the masks
+    pub const fn encode(&self, red: u8, green: u8, blue: u8) -> u32 {
+        scale(self.red, red) | scale(self.green, green) | scale(self.blue, blue)
+    }
+}
+
+const fn scale(mask: u32, value: u8) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let shift = mask.trailing_zeros();
+    let max = (mask >> shift) as u64;
+    return (((value as u64 * max + 127) / 255) as u32) << shift;
+}
+
+/// Called with a rectangle of pixels that changed
+pub type Flush = fn(fb: &Framebuffer, x: usize, y: usize, width: usize, height: usize);
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -28,6 +67,10 @@ pub struct Framebuffer {
 
     pub scanline_width: usize,
 
+    /// Resolution in pixels
+    pub width: usize,
+    pub height: usize,
+
     pub max_x: u16,
     pub max_y: u16,
 
@@ -35,9 +78,50 @@ pub struct Framebuffer {
     pub cursor_y: u16,
 
     pub mode: Mode,
+    pub format: PixelFormat,
+
+    /// Set when `base` is only a copy of the screen, e.g. for UEFI modes
+    /// without a linear framebuffer
+    pub flush: Option<Flush>,
 }
 
 impl Framebuffer {
+    /// `width` and `height` are in pixels, `scanline_width` too
+    pub fn new(
+        base: *mut u8,
+        memsize: usize,
+        scanline_width: usize,
+        width: usize,
+        height: usize,
+        format: PixelFormat,
+    ) -> Self {
+        Self {
+            base,
+            memsize,
+            scanline_width,
+            width,
+            height,
+            max_x: (width / FONT_X) as u16,
+            max_y: (height / FONT_Y) as u16,
+            cursor_x: 0,
+            cursor_y: 0,
+            mode: Mode::Overwrite,
+            format,
+            flush: None,
+        }
+    }
+
+    fn flush(&self, x: usize, y: usize, width: usize, height: usize) {
+        if let Some(flush) = self.flush {
+            flush(self, x, y, width, height);
+        }
+    }
+
+    /// Bytes in one row of text
+    fn text_line_bytes(&self) -> usize {
+        FONT_Y * self.scanline_width * self.format.bytes_per_pixel()
+    }
+
     fn advance_cursor_y(&mut self) {
         self.cursor_y += 1;
         if self.cursor_y == self.max_y {
@@ -51,11 +135,28 @@ impl Framebuffer {
         }
     }
 
+    /// `index` counts pixels from `base`
+    fn put_pixel(&mut self, index: usize, value: u32) {
+        let bytes_per_pixel = self.format.bytes_per_pixel();
+        unsafe {
+            let p = self.base.add(index * bytes_per_pixel);
+            if bytes_per_pixel == 4 {
+                core::ptr::write_volatile(p.cast::<u32>(), value);
+                return;
+            }
+
+            for (i, byte) in value.to_le_bytes()[..bytes_per_pixel].iter().enumerate() {
+                core::ptr::write_volatile(p.add(i), *byte);
+            }
+        }
+    }
+
     pub fn draw_letter(&mut self, b: u8) {
         let x = self.cursor_x as usize;
         let y = self.cursor_y as usize;
         let font_x = (b % 16) as usize;
         let font_y = (b / 16) as usize;
+        let white = self.format.encode(0xFF, 0xFF, 0xFF);
 
         for fy in 0..FONT_Y {
             for fx in 0..FONT_X {
@@ -64,25 +165,25 @@ impl Framebuffer {
                 let font_pixel_y = font_y * FONT_Y + fy;
                 let font_pixel = font_pixel_y * 16 * FONT_X + font_pixel_x;
 
-                unsafe {
-                    let fb_ptr = self.base.cast::<Rgba>().add(fb_coord);
-                    let font_ptr = FONT.as_ptr().cast::<Rgba>().add(font_pixel);
-                    core::ptr::write_volatile(fb_ptr, *font_ptr);
-                }
+                let lit = FONT[font_pixel * FONT_PIXEL_BYTES] != 0;
+                self.put_pixel(fb_coord, if lit { white } else { 0 });
             }
         }
+
+        self.flush(x * FONT_X, y * FONT_Y, FONT_X, FONT_Y);
     }
 
     fn newline_overwrite(&mut self) {
         self.cursor_x = 0;
         self.advance_cursor_y();
-        let fb_line_base =
-            self.cursor_y as usize * FONT_Y * self.scanline_width * COLORS_BYTES;
-        let fb_line_len = FONT_Y * self.scanline_width * COLORS_BYTES;
+        let fb_line_len = self.text_line_bytes();
+        let fb_line_base = self.cursor_y as usize * fb_line_len;
         unsafe {
             let p = self.base.add(fb_line_base);
             core::intrinsics::volatile_set_memory(p, 0u8, fb_line_len);
         }
+
+        self.flush(0, self.cursor_y as usize * FONT_Y, self.width, FONT_Y);
     }
 
     pub fn draw_letter_overwrite(&mut self, b: u8) {
@@ -107,7 +208,7 @@ impl Framebuffer {
 
     fn newline_scroll(&mut self) {
         self.cursor_x = 0;
-        let one_text_line = self.scanline_width * FONT_Y * COLORS_BYTES;
+        let one_text_line = self.text_line_bytes();
         let src: *const u8 = unsafe { self.base.add(one_text_line) };
         let dst: *mut u8 = self.base;
         let len: usize = one_text_line * (self.max_y as usize - 1);
@@ -116,6 +217,8 @@ impl Framebuffer {
             core::intrinsics::volatile_copy_nonoverlapping_memory(dst, src, len);
             core::intrinsics::volatile_set_memory(last_line_ptr, 0u8, one_text_line);
         }
+
+        self.flush(0, 0, self.width, self.max_y as usize * FONT_Y);
     }
 
     pub fn draw_letter_scroll(&mut self, b: u8) {
@@ -0,0 +1,48 @@
+use fb::*;
+
+const RGB565: PixelFormat = PixelFormat { red: 0xF800, green: 0x07E0, blue: 0x001F, reserved: 0 };
+
+#[test]
+fn pixel_formats() {
+    assert_eq!(PixelFormat::RGB.bytes_per_pixel(), 4);
+    assert_eq!(PixelFormat::BGR.bytes_per_pixel(), 4);
+    assert_eq!(RGB565.bytes_per_pixel(), 2);
+
+    assert_eq!(PixelFormat::RGB.encode(0x12, 0x34, 0x56), 0x0056_3412);
+    assert_eq!(PixelFormat::BGR.encode(0x12, 0x34, 0x56), 0x0012_3456);
+    assert_eq!(RGB565.encode(0xFF, 0xFF, 0xFF), 0xFFFF);
+    assert_eq!(RGB565.encode(0xFF, 0, 0), 0xF800);
+    assert_eq!(RGB565.encode(0, 0x80, 0), 0x0400);
+}
+
+/// Draws `b` in the top left corner of a `width` x `FONT_Y` screen
+fn draw(format: PixelFormat, b: u8) -> Vec<u8> {
+    let width = FONT_X * 2;
+    let mut pixels = vec![0xAAu8; width * FONT_Y * format.bytes_per_pixel()];
+    let mut out = Framebuffer::new(pixels.as_mut_ptr(), pixels.len(), width, width, FONT_Y, format);
+    out.draw_letter(b);
+    pixels
+}
+
+#[test]
+fn letters_use_the_format() {
+    let rgb = draw(PixelFormat::RGB, b'#');
+    let rgb565 = draw(RGB565, b'#');
+
+    // Every pixel of the cell is either white or black, the rest is untouched
+    for (i, pixel) in rgb.chunks(4).enumerate() {
+        let narrow = &rgb565[i * 2..i * 2 + 2];
+        if i % (FONT_X * 2) >= FONT_X {
+            assert_eq!(pixel, [0xAA; 4]);
+            assert_eq!(narrow, [0xAA; 2]);
+        } else if pixel == [0xFF, 0xFF, 0xFF, 0] {
+            assert_eq!(narrow, [0xFF; 2]);
+        } else {
+            assert_eq!(pixel, [0; 4]);
+            assert_eq!(narrow, [0; 2]);
+        }
+    }
+
+    assert!(rgb.chunks(4).any(|p| p == [0xFF, 0xFF, 0xFF, 0]));
+    assert!(draw(PixelFormat::RGB, b' ').chunks(4).all(|p| p == [0; 4] || p == [0xAA; 4]));
+}
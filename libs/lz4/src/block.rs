@@ -0,0 +1,155 @@
+use crate::Error;
+
+/// Matches are at least this long, shorter ones are not encoded
+const MIN_MATCH: usize = 4;
+
+/// The last bytes of a block are always literals
+const LAST_LITERALS: usize = 5;
+
+/// The last match has to start at least this far from the end of a block
+const MF_LIMIT: usize = 12;
+
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+fn read_length(src: &[u8], i: &mut usize, mut len: usize) -> Result<usize, Error> {
+    if len != 15 {
+        return Ok(len);
+    }
+
+    loop {
+        let byte = *src.get(*i).ok_or(Error::CorruptBlock)?;
+        *i += 1;
+        len = len.checked_add(byte as usize).ok_or(Error::CorruptBlock)?;
+        if byte != 255 {
+            return Ok(len);
+        }
+    }
+}
+
+/// Decodes one block into `out[pos..]`. Matches may reach back before `pos`,
+/// which is how linked blocks work. Returns the new end of the output.
+pub fn decompress_block(src: &[u8], out: &mut [u8], mut pos: usize) -> Result<usize, Error> {
+    let mut i = 0usize;
+
+    loop {
+        let token = *src.get(i).ok_or(Error::CorruptBlock)?;
+        i += 1;
+
+        let literals = read_length(src, &mut i, (token >> 4) as usize)?;
+        let lit_src = src.get(i..).and_then(|s| s.get(..literals)).ok_or(Error::CorruptBlock)?;
+        let lit_dst = out.get_mut(pos..).and_then(|d| d.get_mut(..literals)).ok_or(Error::OutputTooSmall)?;
+        lit_dst.copy_from_slice(lit_src);
+        i += literals;
+        pos += literals;
+
+        // The last sequence has no match
+        if i == src.len() {
+            return Ok(pos);
+        }
+
+        let offset = src.get(i..i + 2).ok_or(Error::CorruptBlock)?;
+        let offset = u16::from_le_bytes([offset[0], offset[1]]) as usize;
+        i += 2;
+        if offset == 0 || offset > pos {
+            return Err(Error::CorruptBlock);
+        }
+
+        let len = read_length(src, &mut i, (token & 0xF) as usize)? + MIN_MATCH;
+        if len > out.len() - pos {
+            return Err(Error::OutputTooSmall);
+        }
+
+        // Byte by byte, the match may overlap with what it produces
+        for _ in 0..len {
+            out[pos] = out[pos - offset];
+            pos += 1;
+        }
+    }
+}
+
+fn write_length(out: &mut [u8], o: &mut usize, mut len: usize) -> Result<(), Error> {
+    while len >= 255 {
+        *out.get_mut(*o).ok_or(Error::OutputTooSmall)? = 255;
+        *o += 1;
+        len -= 255;
+    }
+
+    *out.get_mut(*o).ok_or(Error::OutputTooSmall)? = len as u8;
+    *o += 1;
+    return Ok(());
+}
+
+/// Appends a sequence, `len` of zero means only literals
+fn write_sequence(out: &mut [u8], o: &mut usize, literals: &[u8], offset: usize, len: usize) -> Result<(), Error> {
+    let lit_nibble = core::cmp::min(literals.len(), 15);
+    let len_nibble = if len == 0 { 0 } else { core::cmp::min(len - MIN_MATCH, 15) };
+
+    *out.get_mut(*o).ok_or(Error::OutputTooSmall)? = (lit_nibble << 4 | len_nibble) as u8;
+    *o += 1;
+    if lit_nibble == 15 {
+        write_length(out, o, literals.len() - 15)?;
+    }
+
+    let dst = out.get_mut(*o..).and_then(|d| d.get_mut(..literals.len())).ok_or(Error::OutputTooSmall)?;
+    dst.copy_from_slice(literals);
+    *o += literals.len();
+
+    if len == 0 {
+        return Ok(());
+    }
+
+    let dst = out.get_mut(*o..*o + 2).ok_or(Error::OutputTooSmall)?;
+    dst.copy_from_slice(&(offset as u16).to_le_bytes());
+    *o += 2;
+    if len_nibble == 15 {
+        write_length(out, o, len - MIN_MATCH - 15)?;
+    }
+
+    return Ok(());
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn read_u32(src: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([src[i], src[i + 1], src[i + 2], src[i + 3]])
+}
+
+/// Greedy compressor with a single hash table, fast and good enough for
+/// executables. Returns the size of the block.
+pub fn compress_block(src: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let mut table = [u32::MAX; 1 << HASH_BITS];
+    let mut o = 0usize;
+    let mut anchor = 0usize;
+    let mut i = 0usize;
+
+    while i + MF_LIMIT < src.len() {
+        let sequence = read_u32(src, i);
+        let slot = &mut table[hash(sequence)];
+        let candidate = core::mem::replace(slot, i as u32);
+        if candidate == u32::MAX {
+            i += 1;
+            continue;
+        }
+
+        let candidate = candidate as usize;
+        if i - candidate > MAX_OFFSET || read_u32(src, candidate) != sequence {
+            i += 1;
+            continue;
+        }
+
+        let mut len = MIN_MATCH;
+        while i + len < src.len() - LAST_LITERALS && src[candidate + len] == src[i + len] {
+            len += 1;
+        }
+
+        write_sequence(out, &mut o, &src[anchor..i], i - candidate, len)?;
+        i += len;
+        anchor = i;
+    }
+
+    write_sequence(out, &mut o, &src[anchor..], 0, 0)?;
+    return Ok(o);
+}
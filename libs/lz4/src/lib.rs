@@ -0,0 +1,200 @@
+#![no_std]
+
+//! LZ4 frames, used to compress the kernel image.
+//!
+//! Only what the loader needs is supported: frames must carry the content
+//! size, so that the output can be allocated upfront, and dictionaries are
+//! rejected. Checksums are verified when present.
+
+mod block;
+mod xxhash;
+
+pub use block::*;
+pub use xxhash::*;
+
+pub const MAGIC: u32 = 0x184D_2204;
+
+/// Blocks written by `compress`, the biggest size LZ4 allows
+const BLOCK_SIZE: usize = 4 << 20;
+const BLOCK_SIZE_ID: u8 = 7;
+
+/// Magic, FLG, BD, content size and the header checksum
+const HEADER_SIZE: usize = 4 + 2 + 8 + 1;
+
+/// Set in a block size when the block is stored as is
+const UNCOMPRESSED: u32 = 1 << 31;
+
+const FLG_VERSION: u8 = 0b01 << 6;
+const FLG_VERSION_MASK: u8 = 0b11 << 6;
+const FLG_BLOCK_INDEPENDENT: u8 = 1 << 5;
+const FLG_BLOCK_CHECKSUM: u8 = 1 << 4;
+const FLG_CONTENT_SIZE: u8 = 1 << 3;
+const FLG_CONTENT_CHECKSUM: u8 = 1 << 2;
+const FLG_RESERVED: u8 = 1 << 1;
+const FLG_DICT_ID: u8 = 1 << 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    BadMagic,
+    UnsupportedVersion(u8),
+
+    /// Dictionaries, reserved bits or a frame without the content size
+    Unsupported,
+    BadHeaderChecksum,
+
+    /// Input ends in the middle of the frame
+    Truncated,
+    CorruptBlock,
+    OutputTooSmall,
+
+    /// Block or content checksum doesn't match
+    BadChecksum,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// Size of the decompressed data
+    pub content_size: u64,
+    pub max_block_size: usize,
+    pub block_checksum: bool,
+    pub content_checksum: bool,
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32, Error> {
+    let bytes = data.get(at..at + 4).ok_or(Error::Truncated)?;
+    return Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+}
+
+/// Checks the magic number only, like `file` would
+pub fn is_compressed(data: &[u8]) -> bool {
+    read_u32(data, 0) == Ok(MAGIC)
+}
+
+/// Parses the frame header, returns it and its size
+fn parse_header(data: &[u8]) -> Result<(FrameInfo, usize), Error> {
+    if read_u32(data, 0)? != MAGIC {
+        return Err(Error::BadMagic);
+    }
+
+    let descriptor = data.get(4..6).ok_or(Error::Truncated)?;
+    let (flg, bd) = (descriptor[0], descriptor[1]);
+    if flg & FLG_VERSION_MASK != FLG_VERSION {
+        return Err(Error::UnsupportedVersion(flg >> 6));
+    }
+    if flg & (FLG_RESERVED | FLG_DICT_ID) != 0 || flg & FLG_CONTENT_SIZE == 0 {
+        return Err(Error::Unsupported);
+    }
+
+    let block_size_id = (bd >> 4) & 0b111;
+    if bd & 0b1000_1111 != 0 || block_size_id < 4 {
+        return Err(Error::Unsupported);
+    }
+
+    let size = data.get(6..14).ok_or(Error::Truncated)?;
+    let content_size = u64::from_le_bytes(size.try_into().unwrap());
+    let checksum = *data.get(14).ok_or(Error::Truncated)?;
+    if (xxh32(&data[4..14], 0) >> 8) as u8 != checksum {
+        return Err(Error::BadHeaderChecksum);
+    }
+
+    let info = FrameInfo {
+        content_size,
+        max_block_size: 1 << (8 + 2 * block_size_id),
+        block_checksum: flg & FLG_BLOCK_CHECKSUM != 0,
+        content_checksum: flg & FLG_CONTENT_CHECKSUM != 0,
+    };
+    return Ok((info, HEADER_SIZE));
+}
+
+pub fn frame_info(data: &[u8]) -> Result<FrameInfo, Error> {
+    parse_header(data).map(|(info, _)| info)
+}
+
+/// Decompresses a whole frame, `out` has to be at least `content_size` long.
+/// Returns the size of the decompressed data.
+pub fn decompress(data: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let (info, mut i) = parse_header(data)?;
+    if (out.len() as u64) < info.content_size {
+        return Err(Error::OutputTooSmall);
+    }
+
+    let mut pos = 0usize;
+    loop {
+        let raw_size = read_u32(data, i)?;
+        i += 4;
+        if raw_size == 0 {
+            break;
+        }
+
+        let size = (raw_size & !UNCOMPRESSED) as usize;
+        if size > info.max_block_size {
+            return Err(Error::CorruptBlock);
+        }
+
+        let block = data.get(i..i + size).ok_or(Error::Truncated)?;
+        i += size;
+        if info.block_checksum {
+            if read_u32(data, i)? != xxh32(block, 0) {
+                return Err(Error::BadChecksum);
+            }
+            i += 4;
+        }
+
+        if raw_size & UNCOMPRESSED != 0 {
+            let dst = out.get_mut(pos..pos + size).ok_or(Error::OutputTooSmall)?;
+            dst.copy_from_slice(block);
+            pos += size;
+        } else {
+            pos = decompress_block(block, out, pos)?;
+        }
+    }
+
+    if pos as u64 != info.content_size {
+        return Err(Error::CorruptBlock);
+    }
+    if info.content_checksum && read_u32(data, i)? != xxh32(&out[..pos], 0) {
+        return Err(Error::BadChecksum);
+    }
+
+    return Ok(pos);
+}
+
+/// Worst case size of `compress` output, when nothing can be compressed
+pub const fn compress_bound(len: usize) -> usize {
+    HEADER_SIZE + len.div_ceil(BLOCK_SIZE) * 4 + len + 4 + 4
+}
+
+/// Compresses `data` into a frame with independent blocks, the content size
+/// and the content checksum. Returns the size of the frame.
+pub fn compress(data: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    if out.len() < compress_bound(data.len()) {
+        return Err(Error::OutputTooSmall);
+    }
+
+    let flg = FLG_VERSION | FLG_BLOCK_INDEPENDENT | FLG_CONTENT_SIZE | FLG_CONTENT_CHECKSUM;
+    out[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    out[4] = flg;
+    out[5] = BLOCK_SIZE_ID << 4;
+    out[6..14].copy_from_slice(&(data.len() as u64).to_le_bytes());
+    out[14] = (xxh32(&out[4..14], 0) >> 8) as u8;
+
+    let mut o = HEADER_SIZE;
+    for chunk in data.chunks(BLOCK_SIZE) {
+        // Anything that doesn't get smaller is stored as is
+        let space = &mut out[o + 4..o + 4 + chunk.len()];
+        let size = match compress_block(chunk, space) {
+            Ok(size) if size < chunk.len() => size as u32,
+            _ => {
+                space.copy_from_slice(chunk);
+                chunk.len() as u32 | UNCOMPRESSED
+            },
+        };
+
+        out[o..o + 4].copy_from_slice(&size.to_le_bytes());
+        o += 4 + (size & !UNCOMPRESSED) as usize;
+    }
+
+    out[o..o + 4].copy_from_slice(&0u32.to_le_bytes());
+    out[o + 4..o + 8].copy_from_slice(&xxh32(data, 0).to_le_bytes());
+    return Ok(o + 8);
+}
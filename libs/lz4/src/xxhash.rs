@@ -0,0 +1,63 @@
+const PRIME1: u32 = 2654435761;
+const PRIME2: u32 = 2246822519;
+const PRIME3: u32 = 3266489917;
+const PRIME4: u32 = 668265263;
+const PRIME5: u32 = 374761393;
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+fn round(acc: u32, input: u32) -> u32 {
+    acc.wrapping_add(input.wrapping_mul(PRIME2)).rotate_left(13).wrapping_mul(PRIME1)
+}
+
+/// XXH32, used by LZ4 frames for all of their checksums
+pub fn xxh32(data: &[u8], seed: u32) -> u32 {
+    let mut rest = data;
+    let mut hash;
+
+    if data.len() >= 16 {
+        let mut acc = [
+            seed.wrapping_add(PRIME1).wrapping_add(PRIME2),
+            seed.wrapping_add(PRIME2),
+            seed,
+            seed.wrapping_sub(PRIME1),
+        ];
+
+        while rest.len() >= 16 {
+            for (i, acc) in acc.iter_mut().enumerate() {
+                *acc = round(*acc, read_u32(&rest[i * 4..]));
+            }
+            rest = &rest[16..];
+        }
+
+        hash = acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18));
+    } else {
+        hash = seed.wrapping_add(PRIME5);
+    }
+
+    hash = hash.wrapping_add(data.len() as u32);
+
+    while rest.len() >= 4 {
+        hash = hash.wrapping_add(read_u32(rest).wrapping_mul(PRIME3));
+        hash = hash.rotate_left(17).wrapping_mul(PRIME4);
+        rest = &rest[4..];
+    }
+
+    for &byte in rest {
+        hash = hash.wrapping_add((byte as u32).wrapping_mul(PRIME5));
+        hash = hash.rotate_left(11).wrapping_mul(PRIME1);
+    }
+
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(PRIME2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(PRIME3);
+    hash ^= hash >> 16;
+    return hash;
+}
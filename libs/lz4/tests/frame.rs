@@ -0,0 +1,126 @@
+//! Vectors in `vectors/` were made with the reference `lz4` 1.9.4:
+//! `lz4 --content-size <file>`, plus `-BX -B4` for `text_checksums.lz4`.
+
+use lz4::*;
+
+const HELLO: &[u8] = include_bytes!("vectors/hello.lz4");
+const TEXT: &[u8] = include_bytes!("vectors/text.lz4");
+const TEXT_CHECKSUMS: &[u8] = include_bytes!("vectors/text_checksums.lz4");
+const NOISE: &[u8] = include_bytes!("vectors/noise.lz4");
+const NO_CONTENT_SIZE: &[u8] = include_bytes!("vectors/no_content_size.lz4");
+
+fn text() -> Vec<u8> {
+    (0..2000)
+        .flat_map(|i| format!("sovos {} kernel image\n", i % 37).into_bytes())
+        .collect()
+}
+
+/// Same bytes as `vectors/noise`, PCG-style LCG so nothing compresses
+fn noise(len: usize) -> Vec<u8> {
+    let mut x = 1u64;
+    (0..len)
+        .map(|_| {
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (x >> 56) as u8
+        })
+        .collect()
+}
+
+fn decompress_vec(frame: &[u8]) -> Result<Vec<u8>, Error> {
+    let info = frame_info(frame)?;
+    let mut out = vec![0u8; info.content_size as usize];
+    let len = decompress(frame, &mut out)?;
+    out.truncate(len);
+    Ok(out)
+}
+
+fn compress_vec(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; compress_bound(data.len())];
+    let len = compress(data, &mut out).unwrap();
+    out.truncate(len);
+    out
+}
+
+#[test]
+fn xxh32_known_values() {
+    assert_eq!(xxh32(b"", 0), 0x02CC_5D05);
+    assert_eq!(xxh32(b"a", 0), 0x550D_7456);
+    assert_eq!(xxh32(b"abc", 0), 0x32D1_53FF);
+    assert_eq!(xxh32(b"Nobody inspects the spammish repetition", 0), 0xE229_3B2F);
+}
+
+#[test]
+fn reference_vectors() {
+    assert!(is_compressed(HELLO));
+    assert_eq!(decompress_vec(HELLO).unwrap(), b"hello, world\n");
+    assert_eq!(decompress_vec(TEXT).unwrap(), text());
+    assert_eq!(decompress_vec(NOISE).unwrap(), noise(3000));
+
+    let info = frame_info(TEXT_CHECKSUMS).unwrap();
+    assert!(info.block_checksum && info.content_checksum);
+    assert_eq!(info.max_block_size, 64 << 10);
+    assert_eq!(decompress_vec(TEXT_CHECKSUMS).unwrap(), text());
+}
+
+#[test]
+fn roundtrip() {
+    let mut long = text();
+    long.extend(noise(10_000));
+    long.extend(vec![0u8; 5 << 20]);
+    long.extend(text());
+
+    let inputs: [&[u8]; 6] = [b"", b"a", b"hello, world\n", &text(), &noise(3000), &long];
+    for input in inputs {
+        let frame = compress_vec(input);
+        assert_eq!(decompress_vec(&frame).unwrap(), input);
+    }
+
+    // Incompressible data is stored, so the frame barely grows
+    assert!(compress_vec(&noise(3000)).len() <= compress_bound(3000));
+    assert!(compress_vec(&text()).len() < text().len() / 10);
+}
+
+#[test]
+fn rejected_frames() {
+    assert!(!is_compressed(b"\x7fELF"));
+    assert_eq!(frame_info(b"\x7fELF\x02\x01\x01\x00").err(), Some(Error::BadMagic));
+    assert_eq!(frame_info(NO_CONTENT_SIZE).err(), Some(Error::Unsupported));
+    assert_eq!(frame_info(&HELLO[..10]).err(), Some(Error::Truncated));
+
+    let mut bad = HELLO.to_vec();
+    bad[14] ^= 1;
+    assert_eq!(frame_info(&bad).err(), Some(Error::BadHeaderChecksum));
+
+    let mut out = [0u8; 4];
+    assert_eq!(decompress(HELLO, &mut out).err(), Some(Error::OutputTooSmall));
+
+    // Payload of the first block
+    let mut bad = TEXT_CHECKSUMS.to_vec();
+    bad[30] ^= 1;
+    assert_eq!(decompress_vec(&bad).err(), Some(Error::BadChecksum));
+
+    // Content checksum
+    let mut bad = HELLO.to_vec();
+    *bad.last_mut().unwrap() ^= 1;
+    assert_eq!(decompress_vec(&bad).err(), Some(Error::BadChecksum));
+
+    assert_eq!(decompress_vec(&TEXT[..TEXT.len() - 10]).err(), Some(Error::Truncated));
+}
+
+#[test]
+fn corrupt_blocks() {
+    let mut out = [0u8; 64];
+
+    // Match before the start of the output
+    assert_eq!(decompress_block(&[0x10, b'a', 0x02, 0x00], &mut out, 0), Err(Error::CorruptBlock));
+
+    // Zero offset
+    assert_eq!(decompress_block(&[0x10, b'a', 0x00, 0x00], &mut out, 0), Err(Error::CorruptBlock));
+
+    // Literals past the end of the block
+    assert_eq!(decompress_block(&[0x50, b'a'], &mut out, 0), Err(Error::CorruptBlock));
+
+    // Overlapping match repeats the literal
+    assert_eq!(decompress_block(&[0x14, b'a', 0x01, 0x00, 0x00], &mut out, 0), Ok(9));
+    assert_eq!(&out[..9], b"aaaaaaaaa");
+}
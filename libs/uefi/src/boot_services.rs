@@ -1,20 +1,35 @@
+use core::ops::{Deref, DerefMut};
+
 use super::*;
+use impl_bits::impl_bits;
 
 #[repr(C)]
 pub struct BootServices {
-    header: TableHeader,
+    pub(crate) header: TableHeader,
 
-    pub raise_tpl:   usize,
-    pub restore_tpl: usize,
+    /// Raises the task priority level and returns the previous one, which
+    /// has to be passed to RestoreTPL()
+    raise_tpl:   Option<extern "efiapi" fn(new_tpl: Tpl) -> Tpl>,
+    restore_tpl: Option<extern "efiapi" fn(old_tpl: Tpl)>,
 
-    pub allocate_pages: extern "efiapi" fn(
-            usize,
-            memory::Type,
-            u32,
-            *mut *mut u8,
+    /// ## Parameters
+    /// * Type - The type of allocation to perform.
+    /// * MemoryType - The type of memory to allocate.
+    /// * Pages - The number of contiguous 4 KiB pages to allocate.
+    /// * Memory - Pointer to a physical address. On input, the way in which
+    /// the address is used depends on the value of Type. On output the
+    /// address is set to the base of the page range that was allocated.
+    pub(crate) allocate_pages: Option<
+        extern "efiapi" fn(
+            allocate_type: u32,
+            memory_type: memory::Type,
+            pages: usize,
+            memory: &mut u64,
         ) -> RawStatus,
+    >,
 
-    pub free_pages:     usize,
+    /// Frees memory allocated with AllocatePages()
+    pub(crate) free_pages: Option<extern "efiapi" fn(memory: u64, pages: usize) -> RawStatus>,
 
     /// Parameters
     ///
@@ -84,7 +99,7 @@ pub struct BootServices {
     /// it will remain backwards compatible with the current definition.
     /// Thus OS software must use the DescriptorSize to find the start of
     /// each EFI_MEMORY_DESCRIPTOR in the MemoryMap array.
-    get_memory_map: Option<
+    pub(crate) get_memory_map: Option<
         extern "efiapi" fn(
             &mut usize,
             *mut memory::Descriptor,
@@ -94,30 +109,171 @@ pub struct BootServices {
         ) -> RawStatus,
     >,
 
-    pub allocate_pool: usize,
-    pub free_pool:     usize,
-
-    pub create_event:   usize,
-    pub set_timer:      usize,
-    pub wait_for_event: usize,
-    pub signal_event:   usize,
-    pub close_event:    usize,
-    pub check_event:    usize,
-
-    pub install_proto_interface:   usize,
-    pub reinstall_proto_interface: usize,
-    pub uninstall_proto_interface: usize,
-    pub handle_protocol:           usize,
-    __reserved:                    usize,
-    pub register_protocol_notify:  usize,
-    pub locate_handle:             usize,
-    pub locate_device_path:        usize,
-    pub install_cfg_table:         usize,
-
-    pub image_load:   usize,
-    pub image_start:  usize,
-    pub exit:         usize,
-    pub image_unload: usize,
+    /// Allocates `size` bytes of `pool_type`, the buffer is 8 byte aligned
+    pub(crate) allocate_pool: Option<
+        extern "efiapi" fn(
+            pool_type: memory::Type,
+            size: usize,
+            buffer: &mut *mut u8,
+        ) -> RawStatus,
+    >,
+    pub(crate) free_pool: Option<extern "efiapi" fn(buffer: *mut u8) -> RawStatus>,
+
+    /// ## Parameters
+    /// * Type - The type of event to create and its mode and attributes.
+    /// * NotifyTpl - The task priority level of event notifications, if
+    /// needed.
+    /// * NotifyFunction - Pointer to the event's notification function, if
+    /// any.
+    /// * NotifyContext - Pointer to the notification function's context.
+    /// * Event - Pointer to the newly created event if the call succeeds.
+    create_event: Option<
+        extern "efiapi" fn(
+            event_type: EventType,
+            notify_tpl: Tpl,
+            notify_function: Option<EventNotify>,
+            notify_context: *mut (),
+            event: &mut Option<Event>,
+        ) -> RawStatus,
+    >,
+
+    /// Trigger time is in 100ns units, its meaning depends on the timer type
+    set_timer:      Option<extern "efiapi" fn(event: Event, timer_type: u32, trigger_time: u64) -> RawStatus>,
+    /// Stops execution until one of the events is signaled, `index` is set to
+    /// the one that was
+    wait_for_event: Option<
+        extern "efiapi" fn(
+            number_of_events: usize,
+            event: *const Event,
+            index: &mut usize,
+        ) -> RawStatus,
+    >,
+    signal_event:   Option<extern "efiapi" fn(event: Event) -> RawStatus>,
+    close_event:    Option<extern "efiapi" fn(event: Event) -> RawStatus>,
+    check_event:    Option<extern "efiapi" fn(event: Event) -> RawStatus>,
+
+    /// Interface type is always EFI_NATIVE_INTERFACE (0). A null `handle`
+    /// creates a new one.
+    install_protocol_interface:   Option<
+        extern "efiapi" fn(
+            handle: &mut Handle,
+            protocol: &Guid,
+            interface_type: u32,
+            interface: *mut (),
+        ) -> RawStatus,
+    >,
+    reinstall_protocol_interface: Option<
+        extern "efiapi" fn(
+            handle: Handle,
+            protocol: &Guid,
+            old_interface: *mut (),
+            new_interface: *mut (),
+        ) -> RawStatus,
+    >,
+    uninstall_protocol_interface: Option<
+        extern "efiapi" fn(handle: Handle, protocol: &Guid, interface: *mut ()) -> RawStatus,
+    >,
+
+    /// Parameters
+    /// `handle` - The handle being queried.
+    /// `protocol` - The published unique identifier of the protocol.
+    /// `interface` - Supplies the address where a pointer to the
+    /// corresponding Protocol Interface is returned.
+    ///
+    /// Description
+    /// The HandleProtocol() function queries `handle` to determine if it
+    /// supports `protocol`. If it does, then on return `interface` points to a
+    /// pointer to the corresponding Protocol Interface. `interface` can then
+    /// be passed to any protocol service to identify the context of the
+    /// request.
+    ///
+    /// Status codes returned `EFI_SUCCESS` - The interface information for
+    /// the specified protocol was returned.
+    /// `EFI_UNSUPPORTED` - The device does not support the specified protocol.
+    /// `EFI_INVALID_PARAMETER` - `handle` is NULL, `protocol` is NULL or
+    /// `interface` is NULL.
+    pub(crate) handle_protocol: Option<
+        extern "efiapi" fn(
+            handle: Handle,
+            protocol: &Guid,
+            interface: &mut Option<NonNull<()>>,
+        ) -> RawStatus,
+    >,
+    __reserved: usize,
+
+    /// Signals `event` every time an interface is installed for `protocol`
+    register_protocol_notify: Option<
+        extern "efiapi" fn(protocol: &Guid, event: Event, registration: &mut *mut ()) -> RawStatus,
+    >,
+
+    /// ## Parameters
+    /// * SearchType - Specifies which handle(s) are to be returned.
+    /// * Protocol - Specifies the protocol to search by.
+    /// * SearchKey - Specifies the search key.
+    /// * BufferSize - On input, the size in bytes of Buffer. On output, the
+    /// size in bytes of the array returned in Buffer (if the buffer was large
+    /// enough) or the size, in bytes, of the buffer needed to obtain the
+    /// array (if the buffer was not large enough).
+    /// * Buffer - The buffer in which the array is returned.
+    locate_handle: Option<
+        extern "efiapi" fn(
+            search_type: u32,
+            protocol: Option<&Guid>,
+            search_key: *const (),
+            buffer_size: &mut usize,
+            buffer: *mut Handle,
+        ) -> RawStatus,
+    >,
+
+    /// Finds the handle closest to the end of `device_path` that supports
+    /// `protocol`, advancing `device_path` past the matched part
+    locate_device_path: Option<
+        extern "efiapi" fn(protocol: &Guid, device_path: &mut *const (), device: &mut Handle) -> RawStatus,
+    >,
+
+    /// Adds, updates or, with a null `table`, removes a configuration table
+    install_configuration_table: Option<extern "efiapi" fn(guid: &Guid, table: *const ()) -> RawStatus>,
+
+    /// ## Parameters
+    /// * BootPolicy - If TRUE, indicates that the request originates from the
+    /// boot manager, and that the boot manager is attempting to load
+    /// DevicePath as a boot selection. Ignored if SourceBuffer is not NULL.
+    /// * ParentImageHandle - The caller's image handle.
+    /// * DevicePath - The DeviceHandle specific file path from which the
+    /// image is loaded.
+    /// * SourceBuffer - If not NULL, a pointer to the memory location
+    /// containing a copy of the image to be loaded.
+    /// * SourceSize - The size in bytes of SourceBuffer.
+    /// * ImageHandle - Pointer to the returned image handle that is created
+    /// when the image is successfully loaded.
+    load_image:   Option<
+        extern "efiapi" fn(
+            boot_policy: bool,
+            parent_image_handle: Handle,
+            device_path: *const (),
+            source_buffer: *const u8,
+            source_size: usize,
+            image_handle: &mut Handle,
+        ) -> RawStatus,
+    >,
+    /// Exit data is a null-terminated string followed by optional binary
+    /// data, allocated from the pool
+    start_image:  Option<
+        extern "efiapi" fn(
+            image_handle: Handle,
+            exit_data_size: &mut usize,
+            exit_data: &mut *mut u16,
+        ) -> RawStatus,
+    >,
+    exit:         Option<
+        extern "efiapi" fn(
+            image_handle: Handle,
+            exit_status: RawStatus,
+            exit_data_size: usize,
+            exit_data: *const u16,
+        ) -> RawStatus,
+    >,
+    unload_image: Option<extern "efiapi" fn(image_handle: Handle) -> RawStatus>,
 
     /// Parameters:
     ///
@@ -148,19 +304,108 @@ pub struct BootServices {
     /// ExitBootServices(). A UEFI OS loader should not make calls to any
     /// boot service function other than GetMemoryMap() after the first call
     /// to ExitBootServices().
-    exit_boot_services:
+    pub(crate) exit_boot_services:
         Option<extern "efiapi" fn(ImageHandle, memory::MapKey) -> RawStatus>,
 
-    pub get_next_monotonic_count: usize,
-    pub stall:                    usize,
-    pub set_watchdog_timer:       usize,
-    pub connect_controller:       usize,
-    pub disconnect_controller:    usize,
-    pub open_protocol:            usize,
-    pub close_protocol:           usize,
-    pub open_protocol_info:       usize,
-    pub protocols_per_handle:     usize,
-    pub locate_handle_buffer:     usize,
+    get_next_monotonic_count: Option<extern "efiapi" fn(count: &mut u64) -> RawStatus>,
+    stall:                    Option<extern "efiapi" fn(microseconds: usize) -> RawStatus>,
+
+    /// ## Parameters
+    /// * Timeout - The number of seconds to set the watchdog timer to. A
+    /// value of zero disables the timer.
+    /// * WatchdogCode - The numeric code to log on a watchdog timer timeout
+    /// event. The firmware reserves codes 0x0000 to 0xFFFF.
+    /// * DataSize - The size, in bytes, of WatchdogData.
+    /// * WatchdogData - A data buffer that includes a Null-terminated string,
+    /// optionally followed by additional binary data.
+    set_watchdog_timer: Option<
+        extern "efiapi" fn(
+            timeout: usize,
+            watchdog_code: u64,
+            data_size: usize,
+            watchdog_data: *const u16,
+        ) -> RawStatus,
+    >,
+
+    /// `driver_image_handle` is a null-terminated list of drivers to try
+    /// first, it can be null
+    connect_controller:    Option<
+        extern "efiapi" fn(
+            controller_handle: Handle,
+            driver_image_handle: *const Handle,
+            remaining_device_path: *const (),
+            recursive: bool,
+        ) -> RawStatus,
+    >,
+    /// Null handles mean all drivers and all children
+    disconnect_controller: Option<
+        extern "efiapi" fn(
+            controller_handle: Handle,
+            driver_image_handle: Handle,
+            child_handle: Handle,
+        ) -> RawStatus,
+    >,
+
+    /// ## Parameters
+    /// * Handle - The handle for the protocol interface that is being opened.
+    /// * Protocol - The published unique identifier of the protocol.
+    /// * Interface - Supplies the address where a pointer to the
+    /// corresponding Protocol Interface is returned.
+    /// * AgentHandle - The handle of the agent that is opening the protocol
+    /// interface specified by Protocol and Interface.
+    /// * ControllerHandle - If the agent that is opening a protocol is a
+    /// driver that follows the UEFI Driver Model, then this parameter is the
+    /// controller handle that requires the protocol interface. If the agent
+    /// does not follow the UEFI Driver Model, then this parameter is optional
+    /// and may be NULL.
+    /// * Attributes - The open mode of the protocol interface specified by
+    /// Handle and Protocol.
+    open_protocol:  Option<
+        extern "efiapi" fn(
+            handle: Handle,
+            protocol: &Guid,
+            interface: &mut Option<NonNull<()>>,
+            agent_handle: Handle,
+            controller_handle: Handle,
+            attributes: OpenAttributes,
+        ) -> RawStatus,
+    >,
+    close_protocol: Option<
+        extern "efiapi" fn(
+            handle: Handle,
+            protocol: &Guid,
+            agent_handle: Handle,
+            controller_handle: Handle,
+        ) -> RawStatus,
+    >,
+
+    /// The returned buffer is allocated from the pool
+    open_protocol_information: Option<
+        extern "efiapi" fn(
+            handle: Handle,
+            protocol: &Guid,
+            entry_buffer: &mut *mut OpenProtocolInformationEntry,
+            entry_count: &mut usize,
+        ) -> RawStatus,
+    >,
+    /// The returned buffer is allocated from the pool
+    protocols_per_handle:      Option<
+        extern "efiapi" fn(
+            handle: Handle,
+            protocol_buffer: &mut *mut &'static Guid,
+            protocol_buffer_count: &mut usize,
+        ) -> RawStatus,
+    >,
+    /// Same as LocateHandle(), but the buffer is allocated from the pool
+    locate_handle_buffer:      Option<
+        extern "efiapi" fn(
+            search_type: u32,
+            protocol: Option<&Guid>,
+            search_key: *const (),
+            no_handles: &mut usize,
+            buffer: &mut *mut Handle,
+        ) -> RawStatus,
+    >,
 
     /// Parameters
     /// `protocol` - Provides the protocol to search for.
@@ -188,7 +433,7 @@ pub struct BootServices {
     /// `EFI_INVALID_PARAMETER` - `interface` or `protocol` are NULL.
     /// `EFI_NOT_FOUND` - No protocol instances were found that match `protocol`
     /// and `registration`.
-    locate_protocol: Option<
+    pub(crate) locate_protocol: Option<
         extern "efiapi" fn(
             protocol: &Guid,
             registration: Option<&()>,
@@ -196,21 +441,691 @@ pub struct BootServices {
         ) -> RawStatus,
     >,
 
-    pub install_multiple_protocol_interfaces:   usize,
-    pub uninstall_multiple_protocol_interfaces: usize,
+    // Variadic, which can't be expressed with "efiapi"
+    install_multiple_protocol_interfaces:   usize,
+    uninstall_multiple_protocol_interfaces: usize,
+
+    calculate_crc32: Option<extern "efiapi" fn(data: *const u8, data_size: usize, crc32: &mut u32) -> RawStatus>,
+
+    copy_mem:        Option<extern "efiapi" fn(destination: *mut u8, source: *const u8, length: usize)>,
+    set_mem:         Option<extern "efiapi" fn(buffer: *mut u8, size: usize, value: u8)>,
+
+    /// Like CreateEvent(), but the event can be put in an event group
+    create_event_ex: Option<
+        extern "efiapi" fn(
+            event_type: EventType,
+            notify_tpl: Tpl,
+            notify_function: Option<EventNotify>,
+            notify_context: *mut (),
+            event_group: Option<&Guid>,
+            event: &mut Option<Event>,
+        ) -> RawStatus,
+    >,
+}
+
+/// Where `allocate_pages` may put the pages
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AllocateType {
+    AnyPages,
+
+    /// Anywhere below the address, inclusive
+    MaxAddress(u64),
+
+    /// Exactly at the address
+    Address(u64),
+}
+
+impl AllocateType {
+    /// EFI_ALLOCATE_TYPE and the address that goes with it
+    const fn to_raw(self) -> (u32, u64) {
+        match self {
+            Self::AnyPages => (0, 0),
+            Self::MaxAddress(address) => (1, address),
+            Self::Address(address) => (2, address),
+        }
+    }
+}
+
+/// EFI_LOCATE_SEARCH_TYPE
+#[derive(Clone, Copy, Debug)]
+pub enum LocateSearch<'a> {
+    AllHandles,
+    ByProtocol(&'a Guid),
+}
+
+impl<'a> LocateSearch<'a> {
+    const fn to_raw(self) -> (u32, Option<&'a Guid>) {
+        const ALL_HANDLES: u32 = 0;
+        const BY_PROTOCOL: u32 = 2;
+
+        match self {
+            Self::AllHandles => (ALL_HANDLES, None),
+            Self::ByProtocol(guid) => (BY_PROTOCOL, Some(guid)),
+        }
+    }
+}
+
+#[repr(transparent)]
+pub struct OpenAttributes(u32);
+
+impl OpenAttributes {
+    pub const fn new() -> Self { Self(0) }
+}
+
+impl_bits! {
+    OpenAttributes = {
+        by_handle_protocol = 0,
+        get_protocol = 1,
+        test_protocol = 2,
+        by_child_controller = 3,
+        by_driver = 4,
+        exclusive = 5,
+    }
+}
+
+/// EFI_OPEN_PROTOCOL_INFORMATION_ENTRY
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct OpenProtocolInformationEntry {
+    pub agent_handle:      Handle,
+    pub controller_handle: Handle,
+    pub attributes:        u32,
+    pub open_count:        u32,
+}
+
+/// What `get_memory_map` returns besides the descriptors
+pub(crate) struct RawMemoryMap {
+    pub(crate) key:                memory::MapKey,
+
+    /// Of the whole map, in bytes
+    pub(crate) size:               usize,
+    pub(crate) descriptor_size:    usize,
+    pub(crate) descriptor_version: u32,
+}
+
+/// Pages from `BootServices::allocate_pages`, freed on drop
+pub struct Pages<'a> {
+    boot_services: &'a BootServices,
+    ptr:           NonNull<u8>,
+    count:         usize,
+}
+
+impl Pages<'_> {
+    pub fn address(&self) -> u64 {
+        self.ptr.as_ptr() as u64
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Keeps the pages allocated, they will still be there after
+    /// ExitBootServices
+    pub fn leak(self) -> &'static mut [u8] {
+        let pages = core::mem::ManuallyDrop::new(self);
+        // SAFETY: nobody is going to free them
+        return unsafe { core::slice::from_raw_parts_mut(pages.ptr.as_ptr(), pages.count * 4096) };
+    }
+}
+
+impl Deref for Pages<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the pages are ours and were zeroed by allocate_pages
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.count * 4096) }
+    }
+}
+
+impl DerefMut for Pages<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as above
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.count * 4096) }
+    }
+}
+
+impl Drop for Pages<'_> {
+    fn drop(&mut self) {
+        // SAFETY: the pages came from allocate_pages and are not borrowed
+        // anymore
+        let result = unsafe { self.boot_services.free_pages(self.address(), self.count) };
+        result.expect("could not free pages");
+    }
+}
+
+/// Array allocated from the pool, freed on drop. Either ours, from
+/// `BootServices::allocate_pool`, or returned by the firmware.
+pub struct PoolBox<'a, T> {
+    boot_services: &'a BootServices,
+    ptr:           NonNull<T>,
+    len:           usize,
+}
+
+impl<'a, T> PoolBox<'a, T> {
+    /// ## Safety
+    /// `ptr` has to come from AllocatePool() and point to `len` initialized
+    /// elements. A null `ptr` is only allowed with zero `len`.
+    pub(crate) unsafe fn from_raw(boot_services: &'a BootServices, ptr: *mut T, len: usize) -> Self {
+        let ptr = match NonNull::new(ptr) {
+            Some(ptr) => ptr,
+            None => {
+                assert_eq!(len, 0, "got nullptr from the firmware");
+                NonNull::dangling()
+            },
+        };
+
+        return Self { boot_services, ptr, len };
+    }
 
-    pub calculate_crc32: usize,
+    /// Keeps the buffer allocated, it will still be there after
+    /// ExitBootServices
+    pub fn leak(self) -> &'static mut [T] {
+        let pool = core::mem::ManuallyDrop::new(self);
+        // SAFETY: nobody is going to free it
+        return unsafe { core::slice::from_raw_parts_mut(pool.ptr.as_ptr(), pool.len) };
+    }
+}
 
-    pub copy_mem:        usize,
-    pub set_mem:         usize,
-    pub create_event_ex: usize,
+impl<T> Deref for PoolBox<'_, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // SAFETY: the elements are initialized, see `from_raw`
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> DerefMut for PoolBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        // SAFETY: as above
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> Drop for PoolBox<'_, T> {
+    fn drop(&mut self) {
+        if self.ptr == NonNull::dangling() {
+            return;
+        }
+
+        // SAFETY: the buffer came from the pool and is not borrowed anymore
+        let result = unsafe { self.boot_services.free_pool(self.ptr.cast()) };
+        result.expect("could not free pool memory");
+    }
 }
 
 impl BootServices {
+    /// Raises the task priority level, notifications at `tpl` and below are
+    /// blocked until the guard is dropped. `tpl` can't be lower than the
+    /// current level.
+    pub fn raise_tpl(&self, tpl: Tpl) -> TplGuard<'_> {
+        let f = self.raise_tpl.expect("buggy UEFI: BootServices::raise_tpl is null");
+        let old = (f)(tpl);
+        assert!(old <= tpl, "raise_tpl lowered the TPL from {:?} to {:?}", old, tpl);
+        return TplGuard { boot_services: self, old };
+    }
+
+    pub(crate) fn restore_tpl(&self, tpl: Tpl) {
+        let f = self.restore_tpl.expect("buggy UEFI: BootServices::restore_tpl is null");
+        (f)(tpl);
+    }
+
+    /// Creates an event without a notification function, for waiting on
+    /// timers
+    pub fn create_event(&self, event_type: EventType) -> Result<Event, Error> {
+        // SAFETY: there is no notification function to call
+        return unsafe { self.create_event_ex(event_type, Tpl::APPLICATION, None, core::ptr::null_mut(), None) };
+    }
+
+    /// Creates an event, `notify` is called with `context` at `notify_tpl`.
+    /// Events in `group` are signaled together, like
+    /// `Guid::EFI_EVENT_GROUP_EXIT_BOOT_SERVICES`.
+    ///
+    /// ## Safety
+    /// `context` has to be valid for `notify` until the event is closed, and
+    /// `notify` can only do what is allowed at `notify_tpl`
+    pub unsafe fn create_event_ex(
+        &self,
+        event_type: EventType,
+        notify_tpl: Tpl,
+        notify: Option<EventNotify>,
+        context: *mut (),
+        group: Option<&Guid>,
+    ) -> Result<Event, Error> {
+        const ERRORS: &[Error] = &[Error::InvalidParameter, Error::OutOfResources];
+
+        let mut event = None;
+        let result = match group {
+            None => {
+                let f = self.create_event.expect("buggy UEFI: BootServices::create_event is null");
+                (f)(event_type, notify_tpl, notify, context, &mut event)
+            },
+            Some(group) => {
+                let f = self.create_event_ex.expect("buggy UEFI: BootServices::create_event_ex is null");
+                (f)(event_type, notify_tpl, notify, context, Some(group), &mut event)
+            },
+        };
+        result.ok_or_expect_errors(ERRORS)?;
+
+        return Ok(event.expect("got nullptr from create_event"));
+    }
+
+    /// Arms or cancels a timer, the event has to be created with
+    /// `EventType::TIMER`
+    pub fn set_timer(&self, event: Event, delay: TimerDelay) -> Result<(), Error> {
+        let f = self.set_timer.expect("buggy UEFI: BootServices::set_timer is null");
+        let (timer_type, trigger_time) = delay.to_raw();
+        return (f)(event, timer_type, trigger_time).ok_or_expect_errors(&[Error::InvalidParameter]);
+    }
+
+    /// Waits until one of the events is signaled and returns its index. Can
+    /// only be called at `Tpl::APPLICATION`.
+    pub fn wait_for_event(&self, events: &[Event]) -> Result<usize, Error> {
+        let f = self.wait_for_event.expect("buggy UEFI: BootServices::wait_for_event is null");
+        let mut index = 0usize;
+        (f)(events.len(), events.as_ptr(), &mut index)
+            .ok_or_expect_errors(&[Error::InvalidParameter, Error::Unsupported])?;
+
+        assert!(index < events.len(), "wait_for_event returned index {}", index);
+        return Ok(index);
+    }
+
+    /// Whether the event is signaled, which also clears it
+    pub fn check_event(&self, event: Event) -> Result<bool, Error> {
+        let f = self.check_event.expect("buggy UEFI: BootServices::check_event is null");
+        return match (f)(event).ok_or_expect_errors(&[Error::NotReady, Error::InvalidParameter]) {
+            Ok(()) => Ok(true),
+            Err(Error::NotReady) => Ok(false),
+            Err(e) => Err(e),
+        };
+    }
+
+    pub fn signal_event(&self, event: Event) {
+        let f = self.signal_event.expect("buggy UEFI: BootServices::signal_event is null");
+        let _ = (f)(event).ok_or_expect_errors(&[]);
+    }
+
+    /// Closes the event, timers are cancelled
+    pub fn close_event(&self, event: Event) {
+        let f = self.close_event.expect("buggy UEFI: BootServices::close_event is null");
+        let _ = (f)(event).ok_or_expect_errors(&[]);
+    }
+
+    /// Allocates zeroed pages, which are freed when `Pages` is dropped unless
+    /// leaked
+    pub fn allocate_pages(
+        &self,
+        allocate_type: AllocateType,
+        memory_type: memory::Type,
+        count: usize,
+    ) -> Result<Pages<'_>, Error> {
+        const ERRORS: &[Error] = &[Error::OutOfResources, Error::InvalidParameter, Error::NotFound];
+
+        let f = self.allocate_pages.expect("buggy UEFI: BootServices::allocate_pages is null");
+        let (raw_type, mut address) = allocate_type.to_raw();
+        (f)(raw_type, memory_type, count, &mut address).ok_or_expect_errors(ERRORS)?;
+
+        let ptr = NonNull::new(address as *mut u8).expect("got page zero from allocate_pages");
+        // SAFETY: the firmware gave us `count` pages
+        unsafe { ptr.write_bytes(0u8, count * 4096) };
+        return Ok(Pages { boot_services: self, ptr, count });
+    }
+
+    /// ## Safety
+    /// The pages have to come from `allocate_pages` and can't be used
+    /// afterwards
+    pub unsafe fn free_pages(&self, address: u64, count: usize) -> Result<(), Error> {
+        let f = self.free_pages.expect("buggy UEFI: BootServices::free_pages is null");
+        return (f)(address, count).ok_or_expect_errors(&[Error::NotFound, Error::InvalidParameter]);
+    }
+
+    /// Allocates `size` zeroed bytes, aligned to 8
+    pub fn allocate_pool(&self, memory_type: memory::Type, size: usize) -> Result<PoolBox<'_, u8>, Error> {
+        let f = self.allocate_pool.expect("buggy UEFI: BootServices::allocate_pool is null");
+        let mut buffer = core::ptr::null_mut();
+        (f)(memory_type, size, &mut buffer).ok_or_expect_errors(&[Error::OutOfResources, Error::InvalidParameter])?;
+
+        assert!(!buffer.is_null(), "got nullptr from allocate_pool");
+        // SAFETY: the firmware gave us `size` bytes, which we zero
+        return unsafe {
+            buffer.write_bytes(0u8, size);
+            Ok(PoolBox::from_raw(self, buffer, size))
+        };
+    }
+
+    /// ## Safety
+    /// The buffer has to come from the pool and can't be used afterwards
+    pub unsafe fn free_pool(&self, buffer: NonNull<u8>) -> Result<(), Error> {
+        let f = self.free_pool.expect("buggy UEFI: BootServices::free_pool is null");
+        return (f)(buffer.as_ptr()).ok_or_expect_errors(&[Error::InvalidParameter]);
+    }
+
+    /// Installs `interface` on `handle`, or on a new handle if it's `None`.
+    /// Returns the handle.
+    ///
+    /// ## Safety
+    /// `interface` has to be a valid instance of `protocol` for as long as it
+    /// is installed
+    pub unsafe fn install_protocol_interface(
+        &self,
+        handle: Option<Handle>,
+        protocol: &Guid,
+        interface: *mut (),
+    ) -> Result<Handle, Error> {
+        const NATIVE_INTERFACE: u32 = 0;
+
+        let f = self
+            .install_protocol_interface
+            .expect("buggy UEFI: BootServices::install_protocol_interface is null");
+        let mut handle = handle.unwrap_or(Handle(0));
+        (f)(&mut handle, protocol, NATIVE_INTERFACE, interface)
+            .ok_or_expect_errors(&[Error::InvalidParameter, Error::OutOfResources])?;
+        return Ok(handle);
+    }
+
+    /// ## Safety
+    /// Same as `install_protocol_interface`, for `new_interface`
+    pub unsafe fn reinstall_protocol_interface(
+        &self,
+        handle: Handle,
+        protocol: &Guid,
+        old_interface: *mut (),
+        new_interface: *mut (),
+    ) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[Error::NotFound, Error::AccessDenied, Error::InvalidParameter];
+
+        let f = self
+            .reinstall_protocol_interface
+            .expect("buggy UEFI: BootServices::reinstall_protocol_interface is null");
+        return (f)(handle, protocol, old_interface, new_interface).ok_or_expect_errors(ERRORS);
+    }
+
+    /// ## Safety
+    /// Nothing can use `interface` through the handle afterwards
+    pub unsafe fn uninstall_protocol_interface(
+        &self,
+        handle: Handle,
+        protocol: &Guid,
+        interface: *mut (),
+    ) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[Error::NotFound, Error::AccessDenied, Error::InvalidParameter];
+
+        let f = self
+            .uninstall_protocol_interface
+            .expect("buggy UEFI: BootServices::uninstall_protocol_interface is null");
+        return (f)(handle, protocol, interface).ok_or_expect_errors(ERRORS);
+    }
+
+    /// Fills `buf` with matching handles, `BufferTooSmall` if they don't fit
+    pub fn locate_handle<'buf>(
+        &self,
+        search: LocateSearch,
+        buf: &'buf mut [Handle],
+    ) -> Result<&'buf [Handle], Error> {
+        const ERRORS: &[Error] = &[Error::NotFound, Error::BufferTooSmall, Error::InvalidParameter];
+
+        let f = self.locate_handle.expect("buggy UEFI: BootServices::locate_handle is null");
+        let (search_type, protocol) = search.to_raw();
+        let mut size = core::mem::size_of_val(buf);
+        (f)(search_type, protocol, core::ptr::null(), &mut size, buf.as_mut_ptr()).ok_or_expect_errors(ERRORS)?;
+
+        let len = size / core::mem::size_of::<Handle>();
+        return Ok(&buf[..len]);
+    }
+
+    /// Like `locate_handle`, but the firmware allocates the buffer
+    pub fn locate_handle_buffer(&self, search: LocateSearch) -> Result<PoolBox<'_, Handle>, Error> {
+        const ERRORS: &[Error] = &[Error::NotFound, Error::OutOfResources, Error::InvalidParameter];
+
+        let f = self.locate_handle_buffer.expect("buggy UEFI: BootServices::locate_handle_buffer is null");
+        let (search_type, protocol) = search.to_raw();
+        let mut count = 0usize;
+        let mut buffer = core::ptr::null_mut();
+        (f)(search_type, protocol, core::ptr::null(), &mut count, &mut buffer).ok_or_expect_errors(ERRORS)?;
+
+        // SAFETY: the firmware allocated the buffer from the pool
+        return Ok(unsafe { PoolBox::from_raw(self, buffer, count) });
+    }
+
+    /// ## Safety
+    /// `table` has to stay valid, it will be handed to the OS. Null removes
+    /// the table.
+    pub unsafe fn install_configuration_table(&self, guid: &Guid, table: *const ()) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[Error::NotFound, Error::OutOfResources, Error::InvalidParameter];
+
+        let f = self
+            .install_configuration_table
+            .expect("buggy UEFI: BootServices::install_configuration_table is null");
+        return (f)(guid, table).ok_or_expect_errors(ERRORS);
+    }
+
+    /// Loads an image from memory, returns the handle of the new image
+    pub fn load_image(&self, parent: &ImageHandle, image: &[u8]) -> Result<Handle, Error> {
+        const ERRORS: &[Error] = &[
+            Error::NotFound,
+            Error::InvalidParameter,
+            Error::Unsupported,
+            Error::OutOfResources,
+            Error::LoadError,
+            Error::DeviceError,
+            Error::AccessDenied,
+            Error::SecurityViolation,
+        ];
+
+        let f = self.load_image.expect("buggy UEFI: BootServices::load_image is null");
+        let mut handle = Handle(0);
+        (f)(false, parent.as_handle(), core::ptr::null(), image.as_ptr(), image.len(), &mut handle)
+            .ok_or_expect_errors(ERRORS)?;
+        return Ok(handle);
+    }
+
+    /// Runs a loaded image, returns what it passed to Exit(). The exit data
+    /// is thrown away.
+    pub fn start_image(&self, image: Handle) -> RawStatus {
+        let f = self.start_image.expect("buggy UEFI: BootServices::start_image is null");
+        let mut size = 0usize;
+        let mut data = core::ptr::null_mut();
+        let status = (f)(image, &mut size, &mut data);
+
+        if let Some(data) = NonNull::new(data) {
+            // SAFETY: exit data is allocated from the pool and nobody else
+            // knows about it
+            let _ = unsafe { self.free_pool(data.cast()) };
+        }
+
+        return status;
+    }
+
+    pub fn unload_image(&self, image: Handle) -> Result<(), Error> {
+        let f = self.unload_image.expect("buggy UEFI: BootServices::unload_image is null");
+        return (f)(image).ok_or_expect_errors(&[Error::Unsupported, Error::InvalidParameter]);
+    }
+
+    /// Returns control to whoever started the image, only returns on error
+    pub fn exit(&self, image: ImageHandle, status: RawStatus) -> Error {
+        let f = self.exit.expect("buggy UEFI: BootServices::exit is null");
+        let result = (f)(image.as_handle(), status, 0, core::ptr::null());
+        return match result.ok_or_expect_errors(&[Error::InvalidParameter]) {
+            Err(e) => e,
+            Ok(()) => panic!("buggy UEFI: Exit() returned"),
+        };
+    }
+
+    pub fn get_next_monotonic_count(&self) -> Result<u64, Error> {
+        let f = self
+            .get_next_monotonic_count
+            .expect("buggy UEFI: BootServices::get_next_monotonic_count is null");
+        let mut count = 0u64;
+        (f)(&mut count).ok_or_expect_errors(&[Error::DeviceError])?;
+        return Ok(count);
+    }
+
+    /// Busy waits for at least `microseconds`
+    pub fn stall(&self, microseconds: usize) {
+        let f = self.stall.expect("buggy UEFI: BootServices::stall is null");
+        let _ = (f)(microseconds).ok_or_expect_errors(&[]);
+    }
+
+    /// The firmware resets the machine after `timeout` seconds, unless the
+    /// timer is set again. Zero disables it.
+    pub fn set_watchdog_timer(&self, timeout: usize, code: u64) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[Error::InvalidParameter, Error::Unsupported, Error::DeviceError];
+
+        let f = self.set_watchdog_timer.expect("buggy UEFI: BootServices::set_watchdog_timer is null");
+        return (f)(timeout, code, 0, core::ptr::null()).ok_or_expect_errors(ERRORS);
+    }
+
+    /// Connects all drivers that support the controller
+    pub fn connect_controller(&self, controller: Handle, recursive: bool) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[Error::InvalidParameter, Error::NotFound, Error::SecurityViolation];
+
+        let f = self.connect_controller.expect("buggy UEFI: BootServices::connect_controller is null");
+        return (f)(controller, core::ptr::null(), core::ptr::null(), recursive).ok_or_expect_errors(ERRORS);
+    }
+
+    /// `None` means all drivers or all children
+    pub fn disconnect_controller(
+        &self,
+        controller: Handle,
+        driver: Option<Handle>,
+        child: Option<Handle>,
+    ) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[Error::InvalidParameter, Error::OutOfResources, Error::DeviceError];
+
+        let f = self
+            .disconnect_controller
+            .expect("buggy UEFI: BootServices::disconnect_controller is null");
+        let driver = driver.unwrap_or(Handle(0));
+        let child = child.unwrap_or(Handle(0));
+        return (f)(controller, driver, child).ok_or_expect_errors(ERRORS);
+    }
+
+    /// Returns `None` only for `test_protocol`, which doesn't return the
+    /// interface
+    pub fn open_protocol_raw(
+        &self,
+        handle: Handle,
+        protocol: Guid,
+        agent: Handle,
+        controller: Option<Handle>,
+        attributes: OpenAttributes,
+    ) -> Result<Option<NonNull<()>>, Error> {
+        const ERRORS: &[Error] = &[
+            Error::InvalidParameter,
+            Error::Unsupported,
+            Error::AccessDenied,
+            Error::AlreadyStarted,
+        ];
+
+        let f = self.open_protocol.expect("buggy UEFI: BootServices::open_protocol is null");
+        let controller = controller.unwrap_or(Handle(0));
+        let mut ret = None;
+        (f)(handle, &protocol, &mut ret, agent, controller, attributes).ok_or_expect_errors(ERRORS)?;
+        return Ok(ret);
+    }
+
+    /// Opens the protocol on behalf of `agent`, usually our image. It has to
+    /// be closed with `close_protocol`.
+    pub fn open_protocol<P: crate::Protocol>(
+        &mut self,
+        handle: Handle,
+        agent: Handle,
+        controller: Option<Handle>,
+        attributes: OpenAttributes,
+    ) -> Result<&mut P, Error> {
+        let ptr = self.open_protocol_raw(handle, P::GUID, agent, controller, attributes)?;
+        let ptr = ptr.expect("got nullptr from open_protocol");
+        // SAFETY: the firmware returned an instance of `P`
+        return Ok(unsafe { ptr.cast::<P>().as_mut() });
+    }
+
+    pub fn close_protocol(
+        &self,
+        handle: Handle,
+        protocol: Guid,
+        agent: Handle,
+        controller: Option<Handle>,
+    ) -> Result<(), Error> {
+        let f = self.close_protocol.expect("buggy UEFI: BootServices::close_protocol is null");
+        let controller = controller.unwrap_or(Handle(0));
+        return (f)(handle, &protocol, agent, controller)
+            .ok_or_expect_errors(&[Error::InvalidParameter, Error::NotFound]);
+    }
+
+    /// Who has the protocol open and how
+    pub fn open_protocol_information(
+        &self,
+        handle: Handle,
+        protocol: Guid,
+    ) -> Result<PoolBox<'_, OpenProtocolInformationEntry>, Error> {
+        let f = self
+            .open_protocol_information
+            .expect("buggy UEFI: BootServices::open_protocol_information is null");
+        let mut count = 0usize;
+        let mut buffer = core::ptr::null_mut();
+        (f)(handle, &protocol, &mut buffer, &mut count)
+            .ok_or_expect_errors(&[Error::NotFound, Error::OutOfResources])?;
+
+        // SAFETY: the firmware allocated the buffer from the pool
+        return Ok(unsafe { PoolBox::from_raw(self, buffer, count) });
+    }
+
+    /// GUIDs of all protocols installed on the handle
+    pub fn protocols_per_handle(&self, handle: Handle) -> Result<PoolBox<'_, &'static Guid>, Error> {
+        let f = self.protocols_per_handle.expect("buggy UEFI: BootServices::protocols_per_handle is null");
+        let mut count = 0usize;
+        let mut buffer = core::ptr::null_mut();
+        (f)(handle, &mut buffer, &mut count)
+            .ok_or_expect_errors(&[Error::InvalidParameter, Error::OutOfResources])?;
+
+        // SAFETY: the firmware allocated the buffer from the pool
+        return Ok(unsafe { PoolBox::from_raw(self, buffer, count) });
+    }
+
+    pub fn calculate_crc32(&self, data: &[u8]) -> u32 {
+        let f = self.calculate_crc32.expect("buggy UEFI: BootServices::calculate_crc32 is null");
+        let mut crc = 0u32;
+        let result = (f)(data.as_ptr(), data.len(), &mut crc);
+        // Only fails on null pointers or empty data
+        if result.ok_or_expect_errors(&[Error::InvalidParameter]).is_err() {
+            return 0;
+        }
+
+        return crc;
+    }
+
+    /// ## Safety
+    /// Same as `core::ptr::copy`, the buffers may overlap
+    pub unsafe fn copy_mem(&self, destination: *mut u8, source: *const u8, length: usize) {
+        let f = self.copy_mem.expect("buggy UEFI: BootServices::copy_mem is null");
+        (f)(destination, source, length);
+    }
+
+    /// ## Safety
+    /// Same as `core::ptr::write_bytes`
+    pub unsafe fn set_mem(&self, buffer: *mut u8, size: usize, value: u8) {
+        let f = self.set_mem.expect("buggy UEFI: BootServices::set_mem is null");
+        (f)(buffer, size, value);
+    }
+
     pub fn get_memory_map<'buf>(
         &self,
         buf: &'buf mut [u64],
     ) -> Result<(memory::MapKey, memory::DescriptorIterator<'buf>), Error> {
+        let raw = self.get_memory_map_raw(buf)?;
+
+        let init_size = raw.size / core::mem::size_of::<u64>();
+        let init_buffer = &mut buf[..init_size];
+
+        // /* SAFETY: UEFI promised to initialize that piece of memory */
+        // let init_buffer = unsafe { MaybeUninit::slice_assume_init_ref(init_buffer) };
+
+        let iter = memory::DescriptorIterator::new(init_buffer, raw.descriptor_size);
+        return Ok((raw.key, iter));
+    }
+
+    /// Fills `buf` with the memory map, without parsing it
+    pub(crate) fn get_memory_map_raw(&self, buf: &mut [u64]) -> Result<RawMemoryMap, Error> {
         let mut size: usize = core::mem::size_of_val(buf);
         let mut key = memory::MapKey(0xDEAD_BEEF);
         let mut descriptor_size = 0usize;
@@ -227,18 +1142,34 @@ impl BootServices {
             &mut descriptor_version,
         );
 
-        //assert_eq!(descriptor_version, 1);
-
         status.ok_or_expect_errors(&[Error::InvalidParameter, Error::BufferTooSmall])?;
+        return Ok(RawMemoryMap { key, size, descriptor_size, descriptor_version });
+    }
 
-        let init_size = size / core::mem::size_of::<u64>();
-        let init_buffer = &mut buf[..init_size];
+    /// Size of the memory map and of one descriptor, in bytes. The map can
+    /// grow before it is read, allocating the buffer for it is enough.
+    pub fn memory_map_size(&self) -> Result<(usize, usize), Error> {
+        let mut size = 0usize;
+        let mut key = memory::MapKey(0xDEAD_BEEF);
+        let mut descriptor_size = 0usize;
+        let mut descriptor_version = 0u32;
 
-        // /* SAFETY: UEFI promised to initialize that piece of memory */
-        // let init_buffer = unsafe { MaybeUninit::slice_assume_init_ref(init_buffer) };
+        let get_memory_map = self
+            .get_memory_map
+            .expect("buggy UEFI: BootServices::get_memory_map is null");
+        let status = (get_memory_map)(
+            &mut size,
+            core::ptr::null_mut(),
+            &mut key,
+            &mut descriptor_size,
+            &mut descriptor_version,
+        );
 
-        let iter = memory::DescriptorIterator::new(init_buffer, descriptor_size);
-        return Ok((key, iter));
+        return match status.ok_or_expect_errors(&[Error::InvalidParameter, Error::BufferTooSmall]) {
+            Err(Error::BufferTooSmall) => Ok((size, descriptor_size)),
+            Ok(()) => panic!("buggy UEFI: get_memory_map fit in an empty buffer"),
+            Err(e) => Err(e),
+        };
     }
 
     pub unsafe fn exit_boot_services(
@@ -264,6 +1195,29 @@ impl BootServices {
         return Ok(ret);
     }
 
+    pub fn handle_protocol_raw(&self, handle: Handle, protocol: Guid) -> Result<NonNull<()>, Error> {
+        let handle_prot = self
+            .handle_protocol
+            .expect("buggy UEFI: BootServices::handle_protocol is null");
+        let mut ret = None;
+        (handle_prot)(handle, &protocol, &mut ret)
+            .ok_or_expect_errors(&[Error::InvalidParameter, Error::Unsupported])?;
+        let ret = ret.expect("got nullptr from handle_protocol");
+        return Ok(ret);
+    }
+
+    pub fn handle_protocol<P: crate::Protocol>(&self, handle: Handle) -> Result<&P, Error> {
+        self
+            .handle_protocol_raw(handle, P::GUID)
+            .map(|p| unsafe { p.cast::<P>().as_ref() })
+    }
+
+    pub fn handle_protocol_mut<P: crate::Protocol>(&mut self, handle: Handle) -> Result<&mut P, Error> {
+        self
+            .handle_protocol_raw(handle, P::GUID)
+            .map(|p| unsafe { p.cast::<P>().as_mut() })
+    }
+
     pub fn locate_protocol<P: crate::Protocol>(&self) -> Result<&P, Error> {
         self
             .locate_protocol_raw(P::GUID)
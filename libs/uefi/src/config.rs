@@ -0,0 +1,182 @@
+//! Typed lookups of the tables in `SystemTable::config_slice`. Every table
+//! is checked before a reference to it is handed out.
+
+use super::*;
+use cpu::acpi::{OldRsdp, Rsdp};
+
+/// RSDP from `SystemTable::acpi_rsdp`
+#[derive(Clone, Copy)]
+pub enum AcpiRsdp<'a> {
+    /// ACPI 1.0, only has the RSDT
+    V1(&'a OldRsdp),
+    V2(&'a Rsdp),
+}
+
+impl AcpiRsdp<'_> {
+    pub fn address(&self) -> u64 {
+        match self {
+            Self::V1(rsdp) => core::ptr::from_ref(*rsdp) as u64,
+            Self::V2(rsdp) => core::ptr::from_ref(*rsdp) as u64,
+        }
+    }
+}
+
+/// SMBIOS entry point from `SystemTable::smbios_entry`
+#[derive(Clone, Copy)]
+pub enum SmbiosEntry<'a> {
+    V2(&'a smbios::v2::EntryPoint),
+    V3(&'a smbios::v3::EntryPoint),
+}
+
+impl SmbiosEntry<'_> {
+    pub fn address(&self) -> u64 {
+        match self {
+            Self::V2(entry) => core::ptr::from_ref(*entry) as u64,
+            Self::V3(entry) => core::ptr::from_ref(*entry) as u64,
+        }
+    }
+}
+
+/// Whether `len` bytes at `addr` add up to zero
+///
+/// ## Safety
+/// The bytes have to be readable
+unsafe fn checksum_ok(addr: usize, len: usize) -> bool {
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+    return bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0;
+}
+
+/// ## Safety
+/// `addr` has to point to at least `size_of::<T>()` readable bytes, if it's
+/// not null
+unsafe fn table_ref<'a, T>(addr: usize) -> Option<&'a T> {
+    let ptr = addr as *const T;
+    if !ptr.is_aligned() {
+        return None;
+    }
+    return unsafe { ptr.as_ref() };
+}
+
+/// ## Safety
+/// `addr` has to point to an RSDP, which checks its own length
+unsafe fn parse_rsdp<'a>(addr: usize) -> Option<AcpiRsdp<'a>> {
+    let old = unsafe { table_ref::<OldRsdp>(addr)? };
+    let signature = old.signature;
+    if signature != *b"RSD PTR " || !unsafe { checksum_ok(addr, core::mem::size_of::<OldRsdp>()) } {
+        return None;
+    }
+    if old.revision < 2 {
+        return Some(AcpiRsdp::V1(old));
+    }
+
+    let rsdp = unsafe { table_ref::<Rsdp>(addr)? };
+    let length = rsdp.length as usize;
+    if length < core::mem::size_of::<Rsdp>() || !unsafe { checksum_ok(addr, length) } {
+        return None;
+    }
+
+    return Some(AcpiRsdp::V2(rsdp));
+}
+
+/// ## Safety
+/// `addr` has to point to an SMBIOS 3.0 entry point
+unsafe fn parse_smbios3<'a>(addr: usize) -> Option<&'a smbios::v3::EntryPoint> {
+    let entry = unsafe { table_ref::<smbios::v3::EntryPoint>(addr)? };
+    let length = entry.length as usize;
+    if entry.anchor_str != *b"_SM3_" || length < core::mem::size_of::<smbios::v3::EntryPoint>() {
+        return None;
+    }
+
+    return unsafe { checksum_ok(addr, length) }.then_some(entry);
+}
+
+/// ## Safety
+/// `addr` has to point to an SMBIOS 2.x entry point
+unsafe fn parse_smbios2<'a>(addr: usize) -> Option<&'a smbios::v2::EntryPoint> {
+    /// The "_DMI_" part has a checksum of its own
+    const INTERMEDIATE_OFFSET: usize = 0x10;
+    const INTERMEDIATE_SIZE: usize = 0x0F;
+
+    let entry = unsafe { table_ref::<smbios::v2::EntryPoint>(addr)? };
+    let length = entry.length as usize;
+    if entry.anchor_str != *b"_SM_"
+        || entry.entry_point_string != *b"_DMI_"
+        || length < core::mem::size_of::<smbios::v2::EntryPoint>()
+    {
+        return None;
+    }
+
+    let ok = unsafe {
+        checksum_ok(addr, length) && checksum_ok(addr + INTERMEDIATE_OFFSET, INTERMEDIATE_SIZE)
+    };
+    return ok.then_some(entry);
+}
+
+impl SystemTable {
+    /// Address of the first table with this GUID
+    pub fn config_table(&self, guid: Guid) -> Option<usize> {
+        self.config_slice().iter().find(|c| c.guid == guid).map(|c| c.table)
+    }
+
+    /// ACPI 2.0 RSDP if there is a valid one, otherwise the 1.0 one
+    pub fn acpi_rsdp(&self) -> Option<AcpiRsdp<'_>> {
+        // SAFETY: the firmware put RSDPs under these GUIDs
+        let v2 = self.config_table(Guid::EFI_ACPI_20_TABLE).and_then(|addr| unsafe { parse_rsdp(addr) });
+        return v2.or_else(|| self.config_table(Guid::ACPI_TABLE).and_then(|addr| unsafe { parse_rsdp(addr) }));
+    }
+
+    /// SMBIOS 3.0 entry point if there is a valid one, otherwise the 2.x one
+    pub fn smbios_entry(&self) -> Option<SmbiosEntry<'_>> {
+        // SAFETY: the firmware put entry points under these GUIDs
+        let v3 = self.config_table(Guid::SMBIOS3_TABLE).and_then(|addr| unsafe { parse_smbios3(addr) });
+        if let Some(v3) = v3 {
+            return Some(SmbiosEntry::V3(v3));
+        }
+
+        let v2 = self.config_table(Guid::SMBIOS_TABLE).and_then(|addr| unsafe { parse_smbios2(addr) });
+        return v2.map(SmbiosEntry::V2);
+    }
+
+    /// Only the header is checked, see `AttributesTable::entries`
+    pub fn memory_attributes_table(&self) -> Option<&memory::AttributesTable> {
+        let addr = self.config_table(Guid::EFI_MEMORY_ATTRIBUTES_TABLE)?;
+        // SAFETY: the firmware put the table under this GUID
+        let table = unsafe { table_ref::<memory::AttributesTable>(addr)? };
+        if table.version == 0 || (table.descriptor_size as usize) < core::mem::size_of::<memory::Descriptor>() {
+            return None;
+        }
+
+        return Some(table);
+    }
+
+    pub fn rt_properties(&self) -> Option<&RtPropertiesTable> {
+        let addr = self.config_table(Guid::EFI_RT_PROPERTIES_TABLE)?;
+        // SAFETY: the firmware put the table under this GUID
+        let table = unsafe { table_ref::<RtPropertiesTable>(addr)? };
+        if table.version != RtPropertiesTable::VERSION
+            || (table.length as usize) < core::mem::size_of::<RtPropertiesTable>()
+        {
+            return None;
+        }
+
+        return Some(table);
+    }
+
+    /// Flattened device tree blob, as long as its header says
+    pub fn device_tree(&self) -> Option<&[u8]> {
+        const FDT_MAGIC: u32 = 0xD00D_FEED;
+        const FDT_HEADER_SIZE: usize = 40;
+
+        let addr = self.config_table(Guid::DEVICE_TREE)?;
+        // SAFETY: the firmware put the blob under this GUID, the header
+        // fields are big endian
+        let header = unsafe { table_ref::<[u32; 2]>(addr)? };
+        let totalsize = u32::from_be(header[1]) as usize;
+        if u32::from_be(header[0]) != FDT_MAGIC || totalsize < FDT_HEADER_SIZE {
+            return None;
+        }
+
+        // SAFETY: the header says the blob is that long
+        return Some(unsafe { core::slice::from_raw_parts(addr as *const u8, totalsize) });
+    }
+}
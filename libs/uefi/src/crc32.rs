@@ -0,0 +1,50 @@
+//! CRC32 as used by UEFI table headers, the same one as in zlib and
+//! Ethernet. Also works after ExitBootServices, unlike
+//! `BootServices::calculate_crc32`.
+
+/// Reversed 0x04C11DB7
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC32 computed piece by piece
+#[derive(Clone, Copy)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Self(!0)
+    }
+
+    pub const fn update(self, data: &[u8]) -> Self {
+        let mut crc = self.0;
+        let mut i = 0;
+        while i < data.len() {
+            crc = TABLE[((crc ^ data[i] as u32) & 0xFF) as usize] ^ (crc >> 8);
+            i += 1;
+        }
+        Self(crc)
+    }
+
+    pub const fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+pub const fn crc32(data: &[u8]) -> u32 {
+    Crc32::new().update(data).finish()
+}
@@ -0,0 +1,97 @@
+use super::*;
+
+/// EFI_EVENT, an opaque handle owned by the firmware
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(transparent)]
+pub struct Event(NonNull<()>);
+
+/// Called by the firmware at the event's TPL when it is signaled
+pub type EventNotify = extern "efiapi" fn(event: Event, context: *mut ());
+
+/// EFI_TPL, task priority level. Code running at a level blocks notifications
+/// at that level and below.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[repr(transparent)]
+pub struct Tpl(usize);
+
+impl Tpl {
+    pub const APPLICATION: Self = Self(4);
+    pub const CALLBACK: Self = Self(8);
+    pub const NOTIFY: Self = Self(16);
+    pub const HIGH_LEVEL: Self = Self(31);
+}
+
+/// Type of an event, see the associated constants
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(transparent)]
+pub struct EventType(u32);
+
+impl EventType {
+    /// Can be used with `set_timer`
+    pub const TIMER: Self = Self(0x8000_0000);
+
+    /// Allocated from runtime memory, so it can be signaled after
+    /// ExitBootServices
+    pub const RUNTIME: Self = Self(0x4000_0000);
+
+    /// Notification is queued whenever the event is waited on or checked
+    pub const NOTIFY_WAIT: Self = Self(0x0000_0100);
+
+    /// Notification is queued when the event is signaled
+    pub const NOTIFY_SIGNAL: Self = Self(0x0000_0200);
+
+    /// Use `EFI_EVENT_GROUP_EXIT_BOOT_SERVICES` with `create_event_ex` instead
+    pub const SIGNAL_EXIT_BOOT_SERVICES: Self = Self(0x0000_0201);
+    pub const SIGNAL_VIRTUAL_ADDRESS_CHANGE: Self = Self(0x6000_0202);
+
+    pub const fn new() -> Self { Self(0) }
+
+    pub const fn with(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// EFI_TIMER_DELAY, times are in 100ns units
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TimerDelay {
+    Cancel,
+
+    /// Signaled every time the period passes, zero means every timer tick
+    Periodic(u64),
+
+    /// Signaled once, zero means on the next timer tick
+    Relative(u64),
+}
+
+impl TimerDelay {
+    pub const MILLISECOND: u64 = 10_000;
+    pub const SECOND: u64 = 1000 * Self::MILLISECOND;
+
+    pub(crate) const fn to_raw(self) -> (u32, u64) {
+        match self {
+            Self::Cancel => (0, 0),
+            Self::Periodic(time) => (1, time),
+            Self::Relative(time) => (2, time),
+        }
+    }
+}
+
+/// Restores the previous task priority level on drop
+#[must_use]
+pub struct TplGuard<'a> {
+    pub(crate) boot_services: &'a BootServices,
+    pub(crate) old:           Tpl,
+}
+
+impl TplGuard<'_> {
+    /// Level that will be restored
+    pub fn old(&self) -> Tpl {
+        self.old
+    }
+}
+
+impl Drop for TplGuard<'_> {
+    fn drop(&mut self) {
+        self.boot_services.restore_tpl(self.old);
+    }
+}
@@ -17,6 +17,28 @@ impl core::fmt::Debug for Guid {
     }
 }
 
+/// The usual text form, `8868E871-E4F1-11D3-BC22-0080C73C8881`
+impl core::fmt::Display for Guid {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let d = &self.3;
+        return write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+            self.0, self.1, self.2, d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7],
+        );
+    }
+}
+
+impl Guid {
+    /// From the in-memory representation, as found in e.g. GPT headers
+    pub const fn from_bytes(b: [u8; 16]) -> Self {
+        let a = u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        let b1 = u16::from_le_bytes([b[4], b[5]]);
+        let c = u16::from_le_bytes([b[6], b[7]]);
+        Self(a, b1, c, [b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15]])
+    }
+}
+
 macro_rules! impl_guids {
     {
         $($name:ident = { $a:literal, $b:literal, $c:literal, { $($d:literal),+ }},)*
@@ -64,6 +86,10 @@ impl_guids! {
     EFI_DEBUG_IMAGE_INFO_TABLE =
         {0x49152E77,0x1ADA,0x4764, {0xB7,0xA2,0x7A,0xFE,0xFE,0xD9,0x5E,0x8B}},
 
+    EFI_SIMPLE_TEXT_INPUT_PROTOCOL =
+        {0x387477c1,0x69c7,0x11d2, {0x8e,0x39,0x00,0xa0,0xc9,0x69,0x72,0x3b}},
+    EFI_SIMPLE_TEXT_INPUT_EX_PROTOCOL =
+        {0xdd9e7534,0x7762,0x4698, {0x8c,0x14,0xf5,0x85,0x17,0xa6,0x25,0xaa}},
     EFI_SIMPLE_TEXT_OUTPUT_PROTOCOL =
         {0x387477c2,0x69c7,0x11d2, {0x8e,0x39,0x00,0xa0,0xc9,0x69,0x72,0x3b}},
     EFI_GRAPHICS_OUTPUT_PROTOCOL =
@@ -71,6 +97,15 @@ impl_guids! {
     EFI_SERIAL_IO_PROTOCOL =
         {0xBB25CF6F,0xF1D4,0x11D2, {0x9a,0x0c,0x00,0x90,0x27,0x3f,0xc1,0xfd}},
 
+    EFI_EVENT_GROUP_EXIT_BOOT_SERVICES =
+        {0x27abf055,0xb1b8,0x4c26, {0x80,0x48,0x74,0x8f,0x37,0xba,0xa2,0xdf}},
+    EFI_EVENT_GROUP_VIRTUAL_ADDRESS_CHANGE =
+        {0x13fa7698,0xc831,0x49c7, {0x87,0xea,0x8f,0x43,0xfc,0xc2,0x51,0x96}},
+    EFI_EVENT_GROUP_MEMORY_MAP_CHANGE =
+        {0x78bee926,0x692f,0x48fd, {0x9e,0xdb,0x01,0x42,0x2e,0xf0,0xd7,0xab}},
+    EFI_EVENT_GROUP_READY_TO_BOOT =
+        {0x7ce88fb3,0x4bd7,0x4679, {0x87,0xa8,0xa8,0xd8,0xde,0xe5,0x0d,0x2b}},
+
 
     // From https://github.com/torvalds/linux/blob/master/include/linux/efi.h
     NULL                                = {0x00000000, 0x0000, 0x0000, {0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00}},
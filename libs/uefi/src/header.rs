@@ -26,6 +26,7 @@ pub enum VerifyError {
     Signature,
     Revision,
     TableSize,
+    Crc32,
 }
 
 pub trait Verify: Sized {
@@ -43,8 +44,36 @@ pub trait Verify: Sized {
     fn verify_size(&self) -> bool {
         self.get_header().header_size as usize >= core::mem::size_of::<Self>()
     }
+    /// ## Safety
+    /// As in `compute_crc32`, `verify_size` doesn't catch a `header_size`
+    /// that is too big
+    unsafe fn verify_crc32(&self) -> bool {
+        // SAFETY: the caller promised that
+        return unsafe { self.compute_crc32() } == self.get_header().crc32;
+    }
+
+    /// CRC32 of the `header_size` bytes of the table, with `crc32` as 0.
+    /// The header has to be the first field of the table.
+    ///
+    /// ## Safety
+    /// The table has to be at least `header_size` bytes long
+    unsafe fn compute_crc32(&self) -> u32 {
+        const CRC_OFFSET: usize = core::mem::offset_of!(TableHeader, crc32);
+        const CRC_END: usize = CRC_OFFSET + core::mem::size_of::<u32>();
 
-    fn verify(&self) -> Result<(), VerifyError> {
+        let size = (self.get_header().header_size as usize).max(CRC_END);
+        // SAFETY: the caller promised that
+        let table = unsafe { core::slice::from_raw_parts(core::ptr::from_ref(self).cast::<u8>(), size) };
+        return crate::Crc32::new()
+            .update(&table[..CRC_OFFSET])
+            .update(&[0; 4])
+            .update(&table[CRC_END..])
+            .finish();
+    }
+
+    /// ## Safety
+    /// As in `compute_crc32`, which holds for tables the firmware hands out
+    unsafe fn verify(&self) -> Result<(), VerifyError> {
         if !self.verify_signature() {
             return Err(VerifyError::Signature);
         }
@@ -54,6 +83,10 @@ pub trait Verify: Sized {
         if !self.verify_revision() {
             return Err(VerifyError::Revision);
         }
+        // SAFETY: the caller promised that
+        if !unsafe { self.verify_crc32() } {
+            return Err(VerifyError::Crc32);
+        }
 
         return Ok(());
     }
@@ -6,8 +6,13 @@ use core::num::NonZeroUsize;
 
 pub mod memory;
 pub mod protocols;
+#[cfg(feature = "mock")]
+pub mod mock;
 
 mod boot_services;
+mod config;
+mod crc32;
+mod event;
 mod guid;
 mod header;
 mod runtime_services;
@@ -15,6 +20,9 @@ mod status;
 mod system_table;
 
 pub use boot_services::*;
+pub use config::*;
+pub use crc32::*;
+pub use event::*;
 pub use guid::*;
 pub use header::*;
 pub use runtime_services::*;
@@ -32,6 +40,13 @@ pub type EfiImageEntryPointFunc =
 #[repr(transparent)]
 pub struct ImageHandle(Handle);
 
+impl ImageHandle {
+    pub fn as_handle(&self) -> Handle {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[repr(transparent)]
 pub struct Handle(usize);
 
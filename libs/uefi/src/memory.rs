@@ -1,5 +1,8 @@
 use impl_bits::impl_bits;
 
+/// EFI_MEMORY_DESCRIPTOR_VERSION, of `Descriptor`
+pub const DESCRIPTOR_VERSION: u32 = 1;
+
 #[repr(transparent)]
 pub struct MapKey(pub(crate) u64);
 
@@ -57,6 +60,10 @@ impl core::fmt::Debug for Descriptor {
 }
 
 impl Descriptor {
+    pub const fn new(typ: Type, phys_start: u64, pages: u64, attributes: Attributes) -> Self {
+        Self { typ: typ as u32, _padding: 0, phys_start, virt_start: 0, pages, attributes }
+    }
+
     pub fn memory_type(&self) -> Option<Type> {
         Type::from_int(self.typ)
     }
@@ -121,6 +128,7 @@ impl Type {
     }
 }
 
+#[derive(Clone)]
 pub struct DescriptorIterator<'buf> {
     buf:             &'buf [u64],
     descriptor_size: usize,
@@ -159,6 +167,130 @@ impl<'buf> Iterator for DescriptorIterator<'buf> {
     }
 }
 
+/// EFI_MEMORY_ATTRIBUTES_TABLE, descriptors follow the header. It splits
+/// the runtime images into code, with `readonly` set, and data, with
+/// `exec_protect` set.
+#[derive(Debug)]
+#[repr(C)]
+pub struct AttributesTable {
+    pub version:           u32,
+    pub number_of_entries: u32,
+    pub descriptor_size:   u32,
+
+    /// Reserved in version 1
+    pub flags:             u32,
+}
+
+impl AttributesTable {
+    pub const VERSION_1: u32 = 1;
+
+    /// Adds `flags`
+    pub const VERSION_2: u32 = 2;
+
+    /// Runtime code was built with forward control flow guards, like IBT
+    pub fn forward_cfg(&self) -> bool {
+        self.version >= Self::VERSION_2 && self.flags & 1 != 0
+    }
+
+    /// Entries of the table, sorted by `phys_start` if the firmware follows
+    /// the spec. Unknown versions are rejected, they could change the
+    /// meaning of the entries.
+    pub fn entries(&self) -> Result<DescriptorIterator<'_>, MapError> {
+        if !(Self::VERSION_1..=Self::VERSION_2).contains(&self.version) {
+            return Err(MapError::UnsupportedVersion(self.version));
+        }
+
+        let descriptor_size = self.descriptor_size as usize;
+        let header = core::ptr::from_ref(self);
+        if descriptor_size < core::mem::size_of::<Descriptor>()
+            || !descriptor_size.is_multiple_of(8)
+            || !header.cast::<u64>().is_aligned()
+        {
+            return Err(MapError::BadDescriptor(header as u64));
+        }
+
+        let len = self.number_of_entries as usize * descriptor_size / 8;
+        // SAFETY: the firmware puts the entries right after the header
+        let buf = unsafe { core::slice::from_raw_parts(header.add(1).cast::<u64>(), len) };
+        return Ok(DescriptorIterator::new(buf, descriptor_size));
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MapError {
+    Firmware(crate::Error),
+    UnsupportedVersion(u32),
+
+    /// Descriptor at this address is empty, unaligned or past the end of the
+    /// address space
+    BadDescriptor(u64),
+
+    /// Descriptor at this address overlaps the one before it
+    Overlap(u64),
+}
+
+impl From<crate::Error> for MapError {
+    fn from(e: crate::Error) -> Self {
+        Self::Firmware(e)
+    }
+}
+
+/// Memory map with descriptors of `size_of::<Descriptor>()`, sorted by
+/// `phys_start` and checked for overlaps
+pub struct MemoryMap<'buf> {
+    descriptors: &'buf [Descriptor],
+}
+
+impl<'buf> MemoryMap<'buf> {
+    /// Packs descriptors of `descriptor_size` bytes from `buf` in place,
+    /// sorts and checks them
+    pub fn new(buf: &'buf mut [u64], descriptor_size: usize) -> Result<Self, MapError> {
+        const WORDS: usize = core::mem::size_of::<Descriptor>() / 8;
+
+        assert!(descriptor_size >= core::mem::size_of::<Descriptor>());
+        assert_eq!(descriptor_size % 8, 0);
+        let stride = descriptor_size / 8;
+        let count = buf.len() / stride;
+
+        // Moves only go backwards, so nothing gets overwritten before it's
+        // copied
+        for i in 0..count {
+            buf.copy_within(i * stride..i * stride + WORDS, i * WORDS);
+        }
+
+        // SAFETY: `Descriptor` is made of integers, so any bits are fine, and
+        // has the alignment of u64
+        let descriptors = unsafe {
+            core::slice::from_raw_parts_mut(buf.as_mut_ptr().cast::<Descriptor>(), count)
+        };
+        descriptors.sort_unstable_by_key(|d| d.phys_start);
+
+        let mut last_end = 0u64;
+        for d in descriptors.iter() {
+            let end = d.pages.checked_mul(4096).and_then(|size| d.phys_start.checked_add(size));
+            let end = match end {
+                Some(end) if d.pages != 0 && d.phys_start % 4096 == 0 => end,
+                _ => return Err(MapError::BadDescriptor(d.phys_start)),
+            };
+
+            if d.phys_start < last_end {
+                return Err(MapError::Overlap(d.phys_start));
+            }
+            last_end = end;
+        }
+
+        return Ok(Self { descriptors });
+    }
+}
+
+impl core::ops::Deref for MemoryMap<'_> {
+    type Target = [Descriptor];
+
+    fn deref(&self) -> &[Descriptor] {
+        self.descriptors
+    }
+}
+
 #[repr(transparent)]
 #[derive(PartialEq, Eq)]
 pub struct Attributes(u64);
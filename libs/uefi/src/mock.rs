@@ -0,0 +1,506 @@
+//! Fake firmware for tests on the host, behind the `mock` feature.
+//!
+//! `MockFirmware` owns a real `SystemTable`, `BootServices`, GOP and text
+//! output, with shims in place of the function pointers. The shims find the
+//! firmware's state through a thread local, so there can be only one
+//! `MockFirmware` per thread, and the closures given to it can't call back
+//! into the firmware. Boot services other than GetMemoryMap() panic after
+//! ExitBootServices(), just like they would break on real hardware.
+
+extern crate std;
+
+use crate::memory::{self, Descriptor, MapKey};
+use crate::protocols::gop::{self, GraphicsOutput, ModeInformation, PixelBitmask};
+use crate::protocols::simple_text::{Attribute, Output, OutputMode};
+use crate::*;
+use std::alloc::Layout;
+use std::boxed::Box;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::string::String;
+use std::vec::Vec;
+
+type MemoryMapHook = Box<dyn FnMut() -> Result<(Vec<Descriptor>, u64), Error>>;
+type ExitHook = Box<dyn FnMut(u64) -> Result<(), Error>>;
+type AllocateHook = Box<dyn FnMut(memory::Type, usize) -> Result<(), Error>>;
+type SetModeHook = Box<dyn FnMut(u32) -> Result<(), Error>>;
+
+struct State {
+    memory_map:         MemoryMapHook,
+    exit_boot_services: Option<ExitHook>,
+    allocate_pages:     AllocateHook,
+    set_mode:           SetModeHook,
+    descriptor_size:    usize,
+    descriptor_version: u32,
+
+    /// Key of the last map handed out
+    last_key: Option<u64>,
+    exited:   bool,
+
+    /// Pages and pool buffers by address
+    allocations: HashMap<usize, Layout>,
+
+    /// Boxed, because `gop::Mode::info` points to them
+    #[allow(clippy::vec_box)]
+    modes:       Vec<Box<ModeInformation>>,
+    framebuffer: Vec<u32>,
+    gop:         *mut GraphicsOutput,
+    console:     String,
+}
+
+std::thread_local! {
+    static STATE: RefCell<Option<State>> = const { RefCell::new(None) };
+}
+
+fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        f(state.as_mut().expect("no MockFirmware on this thread"))
+    })
+}
+
+/// Like `with_state`, for boot services that can't be called after
+/// ExitBootServices()
+fn with_boot_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    with_state(|s| {
+        assert!(!s.exited, "boot service called after ExitBootServices");
+        f(s)
+    })
+}
+
+fn status(result: Result<(), Error>) -> RawStatus {
+    match result {
+        Ok(()) => RawStatus::ok(),
+        Err(e) => RawStatus::from_error(e),
+    }
+}
+
+/// A small machine: low memory, 127 MiB at 1 MiB and some runtime data
+fn default_memory_map() -> Vec<Descriptor> {
+    let wb = memory::Attributes::new().set_write_back();
+    return std::vec![
+        Descriptor::new(memory::Type::Conventional, 0x1000, 0x9F, wb),
+        Descriptor::new(memory::Type::Conventional, 0x10_0000, 0x7F00, wb),
+        Descriptor::new(memory::Type::RuntimeServicesData, 0x800_0000, 0x10, wb.set_runtime()),
+    ];
+}
+
+pub struct MockFirmware {
+    system_table:  Box<SystemTable>,
+    boot_services: Box<BootServices>,
+    gop:           Box<GraphicsOutput>,
+    gop_mode:      Box<gop::Mode>,
+    con_out:       Box<Output>,
+    con_out_mode:  Box<OutputMode>,
+}
+
+impl MockFirmware {
+    /// Firmware with `default_memory_map`, a 48 byte descriptor size like
+    /// OVMF's and a GOP without any modes
+    pub fn new() -> Self {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            assert!(state.is_none(), "there already is a MockFirmware on this thread");
+            *state = Some(State {
+                memory_map:         Box::new(|| Ok((default_memory_map(), 1))),
+                exit_boot_services: None,
+                allocate_pages:     Box::new(|_, _| Ok(())),
+                set_mode:           Box::new(|_| Ok(())),
+                descriptor_size:    48,
+                descriptor_version: memory::DESCRIPTOR_VERSION,
+                last_key:           None,
+                exited:             false,
+                allocations:        HashMap::new(),
+                modes:              Vec::new(),
+                framebuffer:        Vec::new(),
+                gop:                core::ptr::null_mut(),
+                console:            String::new(),
+            });
+        });
+
+        // SAFETY: all of them are plain integers, raw pointers and options of
+        // function pointers, which can be zero
+        let mut fw = unsafe {
+            Self {
+                system_table:  Box::new(core::mem::zeroed()),
+                boot_services: Box::new(core::mem::zeroed()),
+                gop:           Box::new(core::mem::zeroed()),
+                gop_mode:      Box::new(core::mem::zeroed()),
+                con_out:       Box::new(core::mem::zeroed()),
+                con_out_mode:  Box::new(core::mem::zeroed()),
+            }
+        };
+
+        let bs = &mut *fw.boot_services;
+        bs.allocate_pages = Some(allocate_pages);
+        bs.free_pages = Some(free_pages);
+        bs.get_memory_map = Some(get_memory_map);
+        bs.allocate_pool = Some(allocate_pool);
+        bs.free_pool = Some(free_pool);
+        bs.handle_protocol = Some(handle_protocol);
+        bs.exit_boot_services = Some(exit_boot_services);
+        bs.locate_protocol = Some(locate_protocol);
+        bs.header.signature = BootServices::SIGNATURE;
+        bs.header.revision = BootServices::REVISION;
+        bs.header.header_size = core::mem::size_of::<BootServices>() as u32;
+        // SAFETY: `header_size` is the size of the table
+        bs.header.crc32 = unsafe { bs.compute_crc32() };
+
+        fw.gop.query_mode = Some(query_mode);
+        fw.gop.set_mode = Some(set_mode);
+        fw.gop._mode = core::ptr::from_mut(&mut *fw.gop_mode);
+        let gop = core::ptr::from_mut(&mut *fw.gop);
+        with_state(|s| s.gop = gop);
+
+        let out = &mut *fw.con_out;
+        out.reset = Some(reset);
+        out.output_string = Some(output_string);
+        out.test_string = Some(test_string);
+        out.set_attribute = Some(set_attribute);
+        out.clear_screen = Some(clear_screen);
+        out.set_cursor_position = Some(set_cursor_position);
+        out.enable_cursor = Some(enable_cursor);
+        out.mode = core::ptr::from_ref(&*fw.con_out_mode);
+
+        const VENDOR: &[u16] = &[b'M' as u16, b'o' as u16, b'c' as u16, b'k' as u16, 0];
+        let st = &mut *fw.system_table;
+        st.firmware_vendor = VENDOR.as_ptr();
+        st.con_out = Some(NonNull::from(&mut *fw.con_out));
+        st.con_err = Some(NonNull::from(&mut *fw.con_out));
+        st._boot_services = Some(NonNull::from(&mut *fw.boot_services));
+        st.config_table = NonNull::dangling().as_ptr();
+        st.header.signature = SystemTable::SIGNATURE;
+        st.header.revision = SystemTable::REVISION;
+        st.header.header_size = core::mem::size_of::<SystemTable>() as u32;
+        // SAFETY: as above
+        st.header.crc32 = unsafe { st.compute_crc32() };
+
+        return fw;
+    }
+
+    pub fn system_table(&mut self) -> &mut SystemTable {
+        &mut self.system_table
+    }
+
+    pub fn image_handle(&self) -> ImageHandle {
+        ImageHandle(Handle(1))
+    }
+
+    /// Called on every GetMemoryMap(), also when the caller only asks for
+    /// the size. Returns the map and its key.
+    pub fn on_memory_map(&mut self, f: impl FnMut() -> Result<(Vec<Descriptor>, u64), Error> + 'static) {
+        with_state(|s| s.memory_map = Box::new(f));
+    }
+
+    /// Decides whether ExitBootServices() with the given key succeeds. By
+    /// default only the key of the last map does.
+    pub fn on_exit_boot_services(&mut self, f: impl FnMut(u64) -> Result<(), Error> + 'static) {
+        with_state(|s| s.exit_boot_services = Some(Box::new(f)));
+    }
+
+    /// Called with the memory type and the page count before AllocatePages()
+    /// allocates anything, an error fails the allocation
+    pub fn on_allocate_pages(&mut self, f: impl FnMut(memory::Type, usize) -> Result<(), Error> + 'static) {
+        with_state(|s| s.allocate_pages = Box::new(f));
+    }
+
+    /// Called before a valid mode is set, an error fails SetMode()
+    pub fn on_set_mode(&mut self, f: impl FnMut(u32) -> Result<(), Error> + 'static) {
+        with_state(|s| s.set_mode = Box::new(f));
+    }
+
+    pub fn set_descriptor_format(&mut self, size: usize, version: u32) {
+        with_state(|s| {
+            s.descriptor_size = size;
+            s.descriptor_version = version;
+        });
+    }
+
+    /// Adds a GOP mode, the first one becomes the current one
+    pub fn add_gop_mode(&mut self, width: u32, height: u32, format: gop::PixelFormat) {
+        let info = ModeInformation {
+            version:             0,
+            horizontal_res:      width,
+            vertical_res:        height,
+            pixel_format:        format as u32,
+            pixel_info:          PixelBitmask { red: 0, green: 0, blue: 0, reserved: 0 },
+            pixels_per_scanline: width,
+        };
+
+        let first = with_state(|s| {
+            s.modes.push(Box::new(info));
+            s.modes.len() == 1
+        });
+        self.gop_mode.max_mode += 1;
+        if first {
+            with_state(|s| switch_mode(s, 0));
+        }
+    }
+
+    /// Everything written to the text output
+    pub fn console(&self) -> String {
+        with_state(|s| s.console.clone())
+    }
+
+    /// Pixels of the current GOP mode
+    pub fn framebuffer(&self) -> Vec<u32> {
+        with_state(|s| s.framebuffer.clone())
+    }
+
+    /// Pages and pool buffers that were not freed
+    pub fn allocations(&self) -> usize {
+        with_state(|s| s.allocations.len())
+    }
+
+    pub fn exited_boot_services(&self) -> bool {
+        with_state(|s| s.exited)
+    }
+}
+
+impl Drop for MockFirmware {
+    fn drop(&mut self) {
+        let state = STATE.with(|state| state.borrow_mut().take());
+        let Some(state) = state else {
+            return;
+        };
+
+        for (address, layout) in state.allocations {
+            // SAFETY: allocated by the shims with this layout
+            unsafe { std::alloc::dealloc(address as *mut u8, layout) };
+        }
+    }
+}
+
+fn allocate(s: &mut State, size: usize, align: usize) -> Result<*mut u8, Error> {
+    let layout = Layout::from_size_align(size.max(1), align).map_err(|_| Error::InvalidParameter)?;
+    // SAFETY: the size is not zero
+    let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+    if ptr.is_null() {
+        return Err(Error::OutOfResources);
+    }
+
+    s.allocations.insert(ptr as usize, layout);
+    return Ok(ptr);
+}
+
+fn deallocate(s: &mut State, address: usize, size: Option<usize>) -> Result<(), Error> {
+    let layout = *s.allocations.get(&address).ok_or(Error::NotFound)?;
+    if size.is_some_and(|size| size != layout.size()) {
+        return Err(Error::InvalidParameter);
+    }
+
+    s.allocations.remove(&address);
+    // SAFETY: allocated by `allocate` with this layout
+    unsafe { std::alloc::dealloc(address as *mut u8, layout) };
+    return Ok(());
+}
+
+extern "efiapi" fn allocate_pages(
+    allocate_type: u32,
+    memory_type: memory::Type,
+    pages: usize,
+    memory: &mut u64,
+) -> RawStatus {
+    const ANY_PAGES: u32 = 0;
+
+    with_boot_state(|s| {
+        (s.allocate_pages)(memory_type, pages)?;
+        if pages == 0 {
+            return Err(Error::InvalidParameter);
+        }
+        // The host decides where memory goes
+        if allocate_type != ANY_PAGES {
+            return Err(Error::NotFound);
+        }
+
+        *memory = allocate(s, pages * 4096, 4096)? as u64;
+        return Ok(());
+    })
+    .map_or_else(RawStatus::from_error, |()| RawStatus::ok())
+}
+
+extern "efiapi" fn free_pages(memory: u64, pages: usize) -> RawStatus {
+    with_boot_state(|s| status(deallocate(s, memory as usize, Some(pages * 4096))))
+}
+
+extern "efiapi" fn allocate_pool(_pool_type: memory::Type, size: usize, buffer: &mut *mut u8) -> RawStatus {
+    with_boot_state(|s| {
+        let result = allocate(s, size, 8).map(|ptr| *buffer = ptr);
+        status(result)
+    })
+}
+
+extern "efiapi" fn free_pool(buffer: *mut u8) -> RawStatus {
+    with_boot_state(|s| {
+        let result = deallocate(s, buffer as usize, None).map_err(|_| Error::InvalidParameter);
+        status(result)
+    })
+}
+
+extern "efiapi" fn get_memory_map(
+    size: &mut usize,
+    buffer: *mut Descriptor,
+    key: &mut MapKey,
+    descriptor_size: &mut usize,
+    descriptor_version: &mut u32,
+) -> RawStatus {
+    with_state(|s| {
+        let (map, map_key) = match (s.memory_map)() {
+            Ok(map) => map,
+            Err(e) => return RawStatus::from_error(e),
+        };
+
+        *descriptor_size = s.descriptor_size;
+        *descriptor_version = s.descriptor_version;
+        let needed = map.len() * s.descriptor_size;
+        if *size < needed || buffer.is_null() {
+            *size = needed;
+            return RawStatus::from_error(Error::BufferTooSmall);
+        }
+
+        let out = buffer.cast::<u8>();
+        for (i, descriptor) in map.iter().enumerate() {
+            // SAFETY: the caller said the buffer has `size` bytes, and the
+            // padding after every descriptor stays in it
+            unsafe {
+                let slot = out.add(i * s.descriptor_size);
+                slot.write_bytes(0, s.descriptor_size);
+                slot.cast::<Descriptor>().write_unaligned(*descriptor);
+            }
+        }
+
+        *size = needed;
+        *key = MapKey(map_key);
+        s.last_key = Some(map_key);
+        return RawStatus::ok();
+    })
+}
+
+extern "efiapi" fn exit_boot_services(_handle: ImageHandle, key: MapKey) -> RawStatus {
+    with_boot_state(|s| {
+        let result = match &mut s.exit_boot_services {
+            Some(hook) => hook(key.0),
+            None if s.last_key == Some(key.0) => Ok(()),
+            None => Err(Error::InvalidParameter),
+        };
+
+        s.exited = result.is_ok();
+        status(result)
+    })
+}
+
+fn find_protocol(s: &State, protocol: &Guid) -> Option<NonNull<()>> {
+    if *protocol == GraphicsOutput::GUID {
+        return NonNull::new(s.gop.cast());
+    }
+
+    return None;
+}
+
+extern "efiapi" fn locate_protocol(
+    protocol: &Guid,
+    _registration: Option<&()>,
+    interface: &mut Option<NonNull<()>>,
+) -> RawStatus {
+    with_boot_state(|s| {
+        *interface = find_protocol(s, protocol);
+        status(interface.map(|_| ()).ok_or(Error::NotFound))
+    })
+}
+
+/// Every handle has every protocol
+extern "efiapi" fn handle_protocol(
+    _handle: Handle,
+    protocol: &Guid,
+    interface: &mut Option<NonNull<()>>,
+) -> RawStatus {
+    with_boot_state(|s| {
+        *interface = find_protocol(s, protocol);
+        status(interface.map(|_| ()).ok_or(Error::Unsupported))
+    })
+}
+
+fn switch_mode(s: &mut State, mode_number: u32) {
+    let info: &ModeInformation = &s.modes[mode_number as usize];
+    s.framebuffer = std::vec![0; (info.pixels_per_scanline * info.vertical_res) as usize];
+
+    // SAFETY: `gop` and its mode live in the `MockFirmware`, the mode
+    // information lives as long as the state
+    unsafe {
+        let mode = &mut *(*s.gop)._mode.cast_mut();
+        mode.mode = mode_number;
+        mode.info = Some(&*core::ptr::from_ref(info));
+        mode.size_of_info = core::mem::size_of::<ModeInformation>();
+        mode.framebuffer_base = s.framebuffer.as_mut_ptr() as usize;
+        mode.framebuffer_size = s.framebuffer.len() * 4;
+    }
+}
+
+extern "efiapi" fn query_mode(
+    _this: &GraphicsOutput,
+    mode_number: u32,
+    size_of_info: &mut usize,
+    info: &mut *const ModeInformation,
+) -> RawStatus {
+    with_boot_state(|s| {
+        let Some(mode) = s.modes.get(mode_number as usize) else {
+            return RawStatus::from_error(Error::InvalidParameter);
+        };
+
+        *info = &**mode;
+        *size_of_info = core::mem::size_of::<ModeInformation>();
+        return RawStatus::ok();
+    })
+}
+
+extern "efiapi" fn set_mode(_this: &mut GraphicsOutput, mode_number: u32) -> RawStatus {
+    with_boot_state(|s| {
+        if mode_number as usize >= s.modes.len() {
+            return RawStatus::from_error(Error::Unsupported);
+        }
+        if let Err(e) = (s.set_mode)(mode_number) {
+            return RawStatus::from_error(e);
+        }
+
+        switch_mode(s, mode_number);
+        return RawStatus::ok();
+    })
+}
+
+extern "efiapi" fn output_string(_this: &mut Output, string: *const u16) -> RawStatus {
+    // SAFETY: the caller passes a null terminated string
+    let len = (0..).take_while(|&i| unsafe { *string.add(i) } != 0).count();
+    let string = unsafe { core::slice::from_raw_parts(string, len) };
+
+    with_state(|s| {
+        let chars = char::decode_utf16(string.iter().copied());
+        s.console.extend(chars.map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)));
+        RawStatus::ok()
+    })
+}
+
+/// Every character has a glyph
+extern "efiapi" fn test_string(_this: &Output, _string: *const u16) -> RawStatus {
+    RawStatus::ok()
+}
+
+extern "efiapi" fn reset(_this: &mut Output, _extended_verification: bool) -> RawStatus {
+    RawStatus::ok()
+}
+
+extern "efiapi" fn set_attribute(_this: &mut Output, _attribute: Attribute) -> RawStatus {
+    RawStatus::ok()
+}
+
+extern "efiapi" fn clear_screen(_this: &mut Output) -> RawStatus {
+    with_state(|s| s.console.clear());
+    RawStatus::ok()
+}
+
+extern "efiapi" fn set_cursor_position(_this: &mut Output, _column: usize, _row: usize) -> RawStatus {
+    RawStatus::ok()
+}
+
+extern "efiapi" fn enable_cursor(_this: &mut Output, _visible: bool) -> RawStatus {
+    RawStatus::ok()
+}
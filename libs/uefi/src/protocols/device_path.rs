@@ -0,0 +1,436 @@
+use crate::*;
+use core::fmt;
+
+/// Header of a device path node. The protocol interface is the first node,
+/// the rest follow it in memory up to an end node.
+#[repr(C)]
+pub struct DevicePath {
+    typ:     u8,
+    subtype: u8,
+    length:  [u8; 2],
+}
+
+impl DevicePath {
+    const HEADER_SIZE: usize = 4;
+
+    /// The whole path, including the end node. A node shorter than its
+    /// header ends the path early.
+    pub fn as_bytes(&self) -> &[u8] {
+        let start = core::ptr::from_ref(self).cast::<u8>();
+        let mut len = 0;
+
+        loop {
+            // SAFETY: the firmware ends every path with an end node and
+            // nodes don't go past it
+            let node = unsafe { &*start.add(len).cast::<DevicePath>() };
+            let node_len = u16::from_le_bytes(node.length) as usize;
+            if node_len < Self::HEADER_SIZE {
+                break;
+            }
+
+            len += node_len;
+            if node.typ == NodeType::End as u8 && node.subtype == END_ENTIRE {
+                break;
+            }
+        }
+
+        // SAFETY: checked above
+        return unsafe { core::slice::from_raw_parts(start, len) };
+    }
+
+    pub fn nodes(&self) -> Nodes<'_> {
+        Nodes::new(self.as_bytes())
+    }
+}
+
+/// Text form from the spec, e.g.
+/// `PciRoot(0x0)/Pci(0x1F,0x2)/Sata(0x0,0xFFFF,0x0)/HD(1,GPT,...)`
+impl fmt::Display for DevicePath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.nodes(), f)
+    }
+}
+
+impl crate::Protocol for DevicePath {
+    const GUID: Guid = guid::Guid::EFI_DEVICE_PATH_PROTOCOL;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum NodeType {
+    Hardware = 0x01,
+    Acpi = 0x02,
+    Messaging = 0x03,
+    Media = 0x04,
+    Bbs = 0x05,
+    End = 0x7F,
+}
+
+impl NodeType {
+    pub fn from_int(x: u8) -> Option<Self> {
+        let typ = match x {
+            0x01 => Self::Hardware,
+            0x02 => Self::Acpi,
+            0x03 => Self::Messaging,
+            0x04 => Self::Media,
+            0x05 => Self::Bbs,
+            0x7F => Self::End,
+            _ => return None,
+        };
+
+        return Some(typ);
+    }
+}
+
+const END_INSTANCE: u8 = 0x01;
+const END_ENTIRE: u8 = 0xFF;
+
+/// Iterator over the nodes of a path, without the final end node
+#[derive(Clone)]
+pub struct Nodes<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Nodes<'a> {
+    /// Nodes from raw bytes. Iteration stops at the end node or at the
+    /// first malformed node.
+    pub const fn new(bytes: &'a [u8]) -> Self {
+        Self { rest: bytes }
+    }
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        let &[typ, subtype, l0, l1, ..] = self.rest else {
+            return None;
+        };
+
+        let len = u16::from_le_bytes([l0, l1]) as usize;
+        if len < DevicePath::HEADER_SIZE || len > self.rest.len() {
+            self.rest = &[];
+            return None;
+        }
+        if typ == NodeType::End as u8 && subtype == END_ENTIRE {
+            self.rest = &[];
+            return None;
+        }
+
+        let data = &self.rest[DevicePath::HEADER_SIZE..len];
+        self.rest = &self.rest[len..];
+        return Some(Node { typ, subtype, data });
+    }
+}
+
+/// Nodes separated by `/`, instances by `,`
+impl fmt::Display for Nodes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for node in self.clone() {
+            if node.kind() == NodeKind::EndInstance {
+                f.write_str(",")?;
+                first = true;
+                continue;
+            }
+
+            if !first {
+                f.write_str("/")?;
+            }
+            write!(f, "{}", node)?;
+            first = false;
+        }
+
+        return Ok(());
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Node<'a> {
+    pub typ:     u8,
+    pub subtype: u8,
+
+    /// Everything after the header
+    pub data: &'a [u8],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PartitionSignature {
+    None,
+    Mbr(u32),
+    Gpt(Guid),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NodeKind<'a> {
+    Pci {
+        device:   u8,
+        function: u8,
+    },
+    Acpi {
+        hid: u32,
+        uid: u32,
+    },
+    Usb {
+        parent_port: u8,
+        interface:   u8,
+    },
+    Sata {
+        hba_port:        u16,
+        port_multiplier: u16,
+        lun:             u16,
+    },
+    Nvme {
+        namespace: u32,
+        eui64:     [u8; 8],
+    },
+    Mac {
+        /// Padded with zeroes to 32 bytes
+        address: [u8; 32],
+        if_type: u8,
+    },
+    Ipv4 {
+        local:       [u8; 4],
+        remote:      [u8; 4],
+        local_port:  u16,
+        remote_port: u16,
+        protocol:    u16,
+        static_ip:   bool,
+    },
+    HardDrive {
+        partition: u32,
+        start:     u64,
+        size:      u64,
+        signature: PartitionSignature,
+    },
+    CdRom {
+        boot_entry: u32,
+        start:      u64,
+        size:       u64,
+    },
+    /// UCS-2, not aligned, see `Node::file_path`
+    FilePath(&'a [u8]),
+    EndInstance,
+
+    /// Unknown or too short to be what its type says
+    Other,
+}
+
+fn read<const N: usize>(data: &[u8], offset: usize) -> Option<[u8; N]> {
+    data.get(offset..offset + N)?.try_into().ok()
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    read(data, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    read(data, offset).map(u32::from_le_bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    read(data, offset).map(u64::from_le_bytes)
+}
+
+impl<'a> Node<'a> {
+    pub fn node_type(&self) -> Option<NodeType> {
+        NodeType::from_int(self.typ)
+    }
+
+    pub fn kind(&self) -> NodeKind<'a> {
+        self.decode().unwrap_or(NodeKind::Other)
+    }
+
+    fn decode(&self) -> Option<NodeKind<'a>> {
+        let d = self.data;
+        let kind = match (self.node_type()?, self.subtype) {
+            (NodeType::Hardware, 0x01) => NodeKind::Pci { function: *d.first()?, device: *d.get(1)? },
+            (NodeType::Acpi, 0x01) => NodeKind::Acpi { hid: read_u32(d, 0)?, uid: read_u32(d, 4)? },
+            (NodeType::Messaging, 0x05) => NodeKind::Usb { parent_port: *d.first()?, interface: *d.get(1)? },
+            (NodeType::Messaging, 0x0B) => NodeKind::Mac { address: read(d, 0)?, if_type: *d.get(32)? },
+            (NodeType::Messaging, 0x0C) => NodeKind::Ipv4 {
+                local:       read(d, 0)?,
+                remote:      read(d, 4)?,
+                local_port:  read_u16(d, 8)?,
+                remote_port: read_u16(d, 10)?,
+                protocol:    read_u16(d, 12)?,
+                static_ip:   *d.get(14)? != 0,
+            },
+            (NodeType::Messaging, 0x12) => NodeKind::Sata {
+                hba_port:        read_u16(d, 0)?,
+                port_multiplier: read_u16(d, 2)?,
+                lun:             read_u16(d, 4)?,
+            },
+            (NodeType::Messaging, 0x17) => NodeKind::Nvme { namespace: read_u32(d, 0)?, eui64: read(d, 4)? },
+            (NodeType::Media, 0x01) => {
+                let signature: [u8; 16] = read(d, 20)?;
+                let signature = match *d.get(37)? {
+                    0x01 => PartitionSignature::Mbr(read_u32(&signature, 0)?),
+                    0x02 => PartitionSignature::Gpt(Guid::from_bytes(signature)),
+                    _ => PartitionSignature::None,
+                };
+
+                NodeKind::HardDrive {
+                    partition: read_u32(d, 0)?,
+                    start: read_u64(d, 4)?,
+                    size: read_u64(d, 12)?,
+                    signature,
+                }
+            },
+            (NodeType::Media, 0x02) => NodeKind::CdRom {
+                boot_entry: read_u32(d, 0)?,
+                start:      read_u64(d, 4)?,
+                size:       read_u64(d, 12)?,
+            },
+            (NodeType::Media, 0x04) => NodeKind::FilePath(d),
+            (NodeType::End, END_INSTANCE) => NodeKind::EndInstance,
+            _ => return None,
+        };
+
+        return Some(kind);
+    }
+
+    /// Characters of a file path node, up to the null terminator. Invalid
+    /// UCS-2 comes out as U+FFFD.
+    pub fn file_path(&self) -> Option<impl Iterator<Item = char> + 'a> {
+        let NodeKind::FilePath(bytes) = self.kind() else {
+            return None;
+        };
+
+        let units = bytes
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0);
+        return Some(char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)));
+    }
+}
+
+/// EISA ID of a PNP device, e.g. `PNP0A03`
+fn eisa_pnp_id(hid: u32) -> Option<u16> {
+    const PNP_VENDOR: u32 = 0x41D0;
+    return (hid & 0xFFFF == PNP_VENDOR).then_some((hid >> 16) as u16);
+}
+
+impl fmt::Display for Node<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind() {
+            NodeKind::Pci { device, function } => write!(f, "Pci(0x{:X},0x{:X})", device, function),
+            NodeKind::Acpi { hid, uid } => match eisa_pnp_id(hid) {
+                Some(0x0A03) => write!(f, "PciRoot(0x{:X})", uid),
+                Some(0x0A08) => write!(f, "PcieRoot(0x{:X})", uid),
+                Some(id) => write!(f, "Acpi(PNP{:04X},0x{:X})", id, uid),
+                None => write!(f, "Acpi(0x{:08X},0x{:X})", hid, uid),
+            },
+            NodeKind::Usb { parent_port, interface } => write!(f, "USB(0x{:X},0x{:X})", parent_port, interface),
+            NodeKind::Sata { hba_port, port_multiplier, lun } => {
+                write!(f, "Sata(0x{:X},0x{:X},0x{:X})", hba_port, port_multiplier, lun)
+            },
+            NodeKind::Nvme { namespace, eui64 } => {
+                write!(f, "NVMe(0x{:X},", namespace)?;
+                for (i, b) in eui64.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { "-" };
+                    write!(f, "{}{:02X}", separator, b)?;
+                }
+                f.write_str(")")
+            },
+            NodeKind::Mac { address, if_type } => {
+                // Ethernet and 802.5 use 6 bytes, everything else can use all
+                let len = if if_type <= 1 { 6 } else { address.len() };
+                f.write_str("MAC(")?;
+                for b in &address[..len] {
+                    write!(f, "{:02x}", b)?;
+                }
+                write!(f, ",0x{:X})", if_type)
+            },
+            NodeKind::Ipv4 { local, remote, protocol, static_ip, .. } => {
+                let [r0, r1, r2, r3] = remote;
+                let [l0, l1, l2, l3] = local;
+                write!(f, "IPv4({}.{}.{}.{},", r0, r1, r2, r3)?;
+                match protocol {
+                    6 => f.write_str("TCP,")?,
+                    17 => f.write_str("UDP,")?,
+                    p => write!(f, "0x{:X},", p)?,
+                }
+                let origin = if static_ip { "Static" } else { "DHCP" };
+                write!(f, "{},{}.{}.{}.{})", origin, l0, l1, l2, l3)
+            },
+            NodeKind::HardDrive { partition, start, size, signature } => {
+                write!(f, "HD({},", partition)?;
+                match signature {
+                    PartitionSignature::None => f.write_str("0,0")?,
+                    PartitionSignature::Mbr(sig) => write!(f, "MBR,0x{:08X}", sig)?,
+                    PartitionSignature::Gpt(guid) => write!(f, "GPT,{}", guid)?,
+                }
+                write!(f, ",0x{:X},0x{:X})", start, size)
+            },
+            NodeKind::CdRom { boot_entry, start, size } => {
+                write!(f, "CDROM(0x{:X},0x{:X},0x{:X})", boot_entry, start, size)
+            },
+            NodeKind::FilePath(_) => {
+                for c in self.file_path().into_iter().flatten() {
+                    write!(f, "{}", c)?;
+                }
+                Ok(())
+            },
+            NodeKind::EndInstance => Ok(()),
+            NodeKind::Other => {
+                write!(f, "Path({},{},", self.typ, self.subtype)?;
+                for b in self.data {
+                    write!(f, "{:02X}", b)?;
+                }
+                f.write_str(")")
+            },
+        }
+    }
+}
+
+/// The firmware's own text conversion, it knows more node types
+#[repr(C)]
+pub struct DevicePathToText {
+    convert_device_node: Option<
+        extern "efiapi" fn(node: &DevicePath, display_only: bool, allow_shortcuts: bool) -> *mut u16,
+    >,
+    convert_device_path: Option<
+        extern "efiapi" fn(path: &DevicePath, display_only: bool, allow_shortcuts: bool) -> *mut u16,
+    >,
+}
+
+impl DevicePathToText {
+    /// Text form of the whole path as UCS-2, without the null terminator.
+    /// `display_only` picks the shorter form, which can't be turned back
+    /// into a path.
+    pub fn convert_path<'bs>(
+        &self,
+        boot_services: &'bs BootServices,
+        path: &DevicePath,
+        display_only: bool,
+    ) -> Result<PoolBox<'bs, u16>, Error> {
+        let f = self.convert_device_path.expect("buggy UEFI: convert_device_path is null");
+        return Self::pool_string(boot_services, f(path, display_only, true));
+    }
+
+    /// Same as `convert_path`, but only for the first node
+    pub fn convert_node<'bs>(
+        &self,
+        boot_services: &'bs BootServices,
+        node: &DevicePath,
+        display_only: bool,
+    ) -> Result<PoolBox<'bs, u16>, Error> {
+        let f = self.convert_device_node.expect("buggy UEFI: convert_device_node is null");
+        return Self::pool_string(boot_services, f(node, display_only, true));
+    }
+
+    fn pool_string(boot_services: &BootServices, s: *mut u16) -> Result<PoolBox<'_, u16>, Error> {
+        if s.is_null() {
+            return Err(Error::OutOfResources);
+        }
+
+        // SAFETY: the firmware returns a null terminated string from the pool
+        let len = (0..).take_while(|&i| unsafe { *s.add(i) } != 0).count();
+        return Ok(unsafe { PoolBox::from_raw(boot_services, s, len) });
+    }
+}
+
+impl crate::Protocol for DevicePathToText {
+    const GUID: Guid = guid::Guid::EFI_DEVICE_PATH_TO_TEXT_PROTOCOL;
+}
@@ -0,0 +1,322 @@
+use core::ops::{Deref, DerefMut};
+
+use crate::*;
+use impl_bits::impl_bits;
+
+macro_rules! uefi_fn_ptr {
+    ($($arg:tt)*) => { Option<unsafe extern "efiapi" fn($($arg)*) -> RawStatus> };
+}
+
+/// EFI_SIMPLE_FILE_SYSTEM_PROTOCOL, installed on the handles of FAT volumes
+#[repr(C)]
+pub struct SimpleFileSystem {
+    pub revision: u64,
+
+    /// ## Parameters
+    /// * This - A pointer to the volume to open the root directory of.
+    /// * Root - A pointer to the location to return the opened file handle
+    /// for the root directory.
+    ///
+    /// ## Description
+    /// The OpenVolume() function opens a volume, and returns a file handle to
+    /// the volume's root directory. This handle is used to perform all other
+    /// file I/O operations. The volume remains open until all the file
+    /// handles to it are closed.
+    open_volume: uefi_fn_ptr!(this: &mut Self, root: &mut Option<NonNull<File>>),
+}
+
+impl SimpleFileSystem {
+    pub fn open_volume(&mut self) -> Result<FileHandle, Error> {
+        const ERRORS: &[Error] = &[
+            Error::Unsupported,
+            Error::NoMedia,
+            Error::DeviceError,
+            Error::VolumeCorrupted,
+            Error::AccessDenied,
+            Error::OutOfResources,
+            Error::MediaChanged,
+        ];
+
+        let f = self.open_volume.expect("buggy UEFI: open_volume is null");
+        let mut root = None;
+        let result = unsafe { (f)(self, &mut root) };
+        result.ok_or_expect_errors(ERRORS)?;
+
+        let root = root.expect("got nullptr from open_volume");
+        return Ok(FileHandle(root));
+    }
+}
+
+impl crate::Protocol for SimpleFileSystem {
+    const GUID: Guid = guid::Guid::EFI_FILE_SYSTEM;
+}
+
+#[repr(transparent)]
+pub struct OpenMode(u64);
+
+impl OpenMode {
+    pub const fn new() -> Self { Self(0) }
+}
+
+impl_bits! {
+    OpenMode = {
+        read = 0,
+        write = 1,
+        create = 63,
+    }
+}
+
+#[repr(transparent)]
+pub struct FileAttributes(u64);
+
+impl FileAttributes {
+    pub const fn new() -> Self { Self(0) }
+}
+
+impl_bits! {
+    FileAttributes = {
+        read_only = 0,
+        hidden = 1,
+        system = 2,
+        directory = 4,
+        archive = 5,
+    }
+}
+
+/// EFI_FILE_INFO, followed by a null-terminated file name
+#[derive(Debug)]
+#[repr(C)]
+pub struct FileInfo {
+    /// Size of this structure, including the file name
+    pub size:              u64,
+    pub file_size:         u64,
+
+    /// Amount of space the file takes on the volume
+    pub physical_size:     u64,
+    pub create_time:       Time,
+    pub last_access_time:  Time,
+    pub modification_time: Time,
+    pub attribute:         FileAttributes,
+}
+
+/// EFI_FILE_PROTOCOL, a file or a directory
+#[repr(C)]
+pub struct File {
+    pub revision: u64,
+
+    /// ## Parameters
+    /// * This - A pointer to the EFI_FILE_PROTOCOL instance that is the file
+    /// handle to the source location. This would typically be an open handle
+    /// to a directory.
+    /// * NewHandle - A pointer to the location to return the opened handle
+    /// for the new file.
+    /// * FileName - The Null-terminated string of the name of the file to be
+    /// opened. The file name may contain the following path modifiers: "\",
+    /// ".", and "..".
+    /// * OpenMode - The mode to open the file. The only valid combinations
+    /// that the file may be opened with are: Read, Read/Write, or
+    /// Create/Read/Write.
+    /// * Attributes - Only valid for EFI_FILE_MODE_CREATE, in which case these
+    /// are the attribute bits for the newly created file.
+    ///
+    /// ## Description
+    /// The Open() function opens the file or directory referred to by
+    /// FileName relative to the location of This and returns a NewHandle.
+    open: uefi_fn_ptr!(
+        this: &mut Self,
+        new_handle: &mut Option<NonNull<File>>,
+        file_name: *const u16,
+        open_mode: OpenMode,
+        attributes: FileAttributes,
+    ),
+
+    /// The Close() function closes a specified file handle. All "dirty"
+    /// cached file data is flushed to the device, and the file is closed.
+    /// In all cases the handle is closed.
+    close:  uefi_fn_ptr!(this: &mut Self),
+    delete: usize,
+
+    /// ## Parameters
+    /// * This - A pointer to the EFI_FILE_PROTOCOL instance that is the file
+    /// handle to read data from.
+    /// * BufferSize - On input, the size of the Buffer. On output, the amount
+    /// of data returned in Buffer. In both cases, the size is measured in
+    /// bytes.
+    /// * Buffer - The buffer into which the data is read.
+    ///
+    /// ## Description
+    /// The Read() function reads data from a file. If This is not a
+    /// directory, the function reads the requested number of bytes from the
+    /// file at the file's current position and returns them in Buffer. If
+    /// the read goes beyond the end of the file, the read length is
+    /// truncated to the end of the file. The file's current position is
+    /// increased by the number of bytes returned.
+    read: uefi_fn_ptr!(this: &mut Self, buffer_size: &mut usize, buffer: *mut u8),
+
+    write:        usize,
+    get_position: usize,
+
+    /// The SetPosition() function sets the current file position for the
+    /// handle to the position supplied. With the exception of seeking to
+    /// position 0xFFFFFFFFFFFFFFFF, only absolute positioning is supported.
+    set_position: uefi_fn_ptr!(this: &mut Self, position: u64),
+
+    /// ## Parameters
+    /// * This - A pointer to the EFI_FILE_PROTOCOL instance that is the file
+    /// handle the requested information is for.
+    /// * InformationType - The type identifier for the information being
+    /// requested.
+    /// * BufferSize - On input, the size of Buffer. On output, the amount of
+    /// data returned in Buffer. In both cases, the size is measured in bytes.
+    /// * Buffer - A pointer to the data buffer to return.
+    ///
+    /// ## Description
+    /// The GetInfo() function returns information of type InformationType for
+    /// the requested file. If the file does not support the requested
+    /// information type, then EFI_UNSUPPORTED is returned. If the buffer is
+    /// not large enough to fit the requested structure, EFI_BUFFER_TOO_SMALL
+    /// is returned and the BufferSize is set to the size of buffer that is
+    /// required to make the request.
+    get_info: uefi_fn_ptr!(
+        this: &mut Self,
+        information_type: &Guid,
+        buffer_size: &mut usize,
+        buffer: *mut u8,
+    ),
+
+    set_info: usize,
+    flush:    usize,
+}
+
+/// Converts a path to a null-terminated UTF-16 string, `/` is accepted as a
+/// separator too
+fn path_to_utf16(path: &str, buf: &mut [u16]) -> Result<(), Error> {
+    let mut i = 0usize;
+    for c in path.chars() {
+        let c = if c == '/' { '\\' } else { c };
+        if c as u32 > u16::MAX as u32 || c == '\0' || i + 1 >= buf.len() {
+            return Err(Error::InvalidParameter);
+        }
+        buf[i] = c as u16;
+        i += 1;
+    }
+
+    buf[i] = 0;
+    return Ok(());
+}
+
+impl File {
+    /// Opens `path` relative to this directory
+    pub fn open(&mut self, path: &str, mode: OpenMode, attributes: FileAttributes) -> Result<FileHandle, Error> {
+        const ERRORS: &[Error] = &[
+            Error::NotFound,
+            Error::NoMedia,
+            Error::MediaChanged,
+            Error::DeviceError,
+            Error::VolumeCorrupted,
+            Error::WriteProtected,
+            Error::AccessDenied,
+            Error::OutOfResources,
+            Error::VolumeFull,
+            Error::InvalidParameter,
+        ];
+
+        let mut name = [0u16; 256];
+        path_to_utf16(path, &mut name)?;
+
+        let f = self.open.expect("buggy UEFI: File::open is null");
+        let mut handle = None;
+        let result = unsafe { (f)(self, &mut handle, name.as_ptr(), mode, attributes) };
+        result.ok_or_expect_errors(ERRORS)?;
+
+        let handle = handle.expect("got nullptr from File::open");
+        return Ok(FileHandle(handle));
+    }
+
+    /// Reads from the current position, returns how many bytes were read.
+    /// Zero means the end of the file.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        const ERRORS: &[Error] = &[
+            Error::NoMedia,
+            Error::DeviceError,
+            Error::VolumeCorrupted,
+            Error::BufferTooSmall,
+        ];
+
+        let f = self.read.expect("buggy UEFI: File::read is null");
+        let mut size = buf.len();
+        let result = unsafe { (f)(self, &mut size, buf.as_mut_ptr()) };
+        result.ok_or_expect_errors(ERRORS)?;
+
+        assert!(size <= buf.len());
+        return Ok(size);
+    }
+
+    /// Reads until `buf` is full, `EndOfFile` if the file is shorter
+    pub fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<(), Error> {
+        while !buf.is_empty() {
+            let n = self.read(buf)?;
+            if n == 0 {
+                return Err(Error::EndOfFile);
+            }
+            buf = &mut buf[n..];
+        }
+
+        return Ok(());
+    }
+
+    pub fn set_position(&mut self, position: u64) -> Result<(), Error> {
+        let f = self.set_position.expect("buggy UEFI: File::set_position is null");
+        let result = unsafe { (f)(self, position) };
+        return result.ok_or_expect_errors(&[Error::Unsupported, Error::DeviceError]);
+    }
+
+    /// Fills `buf` with `FileInfo`, the file name has to fit too
+    pub fn info<'a>(&mut self, buf: &'a mut [u64]) -> Result<&'a FileInfo, Error> {
+        const ERRORS: &[Error] = &[
+            Error::Unsupported,
+            Error::NoMedia,
+            Error::DeviceError,
+            Error::VolumeCorrupted,
+            Error::BufferTooSmall,
+        ];
+
+        let f = self.get_info.expect("buggy UEFI: File::get_info is null");
+        let mut size = core::mem::size_of_val(buf);
+        let ptr = buf.as_mut_ptr().cast::<u8>();
+        let result = unsafe { (f)(self, &Guid::EFI_FILE_INFO_ID, &mut size, ptr) };
+        result.ok_or_expect_errors(ERRORS)?;
+
+        assert!(core::mem::size_of::<FileInfo>() <= size);
+        // SAFETY: the firmware filled the buffer, which is aligned enough
+        return Ok(unsafe { &*ptr.cast::<FileInfo>() });
+    }
+}
+
+/// Open `File` that is closed on drop
+pub struct FileHandle(NonNull<File>);
+
+impl Deref for FileHandle {
+    type Target = File;
+
+    fn deref(&self) -> &File {
+        // SAFETY: the firmware keeps the protocol alive until it is closed
+        unsafe { self.0.as_ref() }
+    }
+}
+
+impl DerefMut for FileHandle {
+    fn deref_mut(&mut self) -> &mut File {
+        // SAFETY: as above
+        unsafe { self.0.as_mut() }
+    }
+}
+
+impl Drop for FileHandle {
+    fn drop(&mut self) {
+        let file = &mut **self;
+        let f = file.close.expect("buggy UEFI: File::close is null");
+        // Close can't fail
+        let _ = unsafe { (f)(file) };
+    }
+}
@@ -33,7 +33,7 @@ pub struct GraphicsOutput {
     /// EFI_SUCCESS Valid mode information was returned.
     /// EFI_DEVICE_ERROR A hardware error occurred trying to retrieve the video mode.
     /// EFI_INVALID_PARAMETER ModeNumber is not valid.
-    query_mode: Option<
+    pub(crate) query_mode: Option<
         unsafe extern "efiapi" fn(
             this: &Self,
             mode_number: u32,
@@ -62,7 +62,7 @@ pub struct GraphicsOutput {
     /// EFI_SUCCESS The graphics mode specified by ModeNumber was selected.
     /// EFI_DEVICE_ERROR The device had an error and could not complete the request.
     /// EFI_UNSUPPORTED ModeNumber is not supported by this device
-    set_mode:
+    pub(crate) set_mode:
         Option<unsafe extern "efiapi" fn(this: &mut Self, mode_number: u32) -> RawStatus>,
 
     /// ## Parameters
@@ -150,7 +150,7 @@ pub struct GraphicsOutput {
 
     /// Pointer to EFI_GRAPHICS_OUTPUT_PROTOCOL_MODE data. Type
     /// EFI_GRAPHICS_OUTPUT_PROTOCOL_MODE is defined in “Related Definitions” below.
-    _mode: *const Mode,
+    pub(crate) _mode: *const Mode,
 }
 
 impl GraphicsOutput {
@@ -165,13 +165,10 @@ impl GraphicsOutput {
         let mut p: *const ModeInformation = core::ptr::null();
         let f = self.query_mode.expect("buggy UEFI: query_mode is null");
         let result = unsafe { (f)(self, mode_number, &mut sz, &mut p) };
+        result.ok_or_expect_errors(ERRORS)?;
 
         assert!(core::mem::size_of::<ModeInformation>() <= sz);
-
-        return match result.ok_or_expect_errors(ERRORS) {
-            Ok(()) => Ok(unsafe { p.as_ref().expect("query_mode: pointer is null") }),
-            Err(e) => Err(e),
-        };
+        return Ok(unsafe { p.as_ref().expect("query_mode: pointer is null") });
     }
 
     pub fn set_mode(&mut self, mode_number: u32) -> Result<(), Error> {
@@ -180,8 +177,96 @@ impl GraphicsOutput {
         let result = unsafe { (f)(self, mode_number) };
         return result.ok_or_expect_errors(ERRORS);
     }
+
+    /// SAFETY: `buffer` has to be big enough for the operation
+    unsafe fn blt_raw(
+        &mut self,
+        buffer: *const BltPixel,
+        op: BltOperation,
+        src: (usize, usize),
+        dst: Rect,
+        delta: usize,
+    ) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[Error::InvalidParameter, Error::DeviceError];
+
+        // Firmware rejects empty rectangles
+        if dst.width == 0 || dst.height == 0 {
+            return Ok(());
+        }
+
+        let f = self.blt.expect("buggy UEFI: blt is null");
+        let buffer = NonNull::new(buffer.cast_mut());
+        let result = unsafe {
+            (f)(self, buffer, op, src.0, src.1, dst.x, dst.y, dst.width, dst.height, NonZeroUsize::new(delta))
+        };
+        return result.ok_or_expect_errors(ERRORS);
+    }
+
+    /// Paints `dst` with a single color
+    pub fn blt_fill(&mut self, pixel: BltPixel, dst: Rect) -> Result<(), Error> {
+        // SAFETY: video fill reads only one pixel
+        return unsafe { self.blt_raw(&pixel, BltOperation::VideoFill, (0, 0), dst, 0) };
+    }
+
+    /// Reads the `src` rectangle of the screen into `buffer`, which has
+    /// `stride` pixels per row. `BadBufferSize` if it doesn't fit.
+    pub fn blt_to_buffer(&mut self, src: Rect, buffer: &mut [BltPixel], stride: usize) -> Result<(), Error> {
+        src.check_buffer(buffer.len(), stride)?;
+
+        // The rectangle goes to the start of `buffer`
+        let dst = Rect { x: 0, y: 0, ..src };
+        let op = BltOperation::VideoToBltBuffer;
+        let delta = stride * core::mem::size_of::<BltPixel>();
+        // SAFETY: checked above, the pointer comes from a mutable slice
+        return unsafe { self.blt_raw(buffer.as_mut_ptr(), op, (src.x, src.y), dst, delta) };
+    }
+
+    /// Draws `buffer`, which has `stride` pixels per row, on the `dst`
+    /// rectangle of the screen. `BadBufferSize` if `buffer` is too small.
+    pub fn blt_from_buffer(&mut self, buffer: &[BltPixel], stride: usize, dst: Rect) -> Result<(), Error> {
+        dst.check_buffer(buffer.len(), stride)?;
+
+        let delta = stride * core::mem::size_of::<BltPixel>();
+        // SAFETY: checked above, the firmware only reads the buffer
+        return unsafe { self.blt_raw(buffer.as_ptr(), BltOperation::BufferToVideo, (0, 0), dst, delta) };
+    }
+
+    /// Copies a rectangle of the screen to (`dst.x`, `dst.y`), the two may
+    /// overlap
+    pub fn blt_copy(&mut self, src_x: usize, src_y: usize, dst: Rect) -> Result<(), Error> {
+        // SAFETY: no buffer is used
+        return unsafe {
+            self.blt_raw(core::ptr::null(), BltOperation::VideoToVideo, (src_x, src_y), dst, 0)
+        };
+    }
 }
 
+/// Rectangle on the screen, in pixels
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rect {
+    pub x:      usize,
+    pub y:      usize,
+    pub width:  usize,
+    pub height: usize,
+}
+
+impl Rect {
+    /// Whether a buffer with `stride` pixels per row has room for the rectangle
+    fn check_buffer(&self, len: usize, stride: usize) -> Result<(), Error> {
+        if self.width == 0 || self.height == 0 {
+            return Ok(());
+        }
+
+        let needed = (self.height - 1).checked_mul(stride).and_then(|n| n.checked_add(self.width));
+        if self.width > stride || needed.is_none_or(|n| n > len) {
+            return Err(Error::BadBufferSize);
+        }
+
+        return Ok(());
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[repr(C)]
 pub enum BltOperation {
     VideoFill,
@@ -190,6 +275,7 @@ pub enum BltOperation {
     VideoToVideo,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[repr(C)]
 pub struct BltPixel {
     pub blue:     u8,
@@ -198,6 +284,12 @@ pub struct BltPixel {
     pub reserved: u8,
 }
 
+impl BltPixel {
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self { blue, green, red, reserved: 0 }
+    }
+}
+
 /// If a bit is set in RedMask, GreenMask, or BlueMask then those bits of the
 /// pixel represent the corresponding color. Bits in RedMask, GreenMask,
 /// BlueMask, and ReserverdMask must not over lap bit positions. The values for
@@ -205,7 +297,7 @@ pub struct BltPixel {
 /// intensity. The color intensities must increase as the color values for a
 /// each color mask increase with a minimum intensity of all bits in a color
 /// mask clear to a maximum intensity of all bits in a color mask set.
-#[derive(Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[repr(C)]
 pub struct PixelBitmask {
     pub red:      u32,
@@ -214,6 +306,7 @@ pub struct PixelBitmask {
     pub reserved: u32,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[repr(C)]
 pub enum PixelFormat {
     /// A pixel is 32-bits and byte zero represents red, byte one represents
@@ -240,6 +333,20 @@ pub enum PixelFormat {
     BltOnly,
 }
 
+impl PixelFormat {
+    pub fn from_int(x: u32) -> Option<Self> {
+        let x = match x {
+            0 => Self::Rgbr8bpc,
+            1 => Self::Bgrr8bpc,
+            2 => Self::Bitmask,
+            3 => Self::BltOnly,
+            _ => return None,
+        };
+
+        return Some(x);
+    }
+}
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct ModeInformation {
@@ -282,6 +389,12 @@ pub struct ModeInformation {
     pub pixels_per_scanline: u32,
 }
 
+impl ModeInformation {
+    pub fn pixel_format(&self) -> Option<PixelFormat> {
+        PixelFormat::from_int(self.pixel_format)
+    }
+}
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct Mode {
@@ -0,0 +1,83 @@
+use crate::*;
+use super::device_path::DevicePath;
+
+/// Information about a loaded image, the wrapper uses it to find the volume
+/// it was loaded from
+#[repr(C)]
+pub struct LoadedImage {
+    /// Defines the revision of the EFI_LOADED_IMAGE_PROTOCOL structure. All
+    /// future revisions will be backward compatible to the current revision.
+    pub revision: u32,
+
+    /// Parent image's image handle. NULL if the image is loaded directly from
+    /// the firmware's boot manager.
+    pub parent_handle: Handle,
+
+    /// The image's EFI system table pointer.
+    pub system_table: *const SystemTable,
+
+    /// The device handle that the EFI Image was loaded from.
+    pub device_handle: Handle,
+
+    /// A pointer to the file path portion specific to DeviceHandle that the
+    /// EFI Image was loaded from.
+    pub file_path: *const DevicePath,
+    _reserved:     usize,
+
+    /// The size in bytes of LoadOptions.
+    pub load_options_size: u32,
+
+    /// A pointer to the image's binary load options.
+    pub load_options: *const (),
+
+    /// The base address at which the image was loaded.
+    pub image_base: *const (),
+
+    /// The size in bytes of the loaded image.
+    pub image_size: u64,
+
+    /// The memory type that the code sections were loaded as.
+    pub image_code_type: u32,
+
+    /// The memory type that the data sections were loaded as.
+    pub image_data_type: u32,
+    unload:              usize,
+}
+
+impl LoadedImage {
+    /// `file_path`, relative to the device in `device_handle`
+    pub fn file_device_path(&self) -> Option<&DevicePath> {
+        // SAFETY: the path lives as long as the image
+        return unsafe { self.file_path.as_ref() };
+    }
+
+    /// Empty if there are none
+    pub fn load_options(&self) -> &[u8] {
+        if self.load_options.is_null() {
+            return &[];
+        }
+
+        // SAFETY: the firmware says that's how big they are
+        return unsafe {
+            core::slice::from_raw_parts(self.load_options.cast::<u8>(), self.load_options_size as usize)
+        };
+    }
+
+    /// Load options as UCS-2, which is what the shell and boot manager pass,
+    /// without the null terminator. `None` if they can't be UCS-2.
+    pub fn load_options_ucs2(&self) -> Option<&[u16]> {
+        let ptr = self.load_options.cast::<u16>();
+        if ptr.is_null() || !ptr.is_aligned() || !self.load_options_size.is_multiple_of(2) {
+            return None;
+        }
+
+        // SAFETY: as in `load_options`
+        let options = unsafe { core::slice::from_raw_parts(ptr, self.load_options_size as usize / 2) };
+        let len = options.iter().position(|&c| c == 0).unwrap_or(options.len());
+        return Some(&options[..len]);
+    }
+}
+
+impl crate::Protocol for LoadedImage {
+    const GUID: Guid = guid::Guid::LOADED_IMAGE_PROTOCOL;
+}
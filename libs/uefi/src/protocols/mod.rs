@@ -1,2 +1,7 @@
 pub mod simple_text;
 pub mod gop;
+pub mod rng;
+pub mod loaded_image;
+pub mod file;
+pub mod device_path;
+pub mod pci_io;
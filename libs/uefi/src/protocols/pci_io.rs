@@ -0,0 +1,334 @@
+use crate::*;
+use impl_bits::impl_bits;
+
+/// Access to one PCI function, installed by the PCI bus driver on every
+/// function it found
+#[repr(C)]
+pub struct PciIo {
+    poll_mem: usize,
+    poll_io:  usize,
+    mem:      [usize; 2],
+    io:       [usize; 2],
+
+    /// ## Parameters
+    /// * This - A pointer to the EFI_PCI_IO_PROTOCOL instance.
+    /// * Width - Signifies the width of the memory operations.
+    /// * Offset - The offset within the PCI configuration space for the PCI
+    /// controller.
+    /// * Count - The number of PCI configuration operations to perform.
+    /// * Buffer - For read operations, the destination buffer to store the
+    /// results.
+    ///
+    /// ## Status codes returned
+    /// EFI_SUCCESS The data was read from the PCI controller.
+    /// EFI_UNSUPPORTED The address range specified by Offset, Width, and
+    /// Count is not valid for the PCI configuration header of the PCI
+    /// controller.
+    /// EFI_OUT_OF_RESOURCES The request could not be completed due to a lack
+    /// of resources.
+    /// EFI_INVALID_PARAMETER Buffer is NULL or Width is invalid.
+    pci_read: Option<
+        extern "efiapi" fn(this: &Self, width: u32, offset: u32, count: usize, buffer: *mut u8) -> RawStatus,
+    >,
+    pci_write: usize,
+
+    copy_mem:        usize,
+    map:             usize,
+    unmap:           usize,
+    allocate_buffer: usize,
+    free_buffer:     usize,
+    flush:           usize,
+
+    /// Retrieves this PCI controller's current PCI bus number, device
+    /// number, and function number.
+    get_location: Option<
+        extern "efiapi" fn(
+            this: &Self,
+            segment: &mut usize,
+            bus: &mut usize,
+            device: &mut usize,
+            function: &mut usize,
+        ) -> RawStatus,
+    >,
+
+    /// Performs an operation on the attributes that this PCI controller
+    /// supports. The operations include getting the set of supported
+    /// attributes, retrieving the current attributes, setting the current
+    /// attributes, enabling attributes, and disabling attributes.
+    attributes: Option<
+        extern "efiapi" fn(this: &Self, operation: u32, attributes: u64, result: Option<&mut u64>) -> RawStatus,
+    >,
+
+    /// ## Parameters
+    /// * This - A pointer to the EFI_PCI_IO_PROTOCOL instance.
+    /// * BarIndex - The BAR index of the standard PCI Configuration header to
+    /// use as the base address for the memory operation to perform.
+    /// * Supports - A pointer to the mask of attributes that this PCI
+    /// controller supports setting for this BAR with SetBarAttributes().
+    /// * Resources - A pointer to the resource descriptors that describe the
+    /// current configuration of this BAR of the PCI controller. This buffer
+    /// is allocated by the function and has to be freed with FreePool().
+    ///
+    /// ## Status codes returned
+    /// EFI_SUCCESS If Supports is not NULL, then the attributes that the PCI
+    /// controller supports are returned in Supports. If Resources is not
+    /// NULL, then the resource descriptors that the PCI controller is
+    /// currently using are returned in Resources.
+    /// EFI_INVALID_PARAMETER Both Supports and Resources are NULL.
+    /// EFI_UNSUPPORTED BarIndex not valid for this PCI controller.
+    /// EFI_OUT_OF_RESOURCES There are not enough resources available to
+    /// allocate Resources.
+    get_bar_attributes: Option<
+        extern "efiapi" fn(this: &Self, bar_index: u8, supports: &mut u64, resources: &mut *mut u8) -> RawStatus,
+    >,
+    set_bar_attributes: usize,
+
+    /// Size of the option ROM, in bytes
+    pub rom_size:  u64,
+    pub rom_image: *const (),
+}
+
+impl crate::Protocol for PciIo {
+    const GUID: Guid = guid::Guid::EFI_PCI_IO_PROTOCOL;
+}
+
+/// Type-specific flag of memory resources that are prefetchable
+const ACPI_PREFETCHABLE: u8 = 0x06;
+
+impl PciIo {
+    /// Reads dwords of the configuration space, starting at `offset`
+    pub fn read_config(&self, offset: u32, buf: &mut [u32]) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[Error::Unsupported, Error::OutOfResources, Error::InvalidParameter];
+        const WIDTH_UINT32: u32 = 2;
+
+        let f = self.pci_read.expect("buggy UEFI: PciIo::pci_read is null");
+        let result = f(self, WIDTH_UINT32, offset, buf.len(), buf.as_mut_ptr().cast());
+        return result.ok_or_expect_errors(ERRORS);
+    }
+
+    /// Segment, bus, device and function
+    pub fn location(&self) -> Result<(u16, u8, u8, u8), Error> {
+        const ERRORS: &[Error] = &[Error::InvalidParameter];
+
+        let f = self.get_location.expect("buggy UEFI: PciIo::get_location is null");
+        let (mut segment, mut bus, mut device, mut function) = (0, 0, 0, 0);
+        f(self, &mut segment, &mut bus, &mut device, &mut function).ok_or_expect_errors(ERRORS)?;
+        return Ok((segment as u16, bus as u8, device as u8, function as u8));
+    }
+
+    /// `EFI_PCI_IO_ATTRIBUTE_*` bits that are currently enabled
+    pub fn current_attributes(&self) -> Result<u64, Error> {
+        self.attributes_op(AttributeOperation::Get)
+    }
+
+    /// `EFI_PCI_IO_ATTRIBUTE_*` bits that this function supports
+    pub fn supported_attributes(&self) -> Result<u64, Error> {
+        self.attributes_op(AttributeOperation::Supported)
+    }
+
+    fn attributes_op(&self, operation: AttributeOperation) -> Result<u64, Error> {
+        const ERRORS: &[Error] = &[Error::Unsupported, Error::InvalidParameter];
+
+        let f = self.attributes.expect("buggy UEFI: PciIo::attributes is null");
+        let mut result = 0;
+        f(self, operation as u32, 0, Some(&mut result)).ok_or_expect_errors(ERRORS)?;
+        return Ok(result);
+    }
+
+    /// Address range of a BAR. `None` if the function doesn't implement it.
+    pub fn bar(&self, boot_services: &BootServices, index: u8) -> Result<Option<Bar>, Error> {
+        const ERRORS: &[Error] = &[Error::InvalidParameter, Error::Unsupported, Error::OutOfResources];
+        const QWORD_DESCRIPTOR: u8 = 0x8A;
+        const QWORD_DESCRIPTOR_SIZE: usize = 46;
+        const MEMORY: u8 = 0;
+        const IO: u8 = 1;
+
+        let f = self.get_bar_attributes.expect("buggy UEFI: PciIo::get_bar_attributes is null");
+        let mut supports = 0;
+        let mut resources = core::ptr::null_mut();
+        match f(self, index, &mut supports, &mut resources).ok_or_expect_errors(ERRORS) {
+            Ok(()) => {},
+            Err(Error::Unsupported) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let Some(resources) = NonNull::new(resources) else {
+            return Ok(None);
+        };
+
+        // SAFETY: a QWORD address space descriptor is first, unless the BAR
+        // isn't used, then it's just the 2 byte end tag
+        let descriptor = unsafe {
+            match *resources.as_ptr() {
+                QWORD_DESCRIPTOR => Some(resources.as_ptr().cast::<[u8; QWORD_DESCRIPTOR_SIZE]>().read_unaligned()),
+                _ => None,
+            }
+        };
+        // SAFETY: the firmware allocated it from the pool and it's not used
+        // anymore
+        let _ = unsafe { boot_services.free_pool(resources) };
+
+        let Some(d) = descriptor else {
+            return Ok(None);
+        };
+
+        let u64_at = |offset: usize| u64::from_le_bytes(d[offset..offset + 8].try_into().unwrap());
+        let (granularity, base, size) = (u64_at(6), u64_at(14), u64_at(38));
+        let mut flags = BarFlags::new();
+        match d[3] {
+            MEMORY => {},
+            IO => flags = flags.set_io(),
+            _ => return Ok(None),
+        }
+        if granularity == 64 {
+            flags = flags.set_mem64();
+        }
+        if d[5] & ACPI_PREFETCHABLE == ACPI_PREFETCHABLE {
+            flags = flags.set_prefetchable();
+        }
+
+        return Ok(Some(Bar { base, size, attributes: supports, flags, _reserved: 0 }));
+    }
+
+    /// Everything about this function that fits in a `PciFunction`
+    pub fn describe(&self, boot_services: &BootServices) -> Result<PciFunction, Error> {
+        const BRIDGE_HEADER: u8 = 0x01;
+
+        let mut header = [0u32; 4];
+        self.read_config(0, &mut header)?;
+        let (segment, bus, device, function) = self.location()?;
+
+        let [vendor_id, device_id] = [header[0] as u16, (header[0] >> 16) as u16];
+        let [revision, prog_if, subclass, class] = header[2].to_le_bytes();
+        let header_type = (header[3] >> 16) as u8;
+        let mut info = PciFunction {
+            segment,
+            bus,
+            device,
+            function,
+            header_type,
+            vendor_id,
+            device_id,
+            class,
+            subclass,
+            prog_if,
+            revision,
+            _reserved: 0,
+            attributes: self.supported_attributes().unwrap_or(0),
+            bars: [Bar::NONE; 6],
+        };
+
+        // Bridges only have 2 BARs, the rest of the header is bus numbers
+        let bars = if header_type & 0x7F == BRIDGE_HEADER { 2 } else { 6 };
+        for (i, bar) in info.bars.iter_mut().enumerate().take(bars) {
+            *bar = self.bar(boot_services, i as u8)?.unwrap_or(Bar::NONE);
+        }
+
+        return Ok(info);
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(u32)]
+enum AttributeOperation {
+    Get = 0,
+    Supported = 4,
+}
+
+#[repr(transparent)]
+pub struct BarFlags(u32);
+
+impl BarFlags {
+    pub const fn new() -> Self { Self(0) }
+}
+
+impl_bits! {
+    BarFlags = {
+        /// I/O ports instead of memory
+        io = 0,
+        prefetchable = 1,
+
+        /// Memory BAR that can be placed above 4 GiB
+        mem64 = 2,
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Bar {
+    /// Address assigned by the firmware
+    pub base:       u64,
+
+    /// In bytes, zero for BARs that are not implemented
+    pub size:       u64,
+
+    /// `EFI_PCI_IO_ATTRIBUTE_*` bits this BAR supports
+    pub attributes: u64,
+    pub flags:      BarFlags,
+    pub _reserved:  u32,
+}
+
+impl Bar {
+    pub const NONE: Self = Self { base: 0, size: 0, attributes: 0, flags: BarFlags::new(), _reserved: 0 };
+}
+
+/// One entry of the inventory from `pci_functions`. Plain integers, so that
+/// it can be handed to the kernel as is.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PciFunction {
+    pub segment:     u16,
+    pub bus:         u8,
+    pub device:      u8,
+    pub function:    u8,
+    pub header_type: u8,
+    pub vendor_id:   u16,
+    pub device_id:   u16,
+    pub class:       u8,
+    pub subclass:    u8,
+    pub prog_if:     u8,
+    pub revision:    u8,
+    pub _reserved:   u16,
+
+    /// `EFI_PCI_IO_ATTRIBUTE_*` bits the function supports
+    pub attributes:  u64,
+    pub bars:        [Bar; 6],
+}
+
+/// Iterator over every PCI function that has a `PciIo`
+pub struct PciFunctions<'bs> {
+    boot_services: &'bs BootServices,
+    handles:       PoolBox<'bs, Handle>,
+    next:          usize,
+}
+
+impl Iterator for PciFunctions<'_> {
+    type Item = Result<PciFunction, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let &handle = self.handles.get(self.next)?;
+        self.next += 1;
+
+        let result = self.boot_services.handle_protocol::<PciIo>(handle);
+        return Some(result.and_then(|pci| pci.describe(self.boot_services)));
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.handles.len() - self.next;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for PciFunctions<'_> {}
+
+/// Every PCI function the firmware knows about, in the order of its handles
+pub fn pci_functions(boot_services: &BootServices) -> Result<PciFunctions<'_>, Error> {
+    let handles = match boot_services.locate_handle_buffer(LocateSearch::ByProtocol(&PciIo::GUID)) {
+        Ok(handles) => handles,
+        // SAFETY: empty is fine
+        Err(Error::NotFound) => unsafe { PoolBox::from_raw(boot_services, core::ptr::null_mut(), 0) },
+        Err(e) => return Err(e),
+    };
+
+    return Ok(PciFunctions { boot_services, handles, next: 0 });
+}
@@ -0,0 +1,103 @@
+use crate::*;
+
+#[repr(C)]
+pub struct Rng {
+    /// ## Parameters
+    /// * This - A pointer to the EFI_RNG_PROTOCOL instance.
+    /// * RNGAlgorithmListSize - On input, the size in bytes of RNGAlgorithmList.
+    /// On output with a return code of EFI_SUCCESS, the size in bytes of the
+    /// data returned in RNGAlgorithmList. On output with a return code of
+    /// EFI_BUFFER_TOO_SMALL, the size of RNGAlgorithmList required to obtain
+    /// the list.
+    /// * RNGAlgorithmList - A caller-allocated memory buffer filled by the
+    /// driver with one EFI_RNG_ALGORITHM element for each supported RNG
+    /// algorithm. The list must not change across multiple calls to the same
+    /// driver. The first algorithm in the list is the default algorithm for
+    /// the driver.
+    ///
+    /// ## Description
+    /// This function returns information about supported RNG algorithms.
+    ///
+    /// ## Status codes returned
+    /// EFI_SUCCESS The RNG algorithm list was returned successfully.
+    /// EFI_UNSUPPORTED The services is not supported by this driver.
+    /// EFI_DEVICE_ERROR The list of algorithms could not be retrieved due to a
+    /// hardware or firmware error.
+    /// EFI_BUFFER_TOO_SMALL The buffer RNGAlgorithmList is too small to hold
+    /// the result.
+    get_info: Option<
+        unsafe extern "efiapi" fn(
+            this: &mut Self,
+            algorithm_list_size: &mut usize,
+            algorithm_list: *mut Guid,
+        ) -> RawStatus,
+    >,
+
+    /// ## Parameters
+    /// * This - A pointer to the EFI_RNG_PROTOCOL instance.
+    /// * RNGAlgorithm - A pointer to the EFI_RNG_ALGORITHM that identifies the
+    /// RNG algorithm to use. May be NULL in which case the function will use
+    /// its default RNG algorithm.
+    /// * RNGValueLength - The length in bytes of the memory buffer pointed to
+    /// by RNGValue. The driver shall return exactly this numbers of bytes.
+    /// * RNGValue - A caller-allocated memory buffer filled by the driver with
+    /// the resulting RNG value.
+    ///
+    /// ## Description
+    /// This function fills the RNGValue buffer with random bytes from the
+    /// specified RNG algorithm. The driver must not reuse RNG values across
+    /// calls.
+    ///
+    /// ## Status codes returned
+    /// EFI_SUCCESS The RNG value was returned successfully.
+    /// EFI_UNSUPPORTED The algorithm specified by RNGAlgorithm is not
+    /// supported by this driver.
+    /// EFI_DEVICE_ERROR An RNG value could not be retrieved due to a hardware
+    /// or firmware error.
+    /// EFI_NOT_READY There is not enough random data available to satisfy the
+    /// length requested by RNGValueLength.
+    /// EFI_INVALID_PARAMETER RNGValue is NULL or RNGValueLength is zero.
+    get_rng: Option<
+        unsafe extern "efiapi" fn(
+            this: &mut Self,
+            algorithm: Option<&Guid>,
+            value_length: usize,
+            value: *mut u8,
+        ) -> RawStatus,
+    >,
+}
+
+impl Rng {
+    /// Fills `list` with the supported algorithms, the first one is the
+    /// default. Returns `BufferTooSmall` if they don't fit.
+    pub fn algorithms<'a>(&mut self, list: &'a mut [Guid]) -> Result<&'a [Guid], Error> {
+        const ERRORS: &[Error] = &[Error::Unsupported, Error::DeviceError, Error::BufferTooSmall];
+
+        let mut size = core::mem::size_of_val(list);
+        let f = self.get_info.expect("buggy UEFI: get_info is null");
+        let result = unsafe { (f)(self, &mut size, list.as_mut_ptr()) };
+        result.ok_or_expect_errors(ERRORS)?;
+
+        let len = size / core::mem::size_of::<Guid>();
+        return Ok(&list[..len]);
+    }
+
+    /// Fills `buf` with random bytes, `algorithm` of `None` picks the
+    /// driver's default
+    pub fn get_rng(&mut self, algorithm: Option<&Guid>, buf: &mut [u8]) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[
+            Error::Unsupported,
+            Error::DeviceError,
+            Error::NotReady,
+            Error::InvalidParameter,
+        ];
+
+        let f = self.get_rng.expect("buggy UEFI: get_rng is null");
+        let result = unsafe { (f)(self, algorithm, buf.len(), buf.as_mut_ptr()) };
+        return result.ok_or_expect_errors(ERRORS);
+    }
+}
+
+impl crate::Protocol for Rng {
+    const GUID: Guid = guid::Guid::EFI_RNG_PROTOCOL;
+}
@@ -1,11 +1,308 @@
 use core::fmt;
+use core::ptr::NonNull;
 
 use crate::*;
+use impl_bits::impl_bits;
 
 macro_rules! uefi_fn_ptr {
     ($($arg:tt)*) => { Option<unsafe extern "efiapi" fn($($arg)*) -> RawStatus> };
 }
 
+/// Keys without a character, `NULL` for printable ones
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(transparent)]
+pub struct ScanCode(pub u16);
+
+impl ScanCode {
+    pub const NULL: Self = Self(0x00);
+    pub const UP: Self = Self(0x01);
+    pub const DOWN: Self = Self(0x02);
+    pub const RIGHT: Self = Self(0x03);
+    pub const LEFT: Self = Self(0x04);
+    pub const HOME: Self = Self(0x05);
+    pub const END: Self = Self(0x06);
+    pub const INSERT: Self = Self(0x07);
+    pub const DELETE: Self = Self(0x08);
+    pub const PAGE_UP: Self = Self(0x09);
+    pub const PAGE_DOWN: Self = Self(0x0A);
+    pub const F1: Self = Self(0x0B);
+    pub const F2: Self = Self(0x0C);
+    pub const F3: Self = Self(0x0D);
+    pub const F4: Self = Self(0x0E);
+    pub const F5: Self = Self(0x0F);
+    pub const F6: Self = Self(0x10);
+    pub const F7: Self = Self(0x11);
+    pub const F8: Self = Self(0x12);
+    pub const F9: Self = Self(0x13);
+    pub const F10: Self = Self(0x14);
+    pub const F11: Self = Self(0x15);
+    pub const F12: Self = Self(0x16);
+    pub const ESCAPE: Self = Self(0x17);
+
+    /// Only reported by InputEx
+    pub const PAUSE: Self = Self(0x48);
+}
+
+/// EFI_INPUT_KEY, `scan_code` is `ScanCode::NULL` for printable keys
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct InputKey {
+    pub scan_code:    ScanCode,
+    pub unicode_char: u16,
+}
+
+impl InputKey {
+    pub const CHAR_BACKSPACE: u16 = 0x08;
+    pub const CHAR_TAB: u16 = 0x09;
+    pub const CHAR_LINEFEED: u16 = 0x0A;
+    pub const CHAR_CARRIAGE_RETURN: u16 = 0x0D;
+
+    /// `None` for keys without a character and for lone surrogates
+    pub fn char(&self) -> Option<char> {
+        if self.unicode_char == 0 {
+            return None;
+        }
+
+        return char::from_u32(self.unicode_char as u32);
+    }
+}
+
+/// EFI_SIMPLE_TEXT_INPUT_PROTOCOL
+#[repr(C)]
+pub struct Input {
+    /// The Reset() function resets the input device hardware. As part of
+    /// initialization process, the firmware/device will make a quick but
+    /// reasonable attempt to verify that the device is functioning.
+    pub reset: uefi_fn_ptr!(this: &mut Self, extended_verification: bool),
+
+    /// # Parameters
+    ///
+    /// * `this` - A pointer to the `SimpleTextInputProtocol` instance.
+    /// * `key` - A pointer to a buffer that is filled in with the keystroke
+    ///   information for the key that was pressed.
+    ///
+    /// # Description
+    ///
+    /// The ReadKeyStroke() function reads the next keystroke from the input
+    /// device. If there is no pending keystroke the function returns
+    /// EFI_NOT_READY.
+    pub read_key_stroke: uefi_fn_ptr!(this: &mut Self, key: &mut InputKey),
+
+    /// Event to use with `BootServices::wait_for_event` to wait for a key
+    pub wait_for_key: Event,
+}
+
+impl Input {
+    /// Also throws away pending keys
+    pub fn reset(&mut self, ver: Verification) -> Result<(), Error> {
+        let f = self.reset.expect("buggy UEFI: simple_text::Input::reset is null");
+        let result = unsafe { (f)(self, ver.to_bool()) };
+        return result.ok_or_expect_errors(&[Error::DeviceError]);
+    }
+
+    /// `NotReady` if no key was pressed
+    pub fn read_key_stroke(&mut self) -> Result<InputKey, Error> {
+        const ERRORS: &[Error] = &[Error::NotReady, Error::DeviceError, Error::Unsupported];
+
+        let f = self.read_key_stroke.expect("buggy UEFI: simple_text::Input::read_key_stroke is null");
+        let mut key = InputKey { scan_code: ScanCode::NULL, unicode_char: 0 };
+        let result = unsafe { (f)(self, &mut key) };
+        result.ok_or_expect_errors(ERRORS)?;
+        return Ok(key);
+    }
+}
+
+impl crate::Protocol for Input {
+    const GUID: Guid = guid::Guid::EFI_SIMPLE_TEXT_INPUT_PROTOCOL;
+}
+
+/// EFI_KEY_SHIFT_STATE, only meaningful if `valid` is set
+#[repr(transparent)]
+#[derive(PartialEq, Eq)]
+pub struct ShiftState(u32);
+
+impl ShiftState {
+    pub const fn new() -> Self { Self(0) }
+}
+
+impl_bits! {
+    ShiftState = {
+        right_shift = 0,
+        left_shift = 1,
+        right_control = 2,
+        left_control = 3,
+        right_alt = 4,
+        left_alt = 5,
+        right_logo = 6,
+        left_logo = 7,
+        menu_key = 8,
+        sys_req = 9,
+        valid = 31,
+    }
+}
+
+impl ShiftState {
+    pub const fn shift(self) -> bool {
+        self.right_shift() || self.left_shift()
+    }
+
+    pub const fn control(self) -> bool {
+        self.right_control() || self.left_control()
+    }
+
+    pub const fn alt(self) -> bool {
+        self.right_alt() || self.left_alt()
+    }
+}
+
+/// EFI_KEY_TOGGLE_STATE, only meaningful if `valid` is set
+#[repr(transparent)]
+#[derive(PartialEq, Eq)]
+pub struct ToggleState(u8);
+
+impl ToggleState {
+    pub const fn new() -> Self { Self(0) }
+}
+
+impl_bits! {
+    ToggleState = {
+        scroll_lock = 0,
+        num_lock = 1,
+        caps_lock = 2,
+
+        /// Report keys that only change the state, they come with
+        /// `ScanCode::NULL` and no character
+        key_state_exposed = 6,
+        valid = 7,
+    }
+}
+
+/// EFI_KEY_STATE
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct KeyState {
+    pub shift_state:  ShiftState,
+    pub toggle_state: ToggleState,
+}
+
+/// EFI_KEY_DATA
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct KeyData {
+    pub key:   InputKey,
+    pub state: KeyState,
+}
+
+/// Called by the firmware when a registered key is pressed
+pub type KeyNotify = extern "efiapi" fn(key_data: &KeyData) -> RawStatus;
+
+/// Returned by `InputEx::register_key_notify`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(transparent)]
+pub struct KeyNotifyHandle(NonNull<()>);
+
+/// EFI_SIMPLE_TEXT_INPUT_EX_PROTOCOL, reports modifiers and keys that
+/// `Input` drops, like Ctrl with a letter
+#[repr(C)]
+pub struct InputEx {
+    /// Same as `Input::reset`
+    pub reset: uefi_fn_ptr!(this: &mut Self, extended_verification: bool),
+
+    /// # Parameters
+    ///
+    /// * `this` - A pointer to the `SimpleTextInputExProtocol` instance.
+    /// * `key_data` - A pointer to a buffer that is filled in with the
+    ///   keystroke state data for the key that was pressed.
+    ///
+    /// # Description
+    ///
+    /// Reads the next keystroke from the input device. If there is no
+    /// pending keystroke the function returns EFI_NOT_READY. Unlike
+    /// `Input::read_key_stroke`, key combinations are not translated, so
+    /// Ctrl-C comes as 'c' with a control key in the shift state.
+    pub read_key_stroke_ex: uefi_fn_ptr!(this: &mut Self, key_data: &mut KeyData),
+
+    /// Event to use with `BootServices::wait_for_event` to wait for a key
+    pub wait_for_key_ex: Event,
+
+    /// Sets the toggle state of the device, e.g. the Caps Lock LED
+    pub set_state: uefi_fn_ptr!(this: &mut Self, key_toggle_state: &ToggleState),
+
+    /// # Parameters
+    ///
+    /// * `this` - A pointer to the `SimpleTextInputExProtocol` instance.
+    /// * `key_data` - Key and shift state to watch for. A `shift_state` or
+    ///   `toggle_state` without the `valid` bit matches any state.
+    /// * `key_notification_function` - Called when the key is pressed.
+    /// * `notify_handle` - Filled with a handle for `unregister_key_notify`.
+    pub register_key_notify: uefi_fn_ptr!(
+        this: &mut Self,
+        key_data: &KeyData,
+        key_notification_function: KeyNotify,
+        notify_handle: &mut Option<KeyNotifyHandle>
+    ),
+
+    pub unregister_key_notify: uefi_fn_ptr!(this: &mut Self, notification_handle: KeyNotifyHandle),
+}
+
+impl InputEx {
+    /// Also throws away pending keys
+    pub fn reset(&mut self, ver: Verification) -> Result<(), Error> {
+        let f = self.reset.expect("buggy UEFI: simple_text::InputEx::reset is null");
+        let result = unsafe { (f)(self, ver.to_bool()) };
+        return result.ok_or_expect_errors(&[Error::DeviceError]);
+    }
+
+    /// `NotReady` if no key was pressed
+    pub fn read_key_stroke(&mut self) -> Result<KeyData, Error> {
+        const ERRORS: &[Error] = &[Error::NotReady, Error::DeviceError, Error::Unsupported];
+
+        let f = self
+            .read_key_stroke_ex
+            .expect("buggy UEFI: simple_text::InputEx::read_key_stroke_ex is null");
+        let mut data = KeyData {
+            key:   InputKey { scan_code: ScanCode::NULL, unicode_char: 0 },
+            state: KeyState { shift_state: ShiftState::new(), toggle_state: ToggleState::new() },
+        };
+        let result = unsafe { (f)(self, &mut data) };
+        result.ok_or_expect_errors(ERRORS)?;
+        return Ok(data);
+    }
+
+    /// `state` needs the `valid` bit
+    pub fn set_state(&mut self, state: ToggleState) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[Error::DeviceError, Error::Unsupported];
+
+        let f = self.set_state.expect("buggy UEFI: simple_text::InputEx::set_state is null");
+        let result = unsafe { (f)(self, &state) };
+        return result.ok_or_expect_errors(ERRORS);
+    }
+
+    /// `notify` runs at TPL_CALLBACK every time `key` is pressed, until the
+    /// returned handle is unregistered
+    pub fn register_key_notify(&mut self, key: &KeyData, notify: KeyNotify) -> Result<KeyNotifyHandle, Error> {
+        let f = self
+            .register_key_notify
+            .expect("buggy UEFI: simple_text::InputEx::register_key_notify is null");
+        let mut handle = None;
+        let result = unsafe { (f)(self, key, notify, &mut handle) };
+        result.ok_or_expect_errors(&[Error::OutOfResources])?;
+        return Ok(handle.expect("buggy UEFI: register_key_notify returned a null handle"));
+    }
+
+    pub fn unregister_key_notify(&mut self, handle: KeyNotifyHandle) -> Result<(), Error> {
+        let f = self
+            .unregister_key_notify
+            .expect("buggy UEFI: simple_text::InputEx::unregister_key_notify is null");
+        let result = unsafe { (f)(self, handle) };
+        return result.ok_or_expect_errors(&[Error::InvalidParameter]);
+    }
+}
+
+impl crate::Protocol for InputEx {
+    const GUID: Guid = guid::Guid::EFI_SIMPLE_TEXT_INPUT_EX_PROTOCOL;
+}
+
 #[repr(C)]
 pub struct OutputMode {
     pub max_mode:       i32,
@@ -67,9 +364,26 @@ pub struct Output {
     /// Unicode character codes will do so.
     pub test_string: uefi_fn_ptr!(this: &Self, string: *const u16),
 
-    pub query_mode:    usize,
-    pub set_mode:      usize,
-    pub set_attribute: usize,
+    /// # Parameters
+    ///
+    /// * `this` - A pointer to the `SimpleTextOutputProtocol` instance.
+    /// * `mode_number` - The mode number to return information on.
+    /// * `columns`, `rows` - Returns the geometry of the text output device
+    ///   for the requested mode.
+    ///
+    /// # Description
+    ///
+    /// Mode 0 is 80x25, mode 1 is 80x50 if supported, other modes are up to
+    /// the device. Valid modes are `0..OutputMode::max_mode`, but some of
+    /// them may still be unsupported.
+    pub query_mode: uefi_fn_ptr!(this: &mut Self, mode_number: usize, columns: &mut usize, rows: &mut usize),
+
+    /// Switches to `mode_number`, clears the screen and puts the cursor at
+    /// (0, 0)
+    pub set_mode: uefi_fn_ptr!(this: &mut Self, mode_number: usize),
+
+    /// Sets the colors of text printed afterwards, see `Attribute`
+    pub set_attribute: uefi_fn_ptr!(this: &mut Self, attribute: Attribute),
 
     /// # Parameters
     ///
@@ -82,12 +396,57 @@ pub struct Output {
     /// 0)
     pub clear_screen: uefi_fn_ptr!(this: &mut Self),
 
-    pub set_cursor_position: usize,
-    pub enable_cursor:       usize,
+    /// Column and row are zero-based and have to fit in the current mode
+    pub set_cursor_position: uefi_fn_ptr!(this: &mut Self, column: usize, row: usize),
+
+    /// Makes the cursor visible or invisible
+    pub enable_cursor: uefi_fn_ptr!(this: &mut Self, visible: bool),
 
     pub mode: *const OutputMode,
 }
 
+/// EFI text colors, only the first 8 can be used as the background
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Color {
+    Black = 0,
+    Blue,
+    Green,
+    Cyan,
+    Red,
+    Magenta,
+    Brown,
+    LightGray,
+    DarkGray,
+    LightBlue,
+    LightGreen,
+    LightCyan,
+    LightRed,
+    LightMagenta,
+    Yellow,
+    White,
+}
+
+/// Foreground and background color, EFI_TEXT_ATTR
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(transparent)]
+pub struct Attribute(usize);
+
+impl Attribute {
+    /// Background colors above `LightGray` lose their brightness
+    pub const fn new(foreground: Color, background: Color) -> Self {
+        Self(foreground as usize | (background as usize & 0x7) << 4)
+    }
+
+    pub const fn foreground(self) -> u8 {
+        (self.0 & 0xF) as u8
+    }
+
+    pub const fn background(self) -> u8 {
+        (self.0 >> 4 & 0x7) as u8
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum Verification {
     None     = 0,
@@ -104,12 +463,75 @@ impl Verification {
 }
 
 impl Output {
+    /// Characters printed at once, the rest of `print_utf8`'s buffer is for
+    /// a `\r\n` and the null
+    const CHUNK: usize = 128;
+
     pub fn reset(&mut self, ver: Verification) -> Result<(), Error> {
         let f = self.reset.expect("buggy UEFI: simple_text::Output::reset is null");
         let result = unsafe { (f)(self, ver.to_bool()) };
         return result.ok_or_expect_errors(&[Error::DeviceError]);
     }
 
+    pub fn mode(&self) -> &OutputMode {
+        unsafe { &*self.mode }
+    }
+
+    /// Columns and rows of `mode`
+    pub fn query_mode(&mut self, mode: usize) -> Result<(usize, usize), Error> {
+        const ERRORS: &[Error] = &[Error::DeviceError, Error::Unsupported];
+
+        let f = self.query_mode.expect("buggy UEFI: simple_text::Output::query_mode is null");
+        let (mut columns, mut rows) = (0usize, 0usize);
+        let result = unsafe { (f)(self, mode, &mut columns, &mut rows) };
+        result.ok_or_expect_errors(ERRORS)?;
+        return Ok((columns, rows));
+    }
+
+    /// Also clears the screen
+    pub fn set_mode(&mut self, mode: usize) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[Error::DeviceError, Error::Unsupported];
+
+        let f = self.set_mode.expect("buggy UEFI: simple_text::Output::set_mode is null");
+        let result = unsafe { (f)(self, mode) };
+        return result.ok_or_expect_errors(ERRORS);
+    }
+
+    pub fn set_attribute(&mut self, attribute: Attribute) -> Result<(), Error> {
+        let f = self.set_attribute.expect("buggy UEFI: simple_text::Output::set_attribute is null");
+        let result = unsafe { (f)(self, attribute) };
+        return result.ok_or_expect_errors(&[Error::DeviceError]);
+    }
+
+    /// Fills the screen with the background color and moves the cursor to
+    /// (0, 0)
+    pub fn clear_screen(&mut self) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[Error::DeviceError, Error::Unsupported];
+
+        let f = self.clear_screen.expect("buggy UEFI: simple_text::Output::clear_screen is null");
+        let result = unsafe { (f)(self) };
+        return result.ok_or_expect_errors(ERRORS);
+    }
+
+    pub fn set_cursor_position(&mut self, column: usize, row: usize) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[Error::DeviceError, Error::Unsupported];
+
+        let f = self
+            .set_cursor_position
+            .expect("buggy UEFI: simple_text::Output::set_cursor_position is null");
+        let result = unsafe { (f)(self, column, row) };
+        return result.ok_or_expect_errors(ERRORS);
+    }
+
+    /// `Unsupported` if the device can't show or hide the cursor
+    pub fn enable_cursor(&mut self, visible: bool) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[Error::DeviceError, Error::Unsupported];
+
+        let f = self.enable_cursor.expect("buggy UEFI: simple_text::Output::enable_cursor is null");
+        let result = unsafe { (f)(self, visible) };
+        return result.ok_or_expect_errors(ERRORS);
+    }
+
     unsafe fn test_raw_utf16(&self, s: *const u16) -> Result<(), Error> {
         let f =
             self.test_string.expect("buggy UEFI: simple_text::Output::test_string is null");
@@ -122,43 +544,66 @@ impl Output {
             .output_string
             .expect("buggy UEFI: simple_text::Output::output_string is null");
         let result = unsafe { (f)(self, s) };
+
+        // Some glyphs were skipped, but the rest got printed
+        if result == RawStatus::from_warning(Warning::UnknownGlyph) {
+            return Ok(());
+        }
+
         return result.ok_or_expect_errors(&[Error::Unsupported, Error::DeviceError]);
     }
 
+    /// Prints a null-terminated chunk, characters the device can't show
+    /// are replaced with `?`
+    fn print_chunk(&mut self, chunk: &mut [u16]) -> Result<(), Error> {
+        debug_assert_eq!(chunk.last(), Some(&0));
+
+        // SAFETY: the chunk is null-terminated
+        if unsafe { self.test_raw_utf16(chunk.as_ptr()) }.is_err() {
+            for c in chunk.iter_mut() {
+                let single = [*c, 0];
+                // SAFETY: as above
+                if *c != 0 && unsafe { self.test_raw_utf16(single.as_ptr()) }.is_err() {
+                    *c = '?' as u16;
+                }
+            }
+        }
+
+        // SAFETY: as above
+        return unsafe { self.print_raw_utf16(chunk.as_ptr()) };
+    }
+
+    /// Prints `s` converted to UCS-2 in chunks, `\n` becomes `\r\n`.
+    /// Characters outside of the BMP are printed as `?`.
     pub fn print_utf8(&mut self, s: &str) -> Result<(), Error> {
-        let mut utf16_buf = [0u16; 256];
+        let mut buf = [0u16; Self::CHUNK + 3];
         let mut i = 0usize;
 
         for c in s.chars() {
-            if i == 250 {
-                utf16_buf[i] = 0; // insert the final null byte
-                unsafe {
-                    self.test_raw_utf16(utf16_buf.as_ptr())?;
-                    self.print_raw_utf16(utf16_buf.as_ptr())?;
-                }
+            if i >= Self::CHUNK {
+                buf[i] = 0;
+                self.print_chunk(&mut buf[..=i])?;
                 i = 0;
             }
 
             if c == '\n' {
-                utf16_buf[i] = b'\r' as u16;
+                buf[i] = '\r' as u16;
                 i += 1;
             }
 
-            utf16_buf[i] = if c as u32 > u16::MAX as u32 { '?' as u16 } else { c as u16 };
-
+            buf[i] = u16::try_from(c as u32).unwrap_or('?' as u16);
             i += 1;
         }
 
-        utf16_buf[i] = 0;
-        unsafe {
-            self.test_raw_utf16(utf16_buf.as_ptr())?;
-            self.print_raw_utf16(utf16_buf.as_ptr())?;
-        }
-
-        return Ok(());
+        buf[i] = 0;
+        return self.print_chunk(&mut buf[..=i]);
     }
 }
 
+impl crate::Protocol for Output {
+    const GUID: Guid = guid::Guid::EFI_SIMPLE_TEXT_OUTPUT_PROTOCOL;
+}
+
 impl fmt::Write for Output {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         match self.print_utf8(s) {
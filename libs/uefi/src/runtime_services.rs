@@ -1,29 +1,530 @@
 use super::*;
+use impl_bits::impl_bits;
 
+/// EFI Runtime Services Table, still usable after ExitBootServices. None of
+/// the functions are reentrant, calls have to be serialized by the caller.
 #[repr(C)]
 pub struct RuntimeServices {
     header: TableHeader,
 
-    get_time:                usize,
-    set_time:                usize,
-    get_wakeup_time:         usize,
-    set_wakeup_time:         usize,
-    set_virtual_address_map: usize,
-    convert_pointer:         usize,
+    /// `capabilities` is optional
+    get_time:        Option<extern "efiapi" fn(time: &mut Time, capabilities: Option<&mut TimeCapabilities>) -> RawStatus>,
+    set_time:        Option<extern "efiapi" fn(time: &Time) -> RawStatus>,
+    get_wakeup_time: Option<extern "efiapi" fn(enabled: &mut bool, pending: &mut bool, time: &mut Time) -> RawStatus>,
+    /// `time` is optional when disabling the alarm
+    set_wakeup_time: Option<extern "efiapi" fn(enable: bool, time: Option<&Time>) -> RawStatus>,
 
-    get_variable:           usize,
-    get_next_variable_name: usize,
-    set_variable:           usize,
+    /// ## Parameters
+    /// * MemoryMapSize - The size in bytes of VirtualMap.
+    /// * DescriptorSize - The size in bytes of an entry in the VirtualMap.
+    /// * DescriptorVersion - The version of the structure entries in
+    /// VirtualMap.
+    /// * VirtualMap - An array of memory descriptors which contain new
+    /// virtual address mapping information for all runtime ranges.
+    ///
+    /// ## Description
+    /// The SetVirtualAddressMap() function is used by the OS loader. The
+    /// function can only be called at runtime, and is called by the owner of
+    /// the system's memory map: i.e., the component which called
+    /// EFI_BOOT_SERVICES.ExitBootServices(). All events of type
+    /// EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE must be signaled before
+    /// SetVirtualAddressMap() returns.
+    ///
+    /// This call changes the addresses of the runtime components of the EFI
+    /// firmware to the new virtual addresses supplied in the VirtualMap. The
+    /// supplied VirtualMap must provide a new virtual address for every
+    /// entry in the memory map at ExitBootServices() that is marked as being
+    /// needed for runtime usage. All of the virtual address fields in the
+    /// VirtualMap must be aligned on 4 KiB boundaries.
+    ///
+    /// A virtual address map may only be applied one time. Once the runtime
+    /// system is in virtual mode, calls to this function return
+    /// EFI_UNSUPPORTED.
+    set_virtual_address_map: Option<
+        extern "efiapi" fn(
+            memory_map_size: usize,
+            descriptor_size: usize,
+            descriptor_version: u32,
+            virtual_map: *mut memory::Descriptor,
+        ) -> RawStatus,
+    >,
 
-    get_next_high_mono_count: usize,
+    /// Only valid during SetVirtualAddressMap(), from
+    /// EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE notifications
+    convert_pointer: Option<extern "efiapi" fn(debug_disposition: usize, address: &mut *const ()) -> RawStatus>,
+
+    /// ## Parameters
+    /// * VariableName - A Null-terminated string that is the name of the
+    /// vendor's variable.
+    /// * VendorGuid - A unique identifier for the vendor.
+    /// * Attributes - If not NULL, a pointer to the memory location to return
+    /// the attributes bitmask for the variable.
+    /// * DataSize - On input, the size in bytes of the return Data buffer. On
+    /// output the size of data returned in Data.
+    /// * Data - The buffer to return the contents of the variable. May be NULL
+    /// with a zero DataSize in order to determine the size buffer needed.
+    get_variable: Option<
+        extern "efiapi" fn(
+            variable_name: *const u16,
+            vendor_guid: &Guid,
+            attributes: Option<&mut VariableAttributes>,
+            data_size: &mut usize,
+            data: *mut u8,
+        ) -> RawStatus,
+    >,
+
+    /// ## Parameters
+    /// * VariableNameSize - The size of the VariableName buffer. The size must
+    /// be large enough to fit input string supplied in VariableName buffer.
+    /// * VariableName - On input, supplies the last VariableName that was
+    /// returned by GetNextVariableName(). On output, returns the
+    /// Null-terminated string of the current variable.
+    /// * VendorGuid - On input, supplies the last VendorGuid that was returned
+    /// by GetNextVariableName(). On output, returns the VendorGuid of the
+    /// current variable.
+    get_next_variable_name: Option<
+        extern "efiapi" fn(
+            variable_name_size: &mut usize,
+            variable_name: *mut u16,
+            vendor_guid: &mut Guid,
+        ) -> RawStatus,
+    >,
+
+    /// A zero `data_size` deletes the variable, unless appending
+    set_variable: Option<
+        extern "efiapi" fn(
+            variable_name: *const u16,
+            vendor_guid: &Guid,
+            attributes: VariableAttributes,
+            data_size: usize,
+            data: *const u8,
+        ) -> RawStatus,
+    >,
+
+    get_next_high_mono_count: Option<extern "efiapi" fn(high_count: &mut u32) -> RawStatus>,
+
+    /// Doesn't return. `reset_data` is a null-terminated string, optionally
+    /// followed by binary data.
+    reset_system: Option<
+        extern "efiapi" fn(
+            reset_type: ResetType,
+            reset_status: RawStatus,
+            data_size: usize,
+            reset_data: *const u8,
+        ),
+    >,
 
-    reset_system: usize,
     /* UEFI 2.0 */
-    /*
-    update_capsule,
-    query_capsule_capabilities,
-    query_variable_info,
-    */
+    update_capsule: Option<
+        extern "efiapi" fn(
+            capsule_header_array: *const &CapsuleHeader,
+            capsule_count: usize,
+            scatter_gather_list: u64,
+        ) -> RawStatus,
+    >,
+    query_capsule_capabilities: Option<
+        extern "efiapi" fn(
+            capsule_header_array: *const &CapsuleHeader,
+            capsule_count: usize,
+            maximum_capsule_size: &mut u64,
+            reset_type: &mut u32,
+        ) -> RawStatus,
+    >,
+    query_variable_info: Option<
+        extern "efiapi" fn(
+            attributes: VariableAttributes,
+            maximum_variable_storage_size: &mut u64,
+            remaining_variable_storage_size: &mut u64,
+            maximum_variable_size: &mut u64,
+        ) -> RawStatus,
+    >,
+}
+
+/// EFI_TIME, as returned by GetTime() and stored in file metadata
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct Time {
+    pub year:       u16,
+    pub month:      u8,
+    pub day:        u8,
+    pub hour:       u8,
+    pub minute:     u8,
+    pub second:     u8,
+    _pad1:          u8,
+    pub nanosecond: u32,
+
+    /// Offset from UTC in minutes, 0x07FF if unspecified
+    pub time_zone:  i16,
+    pub daylight:   u8,
+    _pad2:          u8,
+}
+
+impl Time {
+    pub const UNSPECIFIED_TIMEZONE: i16 = 0x07FF;
+
+    pub const fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Self {
+        Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            _pad1: 0,
+            nanosecond: 0,
+            time_zone: Self::UNSPECIFIED_TIMEZONE,
+            daylight: 0,
+            _pad2: 0,
+        }
+    }
+}
+
+/// EFI_TIME_CAPABILITIES
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct TimeCapabilities {
+    /// Ticks per second of the real time clock
+    pub resolution:   u32,
+
+    /// Error in parts per million
+    pub accuracy:     u32,
+
+    /// Whether setting the time clears the sub-second part
+    pub sets_to_zero: bool,
+}
+
+/// EFI_RESET_TYPE
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum ResetType {
+    Cold = 0,
+    Warm,
+    Shutdown,
+    PlatformSpecific,
+}
+
+impl ResetType {
+    pub fn from_int(x: u32) -> Option<Self> {
+        let x = match x {
+            0 => Self::Cold,
+            1 => Self::Warm,
+            2 => Self::Shutdown,
+            3 => Self::PlatformSpecific,
+            _ => return None,
+        };
+
+        return Some(x);
+    }
+}
+
+#[repr(transparent)]
+pub struct VariableAttributes(u32);
+
+impl VariableAttributes {
+    pub const fn new() -> Self { Self(0) }
+}
+
+impl_bits! {
+    VariableAttributes = {
+        non_volatile = 0,
+        bootservice_access = 1,
+        runtime_access = 2,
+        hardware_error_record = 3,
+        authenticated_write_access = 4,
+        time_based_authenticated_write_access = 5,
+        append_write = 6,
+        enhanced_authenticated_access = 7,
+    }
+}
+
+/// EFI_RT_PROPERTIES_TABLE, which runtime services still work after
+/// ExitBootServices
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct RtPropertiesTable {
+    pub version:   u16,
+    pub length:    u16,
+    pub supported: RtSupported,
+}
+
+impl RtPropertiesTable {
+    pub const VERSION: u16 = 1;
+}
+
+#[repr(transparent)]
+pub struct RtSupported(u32);
+
+impl RtSupported {
+    pub const fn new() -> Self { Self(0) }
+
+    pub const fn as_u32(self) -> u32 { self.0 }
+}
+
+impl_bits! {
+    RtSupported = {
+        get_time = 0,
+        set_time = 1,
+        get_wakeup_time = 2,
+        set_wakeup_time = 3,
+        get_variable = 4,
+        get_next_variable_name = 5,
+        set_variable = 6,
+        set_virtual_address_map = 7,
+        convert_pointer = 8,
+        get_next_high_monotonic_count = 9,
+        reset_system = 10,
+        update_capsule = 11,
+        query_capsule_capabilities = 12,
+        query_variable_info = 13,
+    }
+}
+
+/// Returned by QueryVariableInfo(), for variables with the given attributes
+#[derive(Clone, Copy, Debug)]
+pub struct VariableInfo {
+    pub maximum_storage_size:   u64,
+    pub remaining_storage_size: u64,
+    pub maximum_variable_size:  u64,
+}
+
+/// EFI_CAPSULE_HEADER, followed by the capsule image
+#[derive(Debug)]
+#[repr(C)]
+pub struct CapsuleHeader {
+    pub capsule_guid:       Guid,
+    pub header_size:        u32,
+    pub flags:              u32,
+    pub capsule_image_size: u32,
+}
+
+/// Variable names are UCS-2, like the rest of UEFI
+fn name_to_ucs2(name: &str, buf: &mut [u16]) -> Result<(), Error> {
+    let mut i = 0usize;
+    for c in name.chars() {
+        if c as u32 > u16::MAX as u32 || c == '\0' || i + 1 >= buf.len() {
+            return Err(Error::InvalidParameter);
+        }
+        buf[i] = c as u16;
+        i += 1;
+    }
+
+    buf[i] = 0;
+    return Ok(());
+}
+
+impl RuntimeServices {
+    pub fn get_time(&self) -> Result<(Time, TimeCapabilities), Error> {
+        let f = self.get_time.expect("buggy UEFI: RuntimeServices::get_time is null");
+        let mut time = Time::new(0, 0, 0, 0, 0, 0);
+        let mut capabilities = TimeCapabilities { resolution: 0, accuracy: 0, sets_to_zero: false };
+        (f)(&mut time, Some(&mut capabilities))
+            .ok_or_expect_errors(&[Error::InvalidParameter, Error::DeviceError, Error::Unsupported])?;
+        return Ok((time, capabilities));
+    }
+
+    pub fn set_time(&self, time: &Time) -> Result<(), Error> {
+        let f = self.set_time.expect("buggy UEFI: RuntimeServices::set_time is null");
+        return (f)(time).ok_or_expect_errors(&[Error::InvalidParameter, Error::DeviceError, Error::Unsupported]);
+    }
+
+    /// The alarm, if it's enabled and whether it has gone off
+    pub fn get_wakeup_time(&self) -> Result<(Option<Time>, bool), Error> {
+        const ERRORS: &[Error] = &[Error::InvalidParameter, Error::DeviceError, Error::Unsupported];
+
+        let f = self.get_wakeup_time.expect("buggy UEFI: RuntimeServices::get_wakeup_time is null");
+        let mut enabled = false;
+        let mut pending = false;
+        let mut time = Time::new(0, 0, 0, 0, 0, 0);
+        (f)(&mut enabled, &mut pending, &mut time).ok_or_expect_errors(ERRORS)?;
+
+        let time = if enabled { Some(time) } else { None };
+        return Ok((time, pending));
+    }
+
+    /// `None` disables the alarm
+    pub fn set_wakeup_time(&self, time: Option<&Time>) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[Error::InvalidParameter, Error::DeviceError, Error::Unsupported];
+
+        let f = self.set_wakeup_time.expect("buggy UEFI: RuntimeServices::set_wakeup_time is null");
+        return (f)(time.is_some(), time).ok_or_expect_errors(ERRORS);
+    }
+
+    /// Moves the runtime services to the virtual addresses in `map`, which
+    /// has to describe every runtime region. Can only be called once, after
+    /// ExitBootServices.
+    ///
+    /// ## Safety
+    /// The new mappings have to be in place before any runtime service is
+    /// called again, and the old ones can't be used anymore
+    pub unsafe fn set_virtual_address_map(&self, map: &mut [memory::Descriptor]) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[
+            Error::Unsupported,
+            Error::NoMapping,
+            Error::NotFound,
+            Error::InvalidParameter,
+        ];
+
+        let f = self
+            .set_virtual_address_map
+            .expect("buggy UEFI: RuntimeServices::set_virtual_address_map is null");
+        let size = core::mem::size_of_val(map);
+        let descriptor_size = core::mem::size_of::<memory::Descriptor>();
+        return (f)(size, descriptor_size, memory::DESCRIPTOR_VERSION, map.as_mut_ptr()).ok_or_expect_errors(ERRORS);
+    }
+
+    /// Converts a physical pointer to its new virtual address, `optional`
+    /// allows null pointers
+    ///
+    /// ## Safety
+    /// Can only be called from an EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE
+    /// notification
+    pub unsafe fn convert_pointer(&self, pointer: &mut *const (), optional: bool) -> Result<(), Error> {
+        const OPTIONAL_PTR: usize = 1;
+
+        let f = self.convert_pointer.expect("buggy UEFI: RuntimeServices::convert_pointer is null");
+        let disposition = if optional { OPTIONAL_PTR } else { 0 };
+        return (f)(disposition, pointer).ok_or_expect_errors(&[Error::NotFound, Error::InvalidParameter]);
+    }
+
+    /// Reads a variable into `buf`, returns its attributes and size.
+    /// `BufferTooSmall` if it doesn't fit.
+    pub fn get_variable(
+        &self,
+        name: &str,
+        vendor: &Guid,
+        buf: &mut [u8],
+    ) -> Result<(VariableAttributes, usize), Error> {
+        const ERRORS: &[Error] = &[
+            Error::NotFound,
+            Error::BufferTooSmall,
+            Error::InvalidParameter,
+            Error::DeviceError,
+            Error::SecurityViolation,
+            Error::Unsupported,
+        ];
+
+        let mut ucs2 = [0u16; 256];
+        name_to_ucs2(name, &mut ucs2)?;
+
+        let f = self.get_variable.expect("buggy UEFI: RuntimeServices::get_variable is null");
+        let mut attributes = VariableAttributes::new();
+        let mut size = buf.len();
+        (f)(ucs2.as_ptr(), vendor, Some(&mut attributes), &mut size, buf.as_mut_ptr())
+            .ok_or_expect_errors(ERRORS)?;
+
+        assert!(size <= buf.len());
+        return Ok((attributes, size));
+    }
+
+    /// Advances `name` and `vendor` to the next variable, start with an empty
+    /// `name`. `NotFound` after the last one. Returns the length of `name`
+    /// without the null terminator.
+    pub fn get_next_variable_name(&self, name: &mut [u16], vendor: &mut Guid) -> Result<usize, Error> {
+        const ERRORS: &[Error] = &[
+            Error::NotFound,
+            Error::BufferTooSmall,
+            Error::DeviceError,
+            Error::InvalidParameter,
+            Error::Unsupported,
+        ];
+
+        let f = self
+            .get_next_variable_name
+            .expect("buggy UEFI: RuntimeServices::get_next_variable_name is null");
+        let mut size = core::mem::size_of_val(name);
+        (f)(&mut size, name.as_mut_ptr(), vendor).ok_or_expect_errors(ERRORS)?;
+
+        let len = name.iter().position(|&c| c == 0).expect("variable name is not null-terminated");
+        return Ok(len);
+    }
+
+    /// Empty `data` deletes the variable, unless `append_write` is set
+    pub fn set_variable(
+        &self,
+        name: &str,
+        vendor: &Guid,
+        attributes: VariableAttributes,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[
+            Error::InvalidParameter,
+            Error::OutOfResources,
+            Error::DeviceError,
+            Error::WriteProtected,
+            Error::SecurityViolation,
+            Error::NotFound,
+            Error::Unsupported,
+        ];
+
+        let mut ucs2 = [0u16; 256];
+        name_to_ucs2(name, &mut ucs2)?;
+
+        let f = self.set_variable.expect("buggy UEFI: RuntimeServices::set_variable is null");
+        return (f)(ucs2.as_ptr(), vendor, attributes, data.len(), data.as_ptr()).ok_or_expect_errors(ERRORS);
+    }
+
+    /// Upper 32 bits of the monotonic counter, incremented on every boot
+    pub fn get_next_high_monotonic_count(&self) -> Result<u32, Error> {
+        let f = self
+            .get_next_high_mono_count
+            .expect("buggy UEFI: RuntimeServices::get_next_high_mono_count is null");
+        let mut count = 0u32;
+        (f)(&mut count).ok_or_expect_errors(&[Error::DeviceError, Error::Unsupported])?;
+        return Ok(count);
+    }
+
+    /// Resets or shuts down the machine, `status` is logged by the firmware
+    pub fn reset_system(&self, reset_type: ResetType, status: RawStatus) -> ! {
+        let f = self.reset_system.expect("buggy UEFI: RuntimeServices::reset_system is null");
+        (f)(reset_type, status, 0, core::ptr::null());
+        panic!("buggy UEFI: ResetSystem() returned");
+    }
+
+    /// Passes capsules to the firmware, like firmware updates
+    ///
+    /// ## Safety
+    /// `scatter_gather_list` has to be the physical address of the block
+    /// descriptors for the capsules, or zero when they are not persisted
+    /// across a reset
+    pub unsafe fn update_capsule(&self, capsules: &[&CapsuleHeader], scatter_gather_list: u64) -> Result<(), Error> {
+        const ERRORS: &[Error] = &[
+            Error::InvalidParameter,
+            Error::DeviceError,
+            Error::Unsupported,
+            Error::OutOfResources,
+        ];
+
+        let f = self.update_capsule.expect("buggy UEFI: RuntimeServices::update_capsule is null");
+        return (f)(capsules.as_ptr(), capsules.len(), scatter_gather_list).ok_or_expect_errors(ERRORS);
+    }
+
+    /// Maximum size of the capsules and the reset they need
+    pub fn query_capsule_capabilities(&self, capsules: &[&CapsuleHeader]) -> Result<(u64, ResetType), Error> {
+        const ERRORS: &[Error] = &[Error::Unsupported, Error::OutOfResources, Error::InvalidParameter];
+
+        let f = self
+            .query_capsule_capabilities
+            .expect("buggy UEFI: RuntimeServices::query_capsule_capabilities is null");
+        let mut maximum_size = 0u64;
+        let mut reset_type = 0u32;
+        (f)(capsules.as_ptr(), capsules.len(), &mut maximum_size, &mut reset_type).ok_or_expect_errors(ERRORS)?;
+
+        let reset_type = ResetType::from_int(reset_type).expect("buggy UEFI: invalid reset type");
+        return Ok((maximum_size, reset_type));
+    }
+
+    pub fn query_variable_info(&self, attributes: VariableAttributes) -> Result<VariableInfo, Error> {
+        let f = self.query_variable_info.expect("buggy UEFI: RuntimeServices::query_variable_info is null");
+        let mut info = VariableInfo { maximum_storage_size: 0, remaining_storage_size: 0, maximum_variable_size: 0 };
+        (f)(
+            attributes,
+            &mut info.maximum_storage_size,
+            &mut info.remaining_storage_size,
+            &mut info.maximum_variable_size,
+        )
+        .ok_or_expect_errors(&[Error::InvalidParameter, Error::Unsupported])?;
+        return Ok(info);
+    }
 }
 
 impl Verify for RuntimeServices {
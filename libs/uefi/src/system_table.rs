@@ -26,7 +26,7 @@ pub struct SystemTable {
     pub console_in_handle: Handle,
     /// A pointer to the EFI_SIMPLE_TEXT_INPUT_PROTOCOL interface that is
     /// associated with ConsoleInHandle
-    pub con_in:            usize,
+    pub con_in:            Option<NonNull<protocols::simple_text::Input>>,
 
     pub console_out_handle: Handle,
     pub con_out:            Option<NonNull<protocols::simple_text::Output>>,
@@ -39,9 +39,9 @@ pub struct SystemTable {
     pub con_err:            Option<NonNull<protocols::simple_text::Output>>,
 
     /// A pointer to the EFI Runtime Services Table. See Section 4.5.
-    pub runtime_services: *const RuntimeServices,
+    pub(crate) _runtime_services: Option<NonNull<RuntimeServices>>,
     /// A pointer to the EFI Boot Services Table. See Section 4.4.
-    _boot_services:    Option<NonNull<BootServices>>,
+    pub(crate) _boot_services:    Option<NonNull<BootServices>>,
 
     /// The number of system configuration tables in the buffer
     /// ConfigurationTable.
@@ -72,6 +72,13 @@ impl SystemTable {
         unsafe { &*core::ptr::slice_from_raw_parts(self.config_table, sz) }
     }
 
+    /// Unlike boot services, these stay after exit_boot_services. After
+    /// SetVirtualAddressMap the firmware updates the pointer to the new
+    /// address.
+    pub fn runtime_services(&self) -> Option<&RuntimeServices> {
+        self._runtime_services.map(|p| unsafe { p.as_ref() })
+    }
+
     // This is a method, so that the lifetime of BootServices will get
     // bound to SystemTable, forbidding accidental usage after exit_boot_services.
     pub fn boot_services(&mut self) -> Option<&mut BootServices> {
@@ -84,7 +91,7 @@ impl SystemTable {
     /// `memory::Type::BootServicesData` can be treated as free memory.
     /// This function sets fields of the EFI System Table to 0, like
     /// `console_in_handle`, `con_in` and similar and also `boot_services`.
-    /// Also, since the table is changed CRC checksum must be recomputed.
+    /// Since the table is changed, its CRC32 is recomputed.
     pub fn exit_boot_services(
         &mut self,
         handle: ImageHandle,
@@ -97,13 +104,63 @@ impl SystemTable {
         unsafe { bservices.exit_boot_services(handle, key)?; }
 
         self._boot_services = None;
-        self.con_in = 0;
+        self.con_in = None;
         self.con_out = None;
         self.con_err = None;
         self.console_in_handle = Handle(0);
         self.console_out_handle = Handle(0);
         self.console_err_handle = Handle(0);
 
+        // SAFETY: the firmware made the table `header_size` bytes long
+        self.header.crc32 = unsafe { self.compute_crc32() };
+
         return Ok(());
     }
+
+    /// Reads the memory map and terminates boot services with its key.
+    /// The map can change in between, for example when the firmware handles
+    /// an event, so it's read again until the key is accepted, without
+    /// allocating anything. Buffer for the map is leaked `LoaderData`, so it
+    /// stays valid after the firmware is gone.
+    ///
+    /// The map is checked before ExitBootServices, so boot services are
+    /// still there if this returns an error.
+    pub fn exit_boot_services_with_map(
+        &mut self,
+        handle: ImageHandle,
+    ) -> Result<memory::MemoryMap<'static>, memory::MapError> {
+        const ATTEMPTS: usize = 8;
+
+        /// Our allocation can split a free region, the rest is for changes
+        /// made by the firmware before ExitBootServices
+        const EXTRA_DESCRIPTORS: usize = 8;
+
+        let bservices = self.boot_services().expect("boot services are null");
+        let (size, descriptor_size) = bservices.memory_map_size()?;
+        let pages = (size + EXTRA_DESCRIPTORS * descriptor_size).div_ceil(4096);
+        let pages = bservices.allocate_pages(AllocateType::AnyPages, memory::Type::LoaderData, pages)?.leak();
+
+        let (ptr, len) = (pages.as_mut_ptr().cast::<u64>(), pages.len() / 8);
+
+        for _ in 0..ATTEMPTS {
+            // SAFETY: pages are aligned and zeroed, the map from the attempt
+            // before was dropped
+            let buf = unsafe { core::slice::from_raw_parts_mut(ptr, len) };
+            let bservices = self.boot_services().expect("boot services are null");
+            let raw = bservices.get_memory_map_raw(buf)?;
+            if raw.descriptor_version != memory::DESCRIPTOR_VERSION {
+                return Err(memory::MapError::UnsupportedVersion(raw.descriptor_version));
+            }
+            let map = memory::MemoryMap::new(&mut buf[..raw.size / 8], raw.descriptor_size)?;
+
+            match self.exit_boot_services(ImageHandle(handle.as_handle()), raw.key) {
+                Ok(()) => return Ok(map),
+                // The map changed since we read it
+                Err(Error::InvalidParameter) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        return Err(memory::MapError::Firmware(Error::InvalidParameter));
+    }
 }
@@ -0,0 +1,60 @@
+use uefi::{crc32, Crc32, TableHeader, Verify, VerifyError};
+
+#[test]
+fn known_values() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414F_A339);
+
+    let pieces = Crc32::new().update(b"1234").update(b"").update(b"56789").finish();
+    assert_eq!(pieces, 0xCBF4_3926);
+}
+
+#[repr(C)]
+struct Table {
+    header:  TableHeader,
+    payload: [u64; 3],
+}
+
+impl Verify for Table {
+    const SIGNATURE: u64 = u64::from_le_bytes(*b"TESTTABL");
+
+    fn get_header(&self) -> &TableHeader {
+        &self.header
+    }
+}
+
+fn table() -> Table {
+    // SAFETY: all zeroes is a valid header
+    let mut table = Table { header: unsafe { core::mem::zeroed() }, payload: [1, 2, 3] };
+    table.header.signature = Table::SIGNATURE;
+    table.header.revision = uefi::SPECIFICATION_VERSION;
+    table.header.header_size = core::mem::size_of::<Table>() as u32;
+    // SAFETY: `header_size` is the size of `Table`
+    table.header.crc32 = unsafe { table.compute_crc32() };
+    table
+}
+
+#[test]
+fn verify() {
+    // SAFETY: `header_size` is at most the size of `Table` in all of them
+    let good = table();
+    assert_eq!(unsafe { good.verify() }, Ok(()));
+    assert_ne!(good.header.crc32, 0);
+
+    let mut bad = table();
+    bad.payload[2] ^= 1;
+    assert_eq!(unsafe { bad.verify() }, Err(VerifyError::Crc32));
+
+    let mut bad = table();
+    bad.header.crc32 ^= 1;
+    assert_eq!(unsafe { bad.verify() }, Err(VerifyError::Crc32));
+
+    let mut bad = table();
+    bad.header.signature = 0;
+    assert_eq!(unsafe { bad.verify() }, Err(VerifyError::Signature));
+
+    let mut bad = table();
+    bad.header.header_size -= 8;
+    assert_eq!(unsafe { bad.verify() }, Err(VerifyError::TableSize));
+}
@@ -0,0 +1,115 @@
+use uefi::protocols::device_path::*;
+use uefi::Guid;
+
+fn node(typ: u8, subtype: u8, data: &[u8]) -> Vec<u8> {
+    let len = (data.len() + 4) as u16;
+    let mut node = vec![typ, subtype];
+    node.extend_from_slice(&len.to_le_bytes());
+    node.extend_from_slice(data);
+    node
+}
+
+fn end() -> Vec<u8> {
+    node(0x7F, 0xFF, &[])
+}
+
+fn acpi(hid: u32, uid: u32) -> Vec<u8> {
+    node(0x02, 0x01, &[hid.to_le_bytes(), uid.to_le_bytes()].concat())
+}
+
+fn file(path: &str) -> Vec<u8> {
+    let units: Vec<u8> = path.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect();
+    node(0x04, 0x04, &units)
+}
+
+fn hard_drive(partition: u32, start: u64, size: u64, signature: [u8; 16], signature_type: u8) -> Vec<u8> {
+    let mut data = partition.to_le_bytes().to_vec();
+    data.extend_from_slice(&start.to_le_bytes());
+    data.extend_from_slice(&size.to_le_bytes());
+    data.extend_from_slice(&signature);
+    data.extend_from_slice(&[0x02, signature_type]);
+    node(0x04, 0x01, &data)
+}
+
+const PARTITION_GUID: [u8; 16] = [
+    0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+];
+
+fn text(bytes: &[u8]) -> String {
+    Nodes::new(bytes).to_string()
+}
+
+#[test]
+fn guid_text() {
+    let guid = Guid::from_bytes(PARTITION_GUID);
+    assert_eq!(guid.to_string(), "C12A7328-F81F-11D2-BA4B-00A0C93EC93B");
+}
+
+#[test]
+fn disk_path() {
+    let path = [
+        acpi(0x0A03_41D0, 0),
+        node(0x01, 0x01, &[0x02, 0x1F]),
+        node(0x03, 0x12, &[0, 0, 0xFF, 0xFF, 0, 0]),
+        hard_drive(1, 0x800, 0x10_0000, PARTITION_GUID, 2),
+        file("\\EFI\\BOOT\\BOOTX64.EFI"),
+        end(),
+    ]
+    .concat();
+
+    let nodes: Vec<Node> = Nodes::new(&path).collect();
+    assert_eq!(nodes.len(), 5);
+    assert_eq!(nodes[0].node_type(), Some(NodeType::Acpi));
+    assert_eq!(nodes[1].kind(), NodeKind::Pci { device: 0x1F, function: 2 });
+    assert_eq!(nodes[3].kind(), NodeKind::HardDrive {
+        partition: 1,
+        start:     0x800,
+        size:      0x10_0000,
+        signature: PartitionSignature::Gpt(Guid::from_bytes(PARTITION_GUID)),
+    });
+    assert_eq!(nodes[4].file_path().unwrap().collect::<String>(), "\\EFI\\BOOT\\BOOTX64.EFI");
+
+    assert_eq!(
+        text(&path),
+        "PciRoot(0x0)/Pci(0x1F,0x2)/Sata(0x0,0xFFFF,0x0)/\
+         HD(1,GPT,C12A7328-F81F-11D2-BA4B-00A0C93EC93B,0x800,0x100000)/\\EFI\\BOOT\\BOOTX64.EFI",
+    );
+}
+
+#[test]
+fn other_nodes() {
+    let mut nvme = 1u32.to_le_bytes().to_vec();
+    nvme.extend_from_slice(&[0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+    let path = [acpi(0x0A08_41D0, 1), node(0x03, 0x17, &nvme), end()].concat();
+    assert_eq!(text(&path), "PcieRoot(0x1)/NVMe(0x1,00-11-22-33-44-55-66-77)");
+
+    let mut mac = [0u8; 33];
+    mac[..6].copy_from_slice(&[0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+    let mut ipv4 = [10, 0, 2, 15, 10, 0, 2, 2].to_vec();
+    ipv4.extend_from_slice(&[0x44, 0, 0x43, 0, 17, 0, 0]);
+    let path = [node(0x03, 0x0B, &mac), node(0x03, 0x0C, &ipv4), end()].concat();
+    assert_eq!(text(&path), "MAC(525400123456,0x0)/IPv4(10.0.2.2,UDP,DHCP,10.0.2.15)");
+
+    let mbr = hard_drive(2, 63, 1000, [0xEF, 0xBE, 0xAD, 0xDE, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 1);
+    let path = [acpi(0x1234_5678, 3), mbr, node(0x05, 0x01, &[0xAB]), end()].concat();
+    assert_eq!(text(&path), "Acpi(0x12345678,0x3)/HD(2,MBR,0xDEADBEEF,0x3F,0x3E8)/Path(5,1,AB)");
+}
+
+#[test]
+fn instances_and_malformed() {
+    let path = [acpi(0x0A03_41D0, 0), node(0x7F, 0x01, &[]), acpi(0x0A03_41D0, 1), end()].concat();
+    assert_eq!(text(&path), "PciRoot(0x0),PciRoot(0x1)");
+
+    // Too short for a PCI node
+    assert_eq!(text(&[node(0x01, 0x01, &[1]), end()].concat()), "Path(1,1,01)");
+
+    // Length past the end of the buffer stops the iteration
+    let mut path = [acpi(0x0A03_41D0, 0), file("\\a")].concat();
+    let len = path.len();
+    path[len - 8] = 0xFF;
+    assert_eq!(text(&path), "PciRoot(0x0)");
+
+    // Nothing after the end node
+    let path = [end(), acpi(0x0A03_41D0, 0)].concat();
+    assert_eq!(Nodes::new(&path).count(), 0);
+}
@@ -0,0 +1,156 @@
+use std::cell::Cell;
+use std::ptr::NonNull;
+use std::rc::Rc;
+
+use uefi::memory::{self, Descriptor, MapError};
+use uefi::mock::MockFirmware;
+use uefi::protocols::gop::{GraphicsOutput, PixelFormat};
+use uefi::{Error, Verify};
+
+fn conventional(phys_start: u64, pages: u64) -> Descriptor {
+    Descriptor::new(memory::Type::Conventional, phys_start, pages, memory::Attributes::new())
+}
+
+#[test]
+fn exit_with_stale_keys() {
+    let mut fw = MockFirmware::new();
+    fw.set_descriptor_format(56, memory::DESCRIPTOR_VERSION);
+
+    // Every read gets a new key, the firmware accepts only the third one
+    let reads = Rc::new(Cell::new(0u64));
+    let counter = reads.clone();
+    fw.on_memory_map(move || {
+        counter.set(counter.get() + 1);
+        Ok((vec![conventional(0x20_0000, 16), conventional(0x1000, 1)], counter.get()))
+    });
+    let exits = Rc::new(Cell::new(0));
+    let counter = exits.clone();
+    fw.on_exit_boot_services(move |key| {
+        counter.set(counter.get() + 1);
+        if key == 4 { Ok(()) } else { Err(Error::InvalidParameter) }
+    });
+
+    let handle = fw.image_handle();
+    let st = fw.system_table();
+    // SAFETY: the mock sets `header_size` to the size of the table
+    assert_eq!(unsafe { st.verify() }, Ok(()));
+    let map = st.exit_boot_services_with_map(handle).unwrap();
+
+    // One read for the size, then three attempts
+    assert_eq!(reads.get(), 4);
+    assert_eq!(exits.get(), 3);
+    assert_eq!(map.len(), 2);
+    assert_eq!(map[0].phys_start, 0x1000);
+    assert_eq!(map[1].phys_start, 0x20_0000);
+    assert_eq!(map[1].pages, 16);
+
+    assert!(st.boot_services().is_none());
+    assert!(st.con_out.is_none());
+    // SAFETY: as above
+    assert_eq!(unsafe { st.verify() }, Ok(()));
+    assert!(fw.exited_boot_services());
+    // The map buffer
+    assert_eq!(fw.allocations(), 1);
+}
+
+#[test]
+fn exit_map_errors() {
+    let mut fw = MockFirmware::new();
+    fw.on_exit_boot_services(|_| Err(Error::InvalidParameter));
+    let handle = fw.image_handle();
+    let result = fw.system_table().exit_boot_services_with_map(handle);
+    assert_eq!(result.err(), Some(MapError::Firmware(Error::InvalidParameter)));
+    assert!(!fw.exited_boot_services());
+    drop(fw);
+
+    let mut fw = MockFirmware::new();
+    fw.set_descriptor_format(48, 2);
+    let handle = fw.image_handle();
+    let result = fw.system_table().exit_boot_services_with_map(handle);
+    assert_eq!(result.err(), Some(MapError::UnsupportedVersion(2)));
+    drop(fw);
+
+    let mut fw = MockFirmware::new();
+    fw.on_memory_map(|| Ok((vec![conventional(0x1000, 4), conventional(0x3000, 1)], 1)));
+    let handle = fw.image_handle();
+    let result = fw.system_table().exit_boot_services_with_map(handle);
+    assert_eq!(result.err(), Some(MapError::Overlap(0x3000)));
+    // A bad map is caught while boot services are still there
+    assert!(!fw.exited_boot_services());
+    drop(fw);
+
+    let mut fw = MockFirmware::new();
+    fw.on_allocate_pages(|_, _| Err(Error::OutOfResources));
+    let handle = fw.image_handle();
+    let result = fw.system_table().exit_boot_services_with_map(handle);
+    assert_eq!(result.err(), Some(MapError::Firmware(Error::OutOfResources)));
+}
+
+#[test]
+fn memory_map_and_pool() {
+    let mut fw = MockFirmware::new();
+    let bs = fw.system_table().boot_services().unwrap();
+
+    let (size, descriptor_size) = bs.memory_map_size().unwrap();
+    assert_eq!((size, descriptor_size), (3 * 48, 48));
+    let mut buf = [0u64; 3 * 6];
+    let (_, descriptors) = bs.get_memory_map(&mut buf).unwrap();
+    let types: Vec<_> = descriptors.map(|d| memory::Type::from_int(d.typ)).collect();
+    assert_eq!(types, [
+        Some(memory::Type::Conventional),
+        Some(memory::Type::Conventional),
+        Some(memory::Type::RuntimeServicesData),
+    ]);
+    let mut small = [0u64; 6];
+    assert_eq!(bs.get_memory_map(&mut small).err(), Some(Error::BufferTooSmall));
+
+    let pool = bs.allocate_pool(memory::Type::LoaderData, 100).unwrap().leak();
+    assert_eq!(pool.len(), 100);
+    assert_eq!(fw.allocations(), 1);
+
+    let bs = fw.system_table().boot_services().unwrap();
+    // SAFETY: allocated above
+    unsafe { bs.free_pool(NonNull::from(pool).cast()).unwrap() };
+    assert_eq!(fw.allocations(), 0);
+}
+
+#[test]
+fn gop_modes() {
+    let mut fw = MockFirmware::new();
+    fw.add_gop_mode(640, 480, PixelFormat::Bgrr8bpc);
+    fw.add_gop_mode(1024, 768, PixelFormat::Rgbr8bpc);
+    fw.on_set_mode(|mode| if mode == 1 { Err(Error::DeviceError) } else { Ok(()) });
+
+    let bs = fw.system_table().boot_services().unwrap();
+    let gop = bs.locate_protocol_mut::<GraphicsOutput>().unwrap();
+    assert_eq!(gop.mode().max_mode, 2);
+    assert_eq!(gop.mode().info.unwrap().horizontal_res, 640);
+
+    let info = gop.query_mode(1).unwrap();
+    assert_eq!((info.horizontal_res, info.vertical_res), (1024, 768));
+    assert_eq!(info.pixel_format(), Some(PixelFormat::Rgbr8bpc));
+    assert_eq!(gop.query_mode(2).err(), Some(Error::InvalidParameter));
+
+    assert_eq!(gop.set_mode(1), Err(Error::DeviceError));
+    assert_eq!(gop.set_mode(5), Err(Error::Unsupported));
+    assert_eq!(gop.mode().mode, 0);
+    assert_eq!(fw.framebuffer().len(), 640 * 480);
+
+    let handle = fw.image_handle().as_handle();
+    let bs = fw.system_table().boot_services().unwrap();
+    assert!(bs.handle_protocol::<GraphicsOutput>(handle).is_ok());
+}
+
+#[test]
+fn console() {
+    let mut fw = MockFirmware::new();
+    let out = fw.system_table().con_out.unwrap();
+    // SAFETY: the firmware outlives it
+    let out = unsafe { &mut *out.as_ptr() };
+    out.print_utf8("Hello, ").unwrap();
+    out.print_utf8("żółw\n").unwrap();
+    assert!(fw.console().starts_with("Hello, żółw"));
+
+    out.clear_screen().unwrap();
+    assert_eq!(fw.console(), "");
+}
@@ -0,0 +1,154 @@
+//! Console opened from the boot menu, before ExitBootServices. It draws on
+//! the framebuffer and reads keys from `SystemTable::con_in`.
+
+use core::fmt::Write;
+use arrayvec::{ArrayVec, ArrayVecSized};
+use bootinfo::Bootinfo;
+use uefi::protocols::gop::GraphicsOutput;
+use uefi::protocols::simple_text::{Input, InputKey, ScanCode};
+
+use crate::Size;
+
+const HELP: &str = "\
+help      list the commands
+cmdline   edit the kernel command line
+modes     list the graphics modes
+mode N    switch to the graphics mode N
+memmap    dump the memory map
+boot      continue booting
+";
+
+type Line = ArrayVecSized<u8, 128>;
+
+/// Runs commands until `boot`
+pub fn run(
+    boot_services: &mut uefi::BootServices,
+    con_in: &mut Input,
+    bootinfo: &mut Bootinfo,
+) -> Result<(), uefi::Error> {
+    brint!(bootinfo.fb, "Type `help` to list the commands\n");
+
+    loop {
+        let mut line = Line::new();
+        read_line(boot_services, con_in, &mut bootinfo.fb, "> ", &mut line)?;
+        let line = core::str::from_utf8(line.as_slice()).unwrap();
+
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (None, _, _) => {},
+            (Some("help"), None, _) => brint!(bootinfo.fb, "{}", HELP),
+            (Some("cmdline"), None, _) => {
+                read_line(boot_services, con_in, &mut bootinfo.fb, "cmdline: ", &mut bootinfo.cmdline)?;
+            },
+            (Some("modes"), None, _) => list_modes(boot_services, &mut bootinfo.fb)?,
+            (Some("mode"), Some(mode), None) => match mode.parse() {
+                Ok(mode) => switch_mode(boot_services, &mut bootinfo.fb, mode)?,
+                Err(_) => brint!(bootinfo.fb, "Not a number: {}\n", mode),
+            },
+            (Some("memmap"), None, _) => dump_memory_map(boot_services, bootinfo)?,
+            (Some("boot"), None, _) => return Ok(()),
+            _ => brint!(bootinfo.fb, "Unknown command, try `help`\n"),
+        }
+    }
+}
+
+fn read_key(boot_services: &uefi::BootServices, con_in: &mut Input) -> Result<InputKey, uefi::Error> {
+    loop {
+        boot_services.wait_for_event(&[con_in.wait_for_key])?;
+
+        // Someone else could have taken the key
+        match con_in.read_key_stroke() {
+            Err(uefi::Error::NotReady) => continue,
+            result => return result,
+        }
+    }
+}
+
+/// Moves the cursor one cell back, to the end of the previous row if needed
+fn cursor_back(out: &mut fb::Framebuffer) {
+    if out.cursor_x == 0 {
+        out.cursor_y = out.cursor_y.checked_sub(1).unwrap_or(out.max_y - 1);
+        out.cursor_x = out.max_x;
+    }
+    out.cursor_x -= 1;
+}
+
+/// Lets the user edit `line`, starting with its current contents. Only
+/// printable ASCII can be typed, Backspace removes the last character and
+/// Escape clears the whole line.
+fn read_line(
+    boot_services: &uefi::BootServices,
+    con_in: &mut Input,
+    out: &mut fb::Framebuffer,
+    prompt: &str,
+    line: &mut ArrayVec<u8>,
+) -> Result<(), uefi::Error> {
+    brint!(out, "{}", prompt);
+    out.write_bytes(line.as_slice());
+
+    loop {
+        out.draw_letter(b'_');
+        let key = read_key(boot_services, con_in)?;
+        out.draw_letter(b' ');
+
+        match key.char() {
+            Some(c) if (c.is_ascii_graphic() || c == ' ') && !line.is_full() => {
+                line.push(c as u8);
+                out.write_bytes(&[c as u8]);
+            },
+            Some(_) if key.unicode_char == InputKey::CHAR_BACKSPACE && !line.is_empty() => {
+                line.pop();
+                cursor_back(out);
+            },
+            Some(_) if key.unicode_char == InputKey::CHAR_CARRIAGE_RETURN => {
+                brint!(out, "\n");
+                return Ok(());
+            },
+            None if key.scan_code == ScanCode::ESCAPE => {
+                while line.pop().is_some() {
+                    cursor_back(out);
+                    out.draw_letter(b' ');
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+fn list_modes(boot_services: &mut uefi::BootServices, out: &mut fb::Framebuffer) -> Result<(), uefi::Error> {
+    let gop = boot_services.locate_protocol_mut::<GraphicsOutput>()?;
+    let current = gop.mode().mode;
+
+    for i in 0..gop.mode().max_mode {
+        let info = gop.query_mode(i)?;
+        let mark = if i == current { '*' } else { ' ' };
+        brint!(out, "{} {:>3}: {}x{}\n", mark, i, info.horizontal_res, info.vertical_res);
+    }
+
+    return Ok(());
+}
+
+fn switch_mode(boot_services: &mut uefi::BootServices, out: &mut fb::Framebuffer, mode: u32) -> Result<(), uefi::Error> {
+    let gop = boot_services.locate_protocol_mut::<GraphicsOutput>()?;
+    if let Err(e) = gop.set_mode(mode) {
+        brint!(out, "Can't switch to mode {}: {:?}\n", mode, e);
+        return Ok(());
+    }
+
+    crate::framebuffer_from_gop(boot_services, out)?;
+    let (width, height) = (out.width, out.height);
+    brint!(out, "Switched to {}x{}\n", width, height);
+    return Ok(());
+}
+
+/// Uses `Bootinfo::buf` as scratch space, nothing else needs it
+fn dump_memory_map(boot_services: &uefi::BootServices, bootinfo: &mut Bootinfo) -> Result<(), uefi::Error> {
+    let (_, map) = boot_services.get_memory_map(&mut bootinfo.buf)?;
+
+    for d in map {
+        let typ = d.memory_type();
+        brint!(bootinfo.fb, "{:010X} {:?} {:?}\n", d.phys_start, Size(d.pages * 4096), typ);
+    }
+
+    return Ok(());
+}
@@ -14,33 +14,43 @@ use core::sync::atomic::{Ordering, AtomicPtr};
 use core::fmt::Write;
 use core::ptr::NonNull;
 use arrayvec;
+use cpu::paging::{Flags, FrameAllocator, MapError, Mapper, PML4Entry, Table, PAGE_SIZE};
+use cpu::{PhysAddr, VirtAddr};
+use uefi::protocols::gop::{BltPixel, GraphicsOutput, PixelFormat, Rect};
 
 #[repr(align(16))]
 struct AlignedTo16<T: ?Sized>(T);
+/// Used when there is no `KERNEL_FILE` on the boot volume. Both can be LZ4
+/// frames.
 static KERNEL: &AlignedTo16<[u8]> = &AlignedTo16(*include_bytes!(env!("SOVOS_KERNEL_PATH")));
 
-const UPPER_HALF:     u64 = 0xFFFF_8000_0000_0000;
-const QUARTER:        u64 = 0x0000_4000_0000_0000;
-const THREE_QUARTERS: u64 = UPPER_HALF + QUARTER;
-const GIGAPAGE_SIZE:  u64 = 1 << 20;
-const MEGAPAGE_SIZE:  u64 = 2 << 10;
-
-const PRESENT: u64 = (1 << 0);
-const WRITABLE: u64 = (1 << 1);
-const NONCACHABLE: u64 = (1 << 4);
-const NX: u64 = (1 << 63);
-const VIRT_OFFSET: u64 = THREE_QUARTERS;
-const PTR_MASK: usize  = 0x0000_FFFF_FFFF_F000;
+const KERNEL_FILE: &str = "\\sovos\\kernel";
+const INITRD_FILE: &str = "\\sovos\\initrd";
+
+/// Seconds to wait for a key before booting
+const BOOT_MENU_TIMEOUT: u64 = 3;
+
+/// Size of the direct map, everything above it belongs to `VIRT_OFFSET`
+const DIRECT_MAP_SIZE: u64 = VIRT_OFFSET - DIRECT_MAP;
 const BOOTINFO_SIZE_PAGES: u64 = (core::mem::size_of::<Bootinfo>() / 4096) as u64;
 
 static STUFF_PTR: AtomicPtr<Bootinfo> = AtomicPtr::new(core::ptr::null_mut());
 
+/// UEFI text consoles, used until the framebuffer is ready and null after
+static CON_OUT: AtomicPtr<uefi::protocols::simple_text::Output> = AtomicPtr::new(core::ptr::null_mut());
+static CON_ERR: AtomicPtr<uefi::protocols::simple_text::Output> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Set when the framebuffer is only a copy of the screen, see `blt_flush`
+static BLT_GOP: AtomicPtr<GraphicsOutput> = AtomicPtr::new(core::ptr::null_mut());
+
 macro_rules! brint {
     ($($arg:tt)*) => {{
         let _ = write!($($arg)*);
     }}
 }
 
+mod console;
+
 struct Size(u64);
 impl core::fmt::Debug for Size {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
@@ -65,18 +75,69 @@ impl core::fmt::Debug for Size {
     }
 }
 
-#[panic_handler]
-fn panic_handler(info: &core::panic::PanicInfo) -> ! {
-    let ptr = STUFF_PTR.load(Ordering::SeqCst);
-    let fb = unsafe { &mut *core::ptr::addr_of_mut!((*ptr).fb) };
+/// `brint!` target for the UEFI console, prints nothing after `detach`
+#[derive(Clone, Copy)]
+enum UefiConsole {
+    Out,
+
+    /// Falls back to `Out` if there is no error console
+    Err,
+}
+
+impl UefiConsole {
+    fn attach(st: &uefi::SystemTable) {
+        let ptr = |p: Option<NonNull<_>>| p.map_or(core::ptr::null_mut(), NonNull::as_ptr);
+        CON_OUT.store(ptr(st.con_out), Ordering::SeqCst);
+        CON_ERR.store(ptr(st.con_err), Ordering::SeqCst);
+    }
+
+    /// Everything goes to the framebuffer from now on
+    fn detach() {
+        CON_OUT.store(core::ptr::null_mut(), Ordering::SeqCst);
+        CON_ERR.store(core::ptr::null_mut(), Ordering::SeqCst);
+    }
+
+    fn is_attached() -> bool {
+        !CON_OUT.load(Ordering::SeqCst).is_null() || !CON_ERR.load(Ordering::SeqCst).is_null()
+    }
+}
 
+impl core::fmt::Write for UefiConsole {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let out = CON_OUT.load(Ordering::SeqCst);
+        let ptr = match self {
+            Self::Out => out,
+            Self::Err => NonNull::new(CON_ERR.load(Ordering::SeqCst)).map_or(out, NonNull::as_ptr),
+        };
+
+        // SAFETY: the consoles are valid until ExitBootServices and the
+        // pointers are cleared long before that
+        return match unsafe { ptr.as_mut() } {
+            Some(console) => console.write_str(s),
+            None => Ok(()),
+        };
+    }
+}
+
+fn print_panic(out: &mut impl Write, info: &core::panic::PanicInfo) {
     if let Some(loc) = info.location() {
-        let _ = write!(fb, "Panic at {}:{}\n", loc.file(), loc.line());
+        let _ = write!(out, "Panic at {}:{}\n", loc.file(), loc.line());
     } else {
-        let _ = fb.write_str("Panic at unknown location\n");
+        let _ = out.write_str("Panic at unknown location\n");
     }
 
-    let _ = write!(fb, "Message: '{}'", info.message());
+    let _ = write!(out, "Message: '{}'", info.message());
+}
+
+#[panic_handler]
+fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+    if UefiConsole::is_attached() {
+        print_panic(&mut UefiConsole::Err, info);
+    } else {
+        let ptr = STUFF_PTR.load(Ordering::SeqCst);
+        let fb = unsafe { &mut *core::ptr::addr_of_mut!((*ptr).fb) };
+        print_panic(fb, info);
+    }
 
     loop {
         cpu::halt();
@@ -84,92 +145,201 @@ fn panic_handler(info: &core::panic::PanicInfo) -> ! {
 }
 
 fn setup_framebuffer(boot_services: &mut uefi::BootServices, bootinfo: &mut Bootinfo) -> uefi::RawStatus {
-    let gop = boot_services.locate_protocol_mut::<uefi::protocols::gop::GraphicsOutput>();
-    let Ok(gop) = gop else {
-        return uefi::RawStatus::from_error(uefi::Error::CompromisedData);
+    // Checked at build time, a typo there is not the user's fault
+    let built_in = option_env!("SOVOS_RESOLUTION")
+        .map(|r| loader::parse_resolution(r).expect("SOVOS_RESOLUTION is not WIDTHxHEIGHT"));
+    let preferred = loader::preferred_resolution(&bootinfo.command_line(), built_in, &mut UefiConsole::Err);
+    let gop = match boot_services.locate_protocol_mut::<GraphicsOutput>() {
+        Ok(gop) => gop,
+        Err(e) => {
+            brint!(UefiConsole::Err, "No graphics output: {:?}\n", e);
+            return uefi::RawStatus::from_error(uefi::Error::CompromisedData);
+        },
     };
 
-    let gop_mode = gop.mode();
-    let gop_info = gop_mode.info.unwrap();
-    
-    let out = &mut bootinfo.fb;
-    *out = fb::Framebuffer {
-        base: gop_mode.framebuffer_base as *mut u8,
-        memsize: gop_mode.framebuffer_size,
-        scanline_width: gop_info.pixels_per_scanline as usize,
-        max_x: (gop_info.horizontal_res as usize / fb::FONT_X) as u16,
-        max_y: (gop_info.vertical_res as usize / fb::FONT_Y) as u16,
-        cursor_x: 0,
-        cursor_y: 0,
-        mode: fb::Mode::Overwrite,
-    };
-    // out.cursor_y = out.max_y - 1;
-
-    brint!(out, "Current mode = {}\n", gop_mode.mode);
-    let (mode_index, new_mode) = (0..gop_mode.max_mode)
-        .map(|i| (i, gop.query_mode(i).unwrap()))
-        .max_by_key(|(_, m)| m.horizontal_res * m.vertical_res)
-        .unwrap();
+    let current = gop.mode().mode;
+    let mode_index = loader::choose_mode(gop, preferred, &mut UefiConsole::Out);
+    if mode_index != current {
+        let info = gop.query_mode(mode_index).unwrap();
+        brint!(UefiConsole::Out, "Switching mode to {}x{}\n", info.horizontal_res, info.vertical_res);
+        if let Err(e) = gop.set_mode(mode_index) {
+            brint!(UefiConsole::Err, "Can't switch to mode {}: {:?}\n", mode_index, e);
+        }
+    }
 
-    if gop_mode.mode == mode_index {
-        brint!(out, "Current graphics mode is optimal\n");
-        return uefi::RawStatus::ok();
+    if let Err(e) = framebuffer_from_gop(boot_services, &mut bootinfo.fb) {
+        brint!(UefiConsole::Err, "No usable framebuffer: {:?}\n", e);
+        return uefi::RawStatus::from_error(e);
     }
 
-    let (x, y) = (new_mode.horizontal_res, new_mode.vertical_res);
-    brint!(out, "Switching mode to {}x{}\n", new_mode.horizontal_res, new_mode.vertical_res);
+    let out = &mut bootinfo.fb;
+    let (width, height, bytes_per_pixel) = (out.width, out.height, out.format.bytes_per_pixel());
+    brint!(out, "Graphics mode {}x{}, {} bytes per pixel\n", width, height, bytes_per_pixel);
+    if out.flush.is_some() {
+        brint!(out, "No linear framebuffer, drawing with Blt\n");
+    }
 
-    // This is the only place where we need &mut GraphicsOutput and
-    // because of that we're dragging &mut BootServices all the way here.
-    gop.set_mode(mode_index).unwrap();
+    return uefi::RawStatus::ok();
+}
 
+/// Points `out` at the framebuffer of the current graphics mode. Without a
+/// linear framebuffer, `out` draws to a copy in memory and sends changes to
+/// the screen with Blt, until ExitBootServices.
+fn framebuffer_from_gop(boot_services: &mut uefi::BootServices, out: &mut fb::Framebuffer) -> Result<(), uefi::Error> {
+    let gop = boot_services.locate_protocol::<GraphicsOutput>()?;
     let gop_mode = gop.mode();
     let gop_info = gop_mode.info.unwrap();
+    let (base, memsize) = (gop_mode.framebuffer_base as *mut u8, gop_mode.framebuffer_size);
+    let scanline_width = gop_info.pixels_per_scanline as usize;
+    let width = gop_info.horizontal_res as usize;
+    let height = gop_info.vertical_res as usize;
+
+    let format = match gop_info.pixel_format() {
+        Some(PixelFormat::Rgbr8bpc) => fb::PixelFormat::RGB,
+        Some(PixelFormat::Bgrr8bpc) => fb::PixelFormat::BGR,
+        Some(PixelFormat::Bitmask) => {
+            let mask = gop_info.pixel_info;
+            fb::PixelFormat { red: mask.red, green: mask.green, blue: mask.blue, reserved: mask.reserved }
+        },
+        Some(PixelFormat::BltOnly) => {
+            // `BltPixel` is BGR
+            let pages = (width * height * 4).div_ceil(4096);
+            let shadow = allocate_pages(boot_services, pages)?;
+            let gop = boot_services.locate_protocol_mut::<GraphicsOutput>()?;
+            BLT_GOP.store(gop, Ordering::SeqCst);
+
+            *out = fb::Framebuffer::new(shadow.as_mut_ptr(), shadow.len(), width, width, height, fb::PixelFormat::BGR);
+            out.flush = Some(blt_flush);
+            blt_flush(out, 0, 0, width, height);
+            return Ok(());
+        },
+        None => return Err(uefi::Error::Unsupported),
+    };
+
+    BLT_GOP.store(core::ptr::null_mut(), Ordering::SeqCst);
+    *out = fb::Framebuffer::new(base, memsize, scanline_width, width, height, format);
+    return Ok(());
+}
 
-    *out = fb::Framebuffer {
-        base: gop_mode.framebuffer_base as *mut u8,
-        memsize: gop_mode.framebuffer_size,
-        scanline_width: gop_info.pixels_per_scanline as usize,
-        max_x: (gop_info.horizontal_res as usize / fb::FONT_X) as u16,
-        max_y: (gop_info.vertical_res as usize / fb::FONT_Y) as u16,
-        cursor_x: 0,
-        cursor_y: 0,
-        mode: fb::Mode::Overwrite,
+/// `fb::Flush` for framebuffers made by `framebuffer_from_gop` in Blt-only
+/// modes
+fn blt_flush(fb: &fb::Framebuffer, x: usize, y: usize, width: usize, height: usize) {
+    let Some(gop) = NonNull::new(BLT_GOP.load(Ordering::SeqCst)) else {
+        return;
     };
-    // out.cursor_y = out.max_y - 1;
 
-    brint!(out, "Finished switching to {}x{}\n", x, y);
-    return uefi::RawStatus::ok();
+    // SAFETY: `BLT_GOP` is cleared before ExitBootServices and
+    // `framebuffer_from_gop` made `fb.base` out of `BltPixel`s
+    let (gop, pixels) = unsafe {
+        let len = fb.memsize / core::mem::size_of::<BltPixel>();
+        (&mut *gop.as_ptr(), core::slice::from_raw_parts(fb.base.cast::<BltPixel>(), len))
+    };
+
+    let rect = Rect { x, y, width, height };
+    let _ = gop.blt_from_buffer(&pixels[y * fb.scanline_width + x..], fb.scanline_width, rect);
 }
 
-fn allocate_pages(boot_services: &uefi::BootServices, pages: u32) -> (*mut u8, uefi::RawStatus) {
-    const ALLOCATE_ANY_PAGES: usize = 0;
-    let mut memory: *mut u8 = core::ptr::null_mut();
-    let result = (boot_services.allocate_pages)(
-        ALLOCATE_ANY_PAGES,
+/// Zeroed `LoaderData` pages, which the kernel gets to keep
+fn allocate_pages(boot_services: &uefi::BootServices, pages: usize) -> Result<&'static mut [u8], uefi::Error> {
+    let pages = boot_services.allocate_pages(
+        uefi::AllocateType::AnyPages,
         uefi::memory::Type::LoaderData,
         pages,
-        &mut memory,
-    );
-    return (memory, result);
+    )?;
+    return Ok(pages.leak());
 }
 
-fn base_setup(boot_services: &mut uefi::BootServices) -> Result<&'static mut Bootinfo, uefi::RawStatus> {
-    // First, we need to allocate some memory for global state (framebuffer, memory information..)
-    let (bootinfo_ptr, result) = allocate_pages(boot_services, BOOTINFO_SIZE_PAGES as u32);
+/// Copies the load options given to this image, by the shell or a boot
+/// entry, into `cmdline` as printable ASCII. The shell puts the image path
+/// first, which isn't a part of the command line.
+fn read_load_options(
+    boot_services: &uefi::BootServices,
+    image: &uefi::ImageHandle,
+    cmdline: &mut arrayvec::ArrayVecSized<u8, CMDLINE_SIZE>,
+) -> Result<(), uefi::Error> {
+    use uefi::protocols::loaded_image::LoadedImage;
+
+    let loaded = boot_services.handle_protocol::<LoadedImage>(image.as_handle())?;
+    brint!(UefiConsole::Out, "Loaded at {:p}, {:?}\n", loaded.image_base, Size(loaded.image_size));
+
+    let Some(options) = loaded.load_options_ucs2() else {
+        return Ok(());
+    };
 
-    if !result.is_ok() {
-        // TODO: print on uefi console?
-        return Err(result);
+    let mut ascii = [0u8; 256];
+    let mut len = 0;
+    for c in char::decode_utf16(options.iter().copied()) {
+        let c = match c {
+            Ok(c) if c.is_ascii_graphic() => c as u8,
+            Ok(c) if c.is_whitespace() => b' ',
+            _ => b'?',
+        };
+        if len == ascii.len() {
+            brint!(UefiConsole::Err, "Load options are too long, cutting them off\n");
+            break;
+        }
+        ascii[len] = c;
+        len += 1;
+    }
+
+    // Only ASCII went in
+    let line = core::str::from_utf8(&ascii[..len]).unwrap().trim();
+    let (first, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let is_image_path = first.len() >= 4 && first[first.len() - 4..].eq_ignore_ascii_case(".efi");
+    let line = if is_image_path { rest.trim_start() } else { line };
+
+    for &b in line.as_bytes().iter().take(cmdline.capacity()) {
+        cmdline.push(b);
     }
 
+    if !line.is_empty() {
+        brint!(UefiConsole::Out, "Command line: {}\n", line);
+    }
+    return Ok(());
+}
+
+/// Prints the disk, partition and file this image was loaded from
+fn print_boot_device(boot_services: &uefi::BootServices, image: &uefi::ImageHandle) -> Result<(), uefi::Error> {
+    use uefi::protocols::device_path::DevicePath;
+    use uefi::protocols::loaded_image::LoadedImage;
+
+    let loaded = boot_services.handle_protocol::<LoadedImage>(image.as_handle())?;
+    let device = boot_services.handle_protocol::<DevicePath>(loaded.device_handle)?;
+    brint!(UefiConsole::Out, "Booted from {}\n", device);
+    if let Some(file) = loaded.file_device_path() {
+        brint!(UefiConsole::Out, "Image path {}\n", file);
+    }
+
+    return Ok(());
+}
+
+fn base_setup(
+    boot_services: &mut uefi::BootServices,
+    image: &uefi::ImageHandle,
+) -> Result<&'static mut Bootinfo, uefi::RawStatus> {
+    // First, we need to allocate some memory for global state (framebuffer, memory information..)
+    let bootinfo_ptr = match allocate_pages(boot_services, BOOTINFO_SIZE_PAGES as usize) {
+        Ok(pages) => pages.as_mut_ptr(),
+        Err(e) => {
+            brint!(UefiConsole::Err, "Can't allocate Bootinfo: {:?}\n", e);
+            return Err(uefi::RawStatus::from_error(e));
+        },
+    };
+
+    // The pages are zeroed, which is a valid `Bootinfo`
     let bootinfo_ptr = bootinfo_ptr.cast::<Bootinfo>();
-    // SAFETY: the structure can be initialized with zeros
-    unsafe { bootinfo_ptr.write_bytes(0u8, 1) };
     STUFF_PTR.store(bootinfo_ptr, Ordering::SeqCst);
     // SAFETY: pointer is valid and structure is initialized
     let bootinfo = unsafe { &mut *bootinfo_ptr };
 
+    if let Err(e) = print_boot_device(boot_services, image) {
+        brint!(UefiConsole::Err, "Can't find the boot device: {:?}\n", e);
+    }
+    if let Err(e) = read_load_options(boot_services, image, &mut bootinfo.cmdline) {
+        brint!(UefiConsole::Err, "Can't read load options: {:?}\n", e);
+    }
+
+    brint!(UefiConsole::Out, "Setting up the framebuffer\n");
     let result = setup_framebuffer(boot_services, bootinfo);
     if !result.is_ok() {
         return Err(result);
@@ -178,6 +348,23 @@ fn base_setup(boot_services: &mut uefi::BootServices) -> Result<&'static mut Boo
     return Ok(bootinfo);
 }
 
+/// Only warns, the tables are usable with a bad CRC or an older revision
+fn verify_tables(st: &mut uefi::SystemTable) {
+    use uefi::Verify;
+
+    // SAFETY: all three come from the firmware, so `header_size` is their
+    // real size
+    if let Err(e) = unsafe { st.verify() } {
+        brint!(UefiConsole::Err, "System table: {:?}\n", e);
+    }
+    if let Some(Err(e)) = st.runtime_services().map(|rt| unsafe { rt.verify() }) {
+        brint!(UefiConsole::Err, "Runtime services table: {:?}\n", e);
+    }
+    if let Some(Err(e)) = st.boot_services().map(|bs| unsafe { bs.verify() }) {
+        brint!(UefiConsole::Err, "Boot services table: {:?}\n", e);
+    }
+}
+
 #[no_mangle]
 extern "efiapi" fn efi_main(handle: uefi::ImageHandle, st: Option<&'static mut uefi::SystemTable>) -> uefi::RawStatus {
     cpu::disable_interrupts();
@@ -185,47 +372,61 @@ extern "efiapi" fn efi_main(handle: uefi::ImageHandle, st: Option<&'static mut u
     let Some(st) = st else {
         return uefi::RawStatus::from_error(uefi::Error::HttpError);
     };
+    UefiConsole::attach(st);
+    verify_tables(st);
+    let con_in = st.con_in;
 
     let boot_services = match st.boot_services() {
         Some(p) => p,
-        None => return uefi::RawStatus::from_error(uefi::Error::IpAddressConflict),
+        None => {
+            brint!(UefiConsole::Err, "Boot services are missing\n");
+            return uefi::RawStatus::from_error(uefi::Error::IpAddressConflict);
+        },
     };
 
-    let bootinfo = match base_setup(boot_services) {
+    let bootinfo = match base_setup(boot_services, &handle) {
         Ok(b) => b,
         Err(status) => return status,
     };
+    UefiConsole::detach();
 
-    let (memkey, memmap) = boot_services.get_memory_map(&mut bootinfo.buf).unwrap();
-    let ok = st.exit_boot_services(handle, memkey);
-    assert_eq!(ok, Ok(()));
-    brint!(bootinfo.fb, "Exit boot services\n");
-
-    const TYPICAL_MEMORY: uefi::memory::Attributes = uefi::memory::Attributes::new()
-        .set_noncacheable()
-        .set_write_combine()
-        .set_write_through()
-        .set_write_back();
-
-    for map in memmap {
-        bootinfo.uefi_meminfo.push(*map);
+    if let Some(mut con_in) = con_in {
+        // SAFETY: the protocol is valid until ExitBootServices
+        let con_in = unsafe { con_in.as_mut() };
 
-        if map.phys_start == 0 {
-            assert_eq!(bootinfo.free_memory_at_null, None);
-            bootinfo.free_memory_at_null = core::num::NonZeroU64::new(map.pages);
-            continue;
-        }
+        // Timers need the firmware's timer interrupt
+        cpu::enable_interrupts();
+        let result = boot_menu(boot_services, con_in, bootinfo);
+        cpu::disable_interrupts();
 
-        use uefi::memory::Type;
-        if Type::from_int(map.typ) == Some(Type::Conventional) {
-            assert_eq!(map.attributes, TYPICAL_MEMORY);
-            bootinfo.free_memory.push(FreeMemory{ phys_start: map.phys_start, pages: map.pages });
+        if let Err(e) = result {
+            brint!(bootinfo.fb, "Boot menu failed: {:?}\n", e);
         }
     }
 
-    bootinfo.uefi_meminfo.sort_unstable_by_key(|m| m.phys_start);
+    let seed = kaslr_seed(boot_services);
+
+    let kernel = find_kernel(boot_services, &handle, &mut bootinfo.fb);
+    let initrd = read_boot_file(boot_services, &handle, INITRD_FILE).ok();
+    let pci = pci_inventory(boot_services, &mut bootinfo.fb);
+
+    // Blt is a boot service, the copy in memory is all that is left after
+    BLT_GOP.store(core::ptr::null_mut(), Ordering::SeqCst);
+    let memmap = st.exit_boot_services_with_map(handle).expect("ExitBootServices failed");
+    brint!(bootinfo.fb, "Exit boot services\n");
+
+    bootinfo.free_memory_at_null = loader::collect_free_memory(&memmap, &mut bootinfo.free_memory);
+
+    let handoff_pages = loader::handoff_size(&memmap, pci).div_ceil(4096) as u64;
+    let handoff_buf = post_allocate_page(&mut bootinfo.free_memory, handoff_pages).cast::<u64>();
+    // SAFETY: the pages are free, so nobody else is using them
+    let handoff_buf = unsafe { core::slice::from_raw_parts_mut(handoff_buf.as_ptr(), handoff_pages as usize * 512) };
+    let initrd = initrd.map(|initrd| handoff::Initrd { phys_start: ref_to_addr(initrd.as_ptr()), size: initrd.len() as u64 });
+    let cmdline = bootinfo.command_line().as_str();
+    let mut handoff = loader::start_handoff(handoff_buf, &memmap, pci, initrd.as_ref(), cmdline).unwrap();
+
     let mut last_mem_end = 0;
-    for map in bootinfo.uefi_meminfo.iter() {
+    for map in sorted_memory_map(&handoff) {
         if last_mem_end != map.phys_start {
             brint!(bootinfo.fb, "    Gap {:?}\n", Size(map.phys_start - last_mem_end));
         }
@@ -233,8 +434,336 @@ extern "efiapi" fn efi_main(handle: uefi::ImageHandle, st: Option<&'static mut u
         last_mem_end = map.phys_start + map.pages * 4096;
     }
 
+    forward_config_tables(st, &mut handoff, &mut bootinfo.fb);
+
     bootinfo.uefi_systable = Some(&*st);
-    post_boot_services(bootinfo);
+    post_boot_services(bootinfo, handoff, seed, kernel);
+}
+
+/// Every PCI function the firmware found, in `LoaderData` so it stays after
+/// ExitBootServices
+fn pci_inventory(
+    boot_services: &uefi::BootServices,
+    out: &mut fb::Framebuffer,
+) -> &'static [uefi::protocols::pci_io::PciFunction] {
+    use uefi::protocols::pci_io::{self, PciFunction};
+
+    let functions = match pci_io::pci_functions(boot_services) {
+        Ok(functions) => functions,
+        Err(e) => {
+            brint!(out, "Can't enumerate PCI functions: {:?}\n", e);
+            return &[];
+        },
+    };
+
+    let count = functions.len();
+    let pages = (count * core::mem::size_of::<PciFunction>()).div_ceil(4096).max(1);
+    let buf = match allocate_pages(boot_services, pages) {
+        Ok(buf) => buf,
+        Err(e) => {
+            brint!(out, "No memory for the PCI inventory: {:?}\n", e);
+            return &[];
+        },
+    };
+    // SAFETY: the pages are zeroed and aligned, `PciFunction` is plain
+    // integers
+    let buf = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr().cast::<PciFunction>(), count) };
+
+    let mut len = 0;
+    for function in functions {
+        let f = match function {
+            Ok(f) => f,
+            Err(e) => {
+                brint!(out, "Can't describe a PCI function: {:?}\n", e);
+                continue;
+            },
+        };
+
+        brint!(
+            out,
+            "PCI {:04x}:{:02x}:{:02x}.{} {:04x}:{:04x} class {:02x}.{:02x}.{:02x}\n",
+            f.segment, f.bus, f.device, f.function, f.vendor_id, f.device_id, f.class, f.subclass, f.prog_if,
+        );
+        buf[len] = f;
+        len += 1;
+    }
+
+    return &buf[..len];
+}
+
+/// Counts down `BOOT_MENU_TIMEOUT`, a key press opens the console
+fn boot_menu(
+    boot_services: &mut uefi::BootServices,
+    con_in: &mut uefi::protocols::simple_text::Input,
+    bootinfo: &mut Bootinfo,
+) -> Result<(), uefi::Error> {
+    use uefi::{EventType, TimerDelay};
+
+    con_in.reset(uefi::protocols::simple_text::Verification::None)?;
+    let timer = boot_services.create_event(EventType::TIMER)?;
+    let mut pressed = false;
+
+    boot_services.set_timer(timer, TimerDelay::Periodic(TimerDelay::SECOND))?;
+    for left in (1..=BOOT_MENU_TIMEOUT).rev() {
+        brint!(bootinfo.fb, "Press any key for the console, booting in {}s\n", left);
+        if boot_services.wait_for_event(&[con_in.wait_for_key, timer])? == 0 {
+            pressed = true;
+            break;
+        }
+    }
+    boot_services.close_event(timer);
+
+    if !pressed {
+        return Ok(());
+    }
+
+    // Waiting for the user could take longer than the watchdog's 5 minutes
+    let _ = con_in.read_key_stroke();
+    let _ = boot_services.set_watchdog_timer(0, 0);
+    return console::run(boot_services, con_in, bootinfo);
+}
+
+/// Reads the kernel from the boot volume, falls back to the embedded one
+fn find_kernel(
+    boot_services: &mut uefi::BootServices,
+    image: &uefi::ImageHandle,
+    out: &mut fb::Framebuffer,
+) -> &'static [u8] {
+    match read_boot_file(boot_services, image, KERNEL_FILE) {
+        Ok(file) => match open_kernel(boot_services, file, out) {
+            Ok(kernel) => return kernel,
+            Err(e) => brint!(out, "{} is corrupted: {:?}\n", KERNEL_FILE, e),
+        },
+        Err(e) => brint!(out, "Could not read {}: {:?}\n", KERNEL_FILE, e),
+    }
+
+    brint!(out, "Using the embedded kernel\n");
+    return open_kernel(boot_services, &KERNEL.0, out).expect("embedded kernel is corrupted");
+}
+
+/// Why a kernel image can't be loaded
+// The fields are only ever printed
+#[allow(dead_code)]
+#[derive(Debug)]
+enum KernelError {
+    Lz4(lz4::Error),
+    Elf(elf::Error),
+    Memory(elf::MemoryError),
+    Relocations(elf::RelocationError),
+
+    /// `PT_LOAD` or `PT_GNU_RELRO` segment at this address can't be loaded
+    BadSegment(u64),
+    NoEntry,
+
+    /// Loaded or decompressed image doesn't fit in `KERNEL_SLIDE_RANGE`
+    TooBig(u64),
+    NoMemory(uefi::Error),
+}
+
+impl From<lz4::Error> for KernelError {
+    fn from(e: lz4::Error) -> Self {
+        Self::Lz4(e)
+    }
+}
+
+impl From<elf::Error> for KernelError {
+    fn from(e: elf::Error) -> Self {
+        Self::Elf(e)
+    }
+}
+
+impl From<elf::MemoryError> for KernelError {
+    fn from(e: elf::MemoryError) -> Self {
+        Self::Memory(e)
+    }
+}
+
+impl From<elf::RelocationError> for KernelError {
+    fn from(e: elf::RelocationError) -> Self {
+        Self::Relocations(e)
+    }
+}
+
+/// Decompresses and checks a kernel image. On error the pages stay
+/// allocated, there is no need to give them back this early in boot.
+fn open_kernel(
+    boot_services: &mut uefi::BootServices,
+    data: &'static [u8],
+    out: &mut fb::Framebuffer,
+) -> Result<&'static [u8], KernelError> {
+    let kernel = decompress_image(boot_services, data, out)?;
+    check_kernel(kernel)?;
+    return Ok(kernel);
+}
+
+/// Checks everything `load_kernel` relies on, it can't fail gracefully
+/// after ExitBootServices. Returns the size of the loaded image.
+fn check_kernel(kernel: &[u8]) -> Result<u64, KernelError> {
+    let ker = elf::Elf::<elf::Amd64>::from_bytes(kernel)?;
+    let ker_ph = ker.program_headers()?;
+    let size = image_size(ker_ph)?;
+    if size > KERNEL_SLIDE_RANGE {
+        return Err(KernelError::TooBig(size));
+    }
+
+    let loads = || {
+        ker_ph
+            .iter()
+            .filter(|ph| ph.segment_type() == Some(elf::SegmentType::Load))
+    };
+    for ph in loads() {
+        let in_file = ph.p_offset.checked_add(ph.p_filesz).is_some_and(|end| end <= kernel.len() as u64);
+        let writable_code = ph.p_flags.is_writable() && ph.p_flags.is_executable();
+        if ph.p_filesz > ph.p_memsz || !in_file || writable_code {
+            return Err(KernelError::BadSegment(ph.p_vaddr));
+        }
+    }
+
+    // RELRO is remapped read-only, so it has to be inside a mapped segment
+    for ph in ker_ph
+        .iter()
+        .filter(|ph| ph.segment_type() == Some(elf::SegmentType::OsSpecificGnuRelro))
+    {
+        let Some(end) = ph.p_vaddr.checked_add(ph.p_memsz) else {
+            return Err(KernelError::BadSegment(ph.p_vaddr));
+        };
+        if !loads().any(|load| load.p_vaddr <= ph.p_vaddr && end <= load.p_vaddr + load.p_memsz) {
+            return Err(KernelError::BadSegment(ph.p_vaddr));
+        }
+    }
+
+    match ker.header().e_entry {
+        Some(entry) if entry.get() < size => {},
+        _ => return Err(KernelError::NoEntry),
+    }
+
+    ker.relocations()?.check(size)?;
+    return Ok(size);
+}
+
+/// Decompresses an LZ4 frame into new `LoaderData` pages, anything else is
+/// returned as is. A kernel image bigger than `KERNEL_SLIDE_RANGE` could
+/// not be loaded anyway, so that is as much as it allocates.
+fn decompress_image(
+    boot_services: &mut uefi::BootServices,
+    data: &'static [u8],
+    out: &mut fb::Framebuffer,
+) -> Result<&'static [u8], KernelError> {
+    if !lz4::is_compressed(data) {
+        return Ok(data);
+    }
+
+    let size = lz4::frame_info(data)?.content_size;
+    if size > KERNEL_SLIDE_RANGE {
+        return Err(KernelError::TooBig(size));
+    }
+    let size = size as usize;
+    let pages = size.div_ceil(4096).max(1);
+    let buf = &mut allocate_pages(boot_services, pages).map_err(KernelError::NoMemory)?[..size];
+    let len = lz4::decompress(data, buf)?;
+    brint!(out, "Decompressed {:?} into {:?}\n", Size(data.len() as u64), Size(len as u64));
+    return Ok(&buf[..len]);
+}
+
+/// Reads a whole file from the volume the wrapper was loaded from into new
+/// `LoaderData` pages, which the kernel gets to keep
+fn read_boot_file(
+    boot_services: &mut uefi::BootServices,
+    image: &uefi::ImageHandle,
+    path: &str,
+) -> Result<&'static [u8], uefi::Error> {
+    use uefi::protocols::file::{FileAttributes, OpenMode, SimpleFileSystem};
+    use uefi::protocols::loaded_image::LoadedImage;
+
+    let device = boot_services.handle_protocol::<LoadedImage>(image.as_handle())?.device_handle;
+    let mut root = boot_services.handle_protocol_mut::<SimpleFileSystem>(device)?.open_volume()?;
+    let mut file = root.open(path, OpenMode::new().set_read(), FileAttributes::new())?;
+
+    let mut info = [0u64; 64];
+    let size = file.info(&mut info)?.file_size as usize;
+    let pages = size.div_ceil(4096).max(1);
+    let buf = allocate_pages(boot_services, pages)?;
+    file.read_exact(&mut buf[..size])?;
+    return Ok(&buf[..size]);
+}
+
+/// Seed for KASLR. The RNG protocol is gone after ExitBootServices, so this
+/// has to be called before it.
+fn kaslr_seed(boot_services: &mut uefi::BootServices) -> (u64, handoff::EntropySource) {
+    use handoff::EntropySource;
+
+    if let Some(seed) = option_env!("SOVOS_KASLR_SEED") {
+        let seed = seed.parse().expect("SOVOS_KASLR_SEED is not a number");
+        return (seed, EntropySource::Pinned);
+    }
+
+    if let Ok(rng) = boot_services.locate_protocol_mut::<uefi::protocols::rng::Rng>() {
+        let mut buf = [0u8; 8];
+        if rng.get_rng(None, &mut buf).is_ok() {
+            return (u64::from_le_bytes(buf), EntropySource::RngProtocol);
+        }
+    }
+
+    if let Some(seed) = cpu::rdseed() {
+        return (seed, EntropySource::Rdseed);
+    }
+    if let Some(seed) = cpu::rdrand() {
+        return (seed, EntropySource::Rdrand);
+    }
+    return (cpu::rdtsc(), EntropySource::Tsc);
+}
+
+/// Picks a slide for an image of `size` bytes, so that it stays inside
+/// `KERNEL_SLIDE_RANGE`
+fn kaslr_slide(seed: u64, size: u64) -> u64 {
+    assert!(size <= KERNEL_SLIDE_RANGE, "kernel is too big: {:?}", Size(size));
+    let slots = (KERNEL_SLIDE_RANGE - size.next_multiple_of(KERNEL_SLIDE_ALIGN)) / KERNEL_SLIDE_ALIGN + 1;
+
+    // splitmix64 finalizer, so that pinned seeds 1, 2, 3... land far apart
+    let mut x = seed;
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^= x >> 31;
+
+    return (x % slots) * KERNEL_SLIDE_ALIGN;
+}
+
+/// Tables that failed validation are left out
+fn forward_config_tables(st: &uefi::SystemTable, handoff: &mut handoff::Writer, out: &mut fb::Framebuffer) {
+    match st.acpi_rsdp() {
+        Some(rsdp) => {
+            let version = if let uefi::AcpiRsdp::V1(_) = rsdp { 1 } else { 2 };
+            brint!(out, "ACPI {} RSDP at {:x}\n", version, rsdp.address());
+            handoff.acpi_rsdp(rsdp.address()).unwrap();
+        },
+        None => brint!(out, "No valid ACPI RSDP\n"),
+    }
+
+    if let Some(entry) = st.smbios_entry() {
+        let version = if let uefi::SmbiosEntry::V2(_) = entry { 2 } else { 3 };
+        brint!(out, "SMBIOS {} entry point at {:x}\n", version, entry.address());
+        handoff.smbios(entry.address()).unwrap();
+    }
+
+    if let Some(fdt) = st.device_tree() {
+        brint!(out, "Device tree at {:x}, {:?}\n", ref_to_addr(fdt.as_ptr()), Size(fdt.len() as u64));
+        handoff.device_tree(ref_to_addr(fdt.as_ptr())).unwrap();
+    }
+
+    if let Some(properties) = st.rt_properties() {
+        handoff.rt_properties(properties.supported.as_u32()).unwrap();
+    }
+}
+
+/// Memory map that was already copied and sorted into the handoff
+fn sorted_memory_map<'a>(handoff: &'a handoff::Writer) -> &'a [uefi::memory::Descriptor] {
+    return handoff
+        .tags()
+        .find_map(|tag| match tag {
+            handoff::Tag::MemoryMap(map) => Some(map),
+            _ => None,
+        })
+        .unwrap();
 }
 
 type FreeMemoryVec = arrayvec::ArrayVec<bootinfo::FreeMemory>;
@@ -261,147 +790,328 @@ fn post_allocate_page(free_memory: &mut FreeMemoryVec, pages: u64) -> NonNull<u8
     return NonNull::new(ptr).unwrap();
 }
 
-fn _map_memory_page(
-    free_memory: &mut FreeMemoryVec,
-    mut paging: NonNull<[*mut u8; 512]>,
-    phys_addr: u64,
-    virt: u64,
-    flags: u64,
-    level: u8,
-) {
-    let idx = (virt >> (12 + level * 9)) & 0x1FF;
-    let idx = idx as usize;
-
-    let paging = unsafe { paging.as_mut() };
+/// Hands out single pages from the free memory to `Mapper`
+struct PostAllocator<'a>(&'a mut FreeMemoryVec);
 
-    if let Some(lower_level) = level.checked_sub(1) {
-        if paging[idx].is_null() {
-            let mut p = post_allocate_page(free_memory, 1).cast::<[*mut u8; 512]>();
-            unsafe { p.as_mut().fill(core::ptr::null_mut()); }
-            paging[idx] = p.as_ptr().map_addr(|x| x | 0b11).cast(); // present | readwrite
-        }
-
-        let lower = NonNull::new(paging[idx].map_addr(|p| p & PTR_MASK)).unwrap();
-        return _map_memory_page(free_memory, lower.cast(), phys_addr, virt, flags, lower_level);
+unsafe impl FrameAllocator for PostAllocator<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysAddr> {
+        let page = post_allocate_page(self.0, 1);
+        PhysAddr::new(page.addr().get() as u64)
     }
+}
 
-    assert_eq!(paging[idx], core::ptr::null_mut());
-    let entry = phys_addr | flags;
-    paging[idx] = core::ptr::null_mut::<u8>().with_addr(entry as usize);
+fn mapper<'a>(free_memory: &'a mut FreeMemoryVec, pml4: &'a mut Table<PML4Entry>) -> Mapper<'a, PostAllocator<'a>> {
+    // SAFETY: we are identity-mapped and we are the only user of the tables
+    unsafe { Mapper::new(pml4, PostAllocator(free_memory), 0) }
 }
 
-fn map_memory_page(
+fn map_memory_range(
     free_memory: &mut FreeMemoryVec,
-    paging: NonNull<[*mut u8; 512]>,
+    pml4: &mut Table<PML4Entry>,
     phys_addr: u64,
-    flags: u64,
+    size: u64,
+    flags: Flags,
 ) {
-    assert!(phys_addr & 0xFFF == 0);
-    let virt = phys_addr + VIRT_OFFSET;
-    _map_memory_page(free_memory, paging, phys_addr, virt, flags, 3);
+    // Anything higher would run into the runtime regions and the kernel image
+    assert!(phys_addr + size <= UEFI_RUNTIME_BASE - VIRT_OFFSET);
+
+    let virt = VirtAddr::new(phys_addr + VIRT_OFFSET);
+    let phys = PhysAddr::new(phys_addr).unwrap();
+    mapper(free_memory, pml4).map(virt, phys, size, flags).unwrap();
 }
 
-fn uefi_type_to_flags(typ: uefi::memory::Type) -> u64 {
+fn uefi_type_to_flags(typ: uefi::memory::Type) -> Option<Flags> {
     use uefi::memory::Type;
+    let data = Flags::new().set_writable().set_no_execute();
     return match typ {
         Type::Reserved
-        | Type::Unusable => 0,
+        | Type::Unusable => None,
         Type::Mmio
-        | Type::MmioPortSpace => PRESENT | WRITABLE | NX | NONCACHABLE,
+        | Type::MmioPortSpace => Some(data.set_cache_disable()),
         Type::LoaderData
         | Type::LoaderCode
         | Type::BootServicesData
         | Type::BootServicesCode
         | Type::Persistent
-        | Type::Conventional => PRESENT | WRITABLE | NX,
-        Type::RuntimeServicesCode => PRESENT,
+        | Type::Conventional => Some(data),
+        // It runs from its own mapping, see `map_runtime`
+        Type::RuntimeServicesCode => Some(Flags::new().set_no_execute()),
         Type::RuntimeServicesData
         | Type::AcpiReclaim
         | Type::AcpiNVS
-        | Type::PalCode => PRESENT | NX,
+        | Type::PalCode => Some(Flags::new().set_no_execute()),
     };
 }
 
-fn map_gigapages(free_memory: &mut FreeMemoryVec, mut paging: NonNull<[*mut u8; 512]>, memsize: u64) {
-    let paging = unsafe { paging.as_mut() };
+/// Maps one contiguous run of physical memory into the direct map
+fn map_direct(mapper: &mut Mapper<PostAllocator>, start: u64, end: u64, flags: Flags) {
+    assert!(end <= DIRECT_MAP_SIZE, "too much memory?");
+    let virt = VirtAddr::new(DIRECT_MAP + start);
+    let phys = PhysAddr::new(start).unwrap();
+    mapper.map(virt, phys, end - start, flags).unwrap();
+}
 
-    let mut currently_allocated = 0u64;
-    for lvl4 in (256usize..256+128) {
-        if paging[lvl4].is_null() {
-            let mut p = post_allocate_page(free_memory, 1).cast::<[*mut u8; 512]>();
-            unsafe { p.as_mut().fill(core::ptr::null_mut()); }
-            paging[lvl4] = p.as_ptr().map_addr(|x| x | 0b11).cast(); // present | readwrite
-        }
+/// Maps every usable piece of physical memory at `DIRECT_MAP + phys`.
+/// Neighbouring regions with the same permissions are merged, so that
+/// the mapper can use as many 2MiB and 1GiB pages as possible.
+fn map_whole_memory(bootinfo: &mut Bootinfo, pml4: &mut Table<PML4Entry>, memory_map: &[uefi::memory::Descriptor]) {
+    let giant_pages = cpu::paging::giant_pages_supported();
+    brint!(bootinfo.fb, "1GiB pages supported: {}\n", giant_pages);
 
-        let mut pdp = NonNull::new(paging[lvl4]).unwrap().cast::<[*mut u8; 512]>();
-        let pdp = unsafe { pdp.as_mut() };
+    let mut mapper = mapper(&mut bootinfo.free_memory, pml4).allow_giant_pages(giant_pages);
+    let mut run: Option<(u64, u64, Flags)> = None;
+    let mut memsum = 0u64;
 
-        for lvl3 in (0..512usize) {
-            let phys = ((lvl4 - 256) << 39) | (lvl3 << 30);
-            let flags = PRESENT | WRITABLE | NX | (1 << 7);
-            let entry = (phys as u64) | flags;
-            pdp[lvl3] = core::ptr::null_mut::<u8>().with_addr(entry as usize);
-            currently_allocated += GIGAPAGE_SIZE;
+    // `memory_map` is sorted by `phys_start`
+    for d in memory_map {
+        let Some(flags) = uefi::memory::Type::from_int(d.typ).and_then(uefi_type_to_flags) else {
+            continue;
+        };
+
+        let start = d.phys_start;
+        let end = d.phys_start + d.pages * PAGE_SIZE;
+        memsum += end - start;
+
+        run = match run {
+            Some((run_start, run_end, run_flags)) if run_end == start && run_flags == flags => {
+                Some((run_start, end, run_flags))
+            },
+            Some((run_start, run_end, run_flags)) => {
+                map_direct(&mut mapper, run_start, run_end, run_flags);
+                Some((start, end, flags))
+            },
+            None => Some((start, end, flags)),
+        };
+    }
 
-            if currently_allocated >= memsize {
+    let memsize = run.map(|(_, end, _)| end).unwrap_or(0);
+    if let Some((start, end, flags)) = run {
+        map_direct(&mut mapper, start, end, flags);
+    }
+
+    brint!(bootinfo.fb, "memsum={}MiB or {}GiB\n", memsum >> 20, memsum >> 30);
+    brint!(bootinfo.fb, "memsize={}MiB or {}GiB\n", memsize >> 20, memsize >> 30);
+}
+
+/// Where `phys` ended up after `loader::assign_runtime_addresses`
+fn runtime_virt(memory_map: &[uefi::memory::Descriptor], phys: u64) -> Option<u64> {
+    let d = memory_map
+        .iter()
+        .filter(|d| d.attributes.runtime())
+        .find(|d| (d.phys_start..d.phys_start + d.pages * PAGE_SIZE).contains(&phys))?;
+    return Some(d.virt_start + (phys - d.phys_start));
+}
+
+/// For the parts of runtime regions not described by the Memory Attributes
+/// Table
+fn runtime_flags(typ: Option<uefi::memory::Type>) -> Flags {
+    use uefi::memory::Type;
+    let data = Flags::new().set_writable().set_no_execute();
+    return match typ {
+        // Drivers keep their data in the same region as their code, only
+        // the Memory Attributes Table tells them apart. Without it, the
+        // region has to be both writable and executable.
+        Some(Type::RuntimeServicesCode) => Flags::new().set_writable(),
+        Some(Type::Mmio | Type::MmioPortSpace) => data.set_cache_disable(),
+        _ => data,
+    };
+}
+
+/// Code is read-only and data is not executable. `None` if the entry has
+/// neither attribute, it is up to the caller whether to map it anyway.
+fn attributes_table_flags(entry: &uefi::memory::Descriptor) -> Option<Flags> {
+    let (readonly, exec_protect) = (entry.attributes.readonly(), entry.attributes.exec_protect());
+    return match (readonly, exec_protect) {
+        (true, false) => Some(Flags::new()),
+        (false, true) => Some(Flags::new().set_writable().set_no_execute()),
+        (true, true) => Some(Flags::new().set_no_execute()),
+        (false, false) => None,
+    };
+}
+
+/// Entries of the Memory Attributes Table, if there is one and it makes
+/// sense: the entries have to be sorted and inside the runtime regions of
+/// `memory_map`
+fn attributes_table(bootinfo: &mut Bootinfo, memory_map: &[uefi::memory::Descriptor]) -> Option<uefi::memory::DescriptorIterator<'static>> {
+    let table = bootinfo.uefi_systable?.memory_attributes_table()?;
+    let entries = match table.entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            brint!(bootinfo.fb, "Ignoring the Memory Attributes Table: {:?}\n", e);
+            return None;
+        },
+    };
+
+    let mut last_end = 0u64;
+    for e in entries.clone() {
+        let end = e.phys_start + e.pages * PAGE_SIZE;
+        let inside = memory_map
+            .iter()
+            .filter(|d| d.attributes.runtime())
+            .any(|d| d.phys_start <= e.phys_start && end <= d.phys_start + d.pages * PAGE_SIZE);
+
+        if e.pages == 0 || e.phys_start < last_end || !inside {
+            brint!(bootinfo.fb, "Ignoring the Memory Attributes Table, bad entry at {:x}\n", e.phys_start);
+            return None;
+        }
+        last_end = end;
+    }
+
+    brint!(bootinfo.fb, "Memory Attributes Table: {} entries\n", entries.clone().count());
+    return Some(entries);
+}
+
+/// Maps the runtime regions at the addresses from `loader::assign_runtime_addresses`
+/// and returns their descriptors for SetVirtualAddressMap, along with the
+/// number of pages mapped writable and executable. Permissions come from the
+/// Memory Attributes Table where it has them.
+fn map_runtime(
+    bootinfo: &mut Bootinfo,
+    pml4: &mut Table<PML4Entry>,
+    memory_map: &[uefi::memory::Descriptor],
+) -> (&'static mut [uefi::memory::Descriptor], u64) {
+    use uefi::memory::Descriptor;
+
+    let entries = attributes_table(bootinfo, memory_map);
+
+    // Sized from the map, the firmware can have any number of them
+    let count = memory_map.iter().filter(|d| d.attributes.runtime()).count();
+    let pages = (count * core::mem::size_of::<Descriptor>()).div_ceil(4096).max(1) as u64;
+    let runtime_map = post_allocate_page(&mut bootinfo.free_memory, pages).cast::<Descriptor>();
+    // SAFETY: the pages are free, so nobody else is using them, and they
+    // are big enough for `count` descriptors
+    let runtime_map = unsafe { core::slice::from_raw_parts_mut(runtime_map.as_ptr(), count) };
+
+    let mut rwx_pages = 0;
+    let mut mapper = mapper(&mut bootinfo.free_memory, pml4);
+
+    let runtime = memory_map.iter().filter(|d| d.attributes.runtime());
+    for (slot, d) in runtime_map.iter_mut().zip(runtime) {
+        let fallback = runtime_flags(d.memory_type());
+        let end = d.phys_start + d.pages * PAGE_SIZE;
+        let mut map_piece = |start: u64, end: u64, flags: Flags| {
+            if start == end {
                 return;
             }
+            if flags.writable() && !flags.no_execute() {
+                brint!(bootinfo.fb, "Runtime region {:x}..{:x} is writable and executable\n", start, end);
+                rwx_pages += (end - start) / PAGE_SIZE;
+            }
+            let virt = VirtAddr::new(d.virt_start + (start - d.phys_start));
+            let phys = PhysAddr::new(start).unwrap();
+            mapper.map(virt, phys, end - start, flags).unwrap();
+        };
+
+        // Entries are sorted, so the gaps between them are what's left
+        let mut mapped = d.phys_start;
+        let inside = entries.clone().into_iter().flatten().filter(|e| (d.phys_start..end).contains(&e.phys_start));
+        for e in inside {
+            let e_end = e.phys_start + e.pages * PAGE_SIZE;
+            map_piece(mapped, e.phys_start, fallback);
+            // The firmware says it can't live with any protection here
+            let flags = attributes_table_flags(e).unwrap_or(Flags::new().set_writable());
+            map_piece(e.phys_start, e_end, flags);
+            mapped = e_end;
         }
+        map_piece(mapped, end, fallback);
+
+        *slot = *d;
     }
 
-    panic!("too much memory?");
+    return (runtime_map, rwx_pages);
 }
 
-fn map_whole_memory(bootinfo: &mut Bootinfo, paging: NonNull<[*mut u8; 512]>) {
-    let (memsum, memsize) = bootinfo.uefi_meminfo
-        .iter()
-        .filter(|d| uefi::memory::Type::from_int(d.typ).map(uefi_type_to_flags).unwrap_or(0) != 0)
-        .fold((0u64, 0u64), |(sz, max), d| {
-            let size_in_bytes = d.pages * 4096;
-            (sz + size_in_bytes, max.max(d.phys_start + size_in_bytes))
-        });
+/// Moves the firmware to the addresses from `map_runtime`. Returns the new
+/// addresses of its tables, or `None` if the runtime services can't be used
+/// by the kernel.
+fn enter_virtual_mode(
+    bootinfo: &mut Bootinfo,
+    runtime_map: &mut [uefi::memory::Descriptor],
+    memory_map: &[uefi::memory::Descriptor],
+    rwx_pages: u64,
+) -> Option<handoff::UefiRuntime> {
+    let st = bootinfo.uefi_systable?;
+    let rt = st.runtime_services()?;
+
+    let system_table = runtime_virt(memory_map, ref_to_addr(st));
+    let runtime_services = runtime_virt(memory_map, ref_to_addr(rt));
+    let (Some(system_table), Some(runtime_services)) = (system_table, runtime_services) else {
+        brint!(bootinfo.fb, "UEFI tables are outside of the runtime regions\n");
+        return None;
+    };
 
-    brint!(bootinfo.fb, "memsum={}MiB or {}GiB\n", memsum >> 20, memsum >> 30);
-    brint!(bootinfo.fb, "memsize={}MiB or {}GiB\n", memsize >> 20, memsize >> 30);
-    if memsize > GIGAPAGE_SIZE {
-        assert_eq!(memsize % GIGAPAGE_SIZE, 0);
-        map_gigapages(&mut bootinfo.free_memory, paging, memsize);
-    } else if memsize > MEGAPAGE_SIZE {
-        assert_eq!(memsize % MEGAPAGE_SIZE, 0);
-        todo!();
-    } else {
-        panic!("bruh");
+    // SAFETY: the kernel's tables have the new mappings and the loader
+    // doesn't call runtime services anymore
+    if let Err(e) = unsafe { rt.set_virtual_address_map(runtime_map) } {
+        brint!(bootinfo.fb, "SetVirtualAddressMap failed: {:?}\n", e);
+        return None;
     }
+
+    return Some(handoff::UefiRuntime { system_table, runtime_services, rwx_pages });
 }
 
 fn ref_to_addr<T: 'static>(r: *const T) -> u64 {
     r.addr() as u64
 }
 
-fn calc_exec_pagesize(pheaders: &[elf::ProgramHeader]) -> u64 {
-    const PAGE_ALIGN: u64 = 1 << 12;
-    let (start, end) = pheaders
+/// Size of the image the kernel is loaded into, from 0 to the end of the
+/// last page. Segments have to be sorted and can't share pages, as each one
+/// is mapped with its own permissions.
+fn image_size(pheaders: &[elf::ProgramHeader]) -> Result<u64, KernelError> {
+    let mut end = 0;
+    for ph in pheaders
         .iter()
         .filter(|ph| ph.segment_type() == Some(elf::SegmentType::Load))
-        .fold((u64::MAX, u64::MIN), |current, ph| {
-            assert_eq!(ph.p_align, PAGE_ALIGN);
+    {
+        let page_start = ph.p_vaddr & !(PAGE_SIZE - 1);
+        let page_end = ph
+            .p_vaddr
+            .checked_add(ph.p_memsz)
+            .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE));
+        match page_end {
+            Some(page_end) if ph.p_align == PAGE_SIZE && page_start >= end => end = page_end,
+            _ => return Err(KernelError::BadSegment(ph.p_vaddr)),
+        }
+    }
 
-            let segment_start = ph.p_vaddr & 0xFFF;
-            let segment_end = (ph.p_vaddr + ph.p_memsz).next_multiple_of(PAGE_ALIGN);
-            let min = core::cmp::min(current.0, segment_start);
-            let max = core::cmp::max(current.1, segment_end);
-            (min, max)
-        });
+    return Ok(end);
+}
+
+/// Page permissions for a kernel segment, refuses to map anything that is
+/// both writable and executable
+fn segment_flags(ph: &elf::ProgramHeader) -> Flags {
+    let (writable, executable) = (ph.p_flags.is_writable(), ph.p_flags.is_executable());
+    assert!(!(writable && executable), "kernel segment at {:#x} is both writable and executable", ph.p_vaddr);
 
-    assert_eq!(start, 0);
-    assert_eq!(end % PAGE_ALIGN, 0);
-    return end;
+    // Pages are always readable, there is no way to drop the R bit
+    let mut flags = Flags::new();
+    if writable {
+        flags = flags.set_writable();
+    }
+    if !executable {
+        flags = flags.set_no_execute();
+    }
+    return flags;
 }
 
-fn load_kernel(bootinfo: &mut Bootinfo, paging: NonNull<[*mut u8; 512]>) -> u64 {
-    let ker = elf::Elf::<elf::Amd64>::from_bytes(&KERNEL.0).unwrap();
+/// Loads the kernel at a random address above `KERNEL_BASE` and returns the
+/// virtual address of its entry point
+fn load_kernel(
+    bootinfo: &mut Bootinfo,
+    pml4: &mut Table<PML4Entry>,
+    handoff: &mut handoff::Writer,
+    seed: (u64, handoff::EntropySource),
+    kernel: &[u8],
+) -> u64 {
+    let ker = elf::Elf::<elf::Amd64>::from_bytes(kernel).unwrap();
     let ker_ph = ker.program_headers().unwrap();
-    let sz = calc_exec_pagesize(ker_ph);
+    let sz = image_size(ker_ph).unwrap();
+
+    let slide = kaslr_slide(seed.0, sz);
+    let base = KERNEL_BASE + slide;
+    brint!(bootinfo.fb, "kernel base={:x}, seed={} from {:?}\n", base, seed.0, seed.1);
+    let kaslr = handoff::KaslrInfo { slide, seed: seed.0, source: seed.1 as u32, reserved: 0 };
+    handoff.kaslr(&kaslr).unwrap();
 
     let instr = post_allocate_page(&mut bootinfo.free_memory, sz / 4096);
     let mut instr = NonNull::slice_from_raw_parts(instr, sz as usize);
@@ -413,16 +1123,18 @@ fn load_kernel(bootinfo: &mut Bootinfo, paging: NonNull<[*mut u8; 512]>) -> u64
         .iter()
         .filter(|ph| ph.segment_type() == Some(elf::SegmentType::Load))
     {
-        let flags = 0u64
-            | ((ph.p_flags.is_readable() as u64) << 0)
-            | ((ph.p_flags.is_writable() as u64) << 1)
-            | ((!ph.p_flags.is_executable() as u64) << 63);
-        let paddr_start = instr_addr + (ph.p_vaddr & !0xFFF);
-        let paddr_end = instr_addr + (ph.p_vaddr + ph.p_memsz).next_multiple_of(1 << 12);
-        for addr_to_map in (paddr_start..paddr_end).step_by(4096)
-        {
-            map_memory_page(&mut bootinfo.free_memory, paging, addr_to_map, flags);
+        let page_start = ph.p_vaddr & !(PAGE_SIZE - 1);
+        let page_end = (ph.p_vaddr + ph.p_memsz).next_multiple_of(PAGE_SIZE);
+        let virt = VirtAddr::new(base + page_start);
+        let phys = PhysAddr::new(instr_addr + page_start).unwrap();
+        brint!(bootinfo.fb, "segment {:x}..{:x} {:?}\n", virt.as_u64(), base + page_end, ph.p_flags);
+
+        // Segments sharing a page would need the union of their permissions
+        let mapped = mapper(&mut bootinfo.free_memory, pml4).map(virt, phys, page_end - page_start, segment_flags(ph));
+        if mapped == Err(MapError::AlreadyMapped) {
+            panic!("kernel segment at {:#x} shares a page with another one", ph.p_vaddr);
         }
+        mapped.unwrap();
 
         let fileoff = ph.p_offset as usize;
         let memoff = ph.p_vaddr as usize;
@@ -430,65 +1142,195 @@ fn load_kernel(bootinfo: &mut Bootinfo, paging: NonNull<[*mut u8; 512]>) -> u64
         let copy_end = memoff + to_copy;
         let to_zero = ph.p_memsz.checked_sub(ph.p_filesz).unwrap() as usize;
 
-        let src = &KERNEL.0[fileoff..][..to_copy];
+        let src = &kernel[fileoff..][..to_copy];
         let dst = &mut instr[memoff..][..to_copy];
         dst.copy_from_slice(src);
         let to_zero = &mut instr[copy_end..][..to_zero];
         to_zero.fill(0u8);
     }
 
-    let entry = ker.header().e_entry.unwrap().get() + instr_addr;
-    brint!(bootinfo.fb, "entry={:?}\n", entry);
+    // The kernel is linked at 0, move it to where it will run
+    let relocations = ker.relocations().unwrap_or_else(|e| panic!("bad kernel relocations: {:?}", e));
+    if let Err(e) = relocations.apply(instr, base) {
+        panic!("could not relocate the kernel: {:?}", e);
+    }
+
+    // Nothing writes there after relocation. Like in ld.so, a partial page
+    // at the end stays writable.
+    for ph in ker_ph
+        .iter()
+        .filter(|ph| ph.segment_type() == Some(elf::SegmentType::OsSpecificGnuRelro))
+    {
+        let start = ph.p_vaddr & !(PAGE_SIZE - 1);
+        let end = (ph.p_vaddr + ph.p_memsz) & !(PAGE_SIZE - 1);
+        if start >= end {
+            continue;
+        }
+
+        let flags = Flags::new().set_no_execute();
+        mapper(&mut bootinfo.free_memory, pml4)
+            .update_flags(VirtAddr::new(base + start), end - start, flags)
+            .unwrap();
+    }
+
+    let image = handoff::KernelImage {
+        phys_start: instr_addr,
+        virt_start: base,
+        size:       sz,
+    };
+    handoff.kernel_image(&image).unwrap();
+
+    let entry = ker.header().e_entry.unwrap().get() + base;
+    brint!(bootinfo.fb, "entry={:x}\n", entry);
     return entry;
 }
 
-fn post_boot_services(bootinfo: &'static mut Bootinfo) -> ! {
-    let mut paging = post_allocate_page(&mut bootinfo.free_memory, 1).cast::<[*mut u8; 512]>();
-    unsafe { paging.as_mut().fill(core::ptr::null_mut()); }
+fn post_boot_services(
+    bootinfo: &'static mut Bootinfo,
+    mut handoff: handoff::Writer<'static>,
+    seed: (u64, handoff::EntropySource),
+    kernel: &[u8],
+) -> ! {
+    let pml4 = post_allocate_page(&mut bootinfo.free_memory, 1).cast::<Table<PML4Entry>>();
+    // SAFETY: the page is free, so nobody else is using it
+    let pml4 = unsafe {
+        pml4.write(Table::new());
+        &mut *pml4.as_ptr()
+    };
+    let data_flags = Flags::new().set_writable().set_no_execute();
+
+    let entry = load_kernel(bootinfo, pml4, &mut handoff, seed, kernel);
 
-    let k_entry = load_kernel(bootinfo, paging);
+    let handoff_addr = ref_to_addr(handoff.as_ptr());
+    let handoff_size = handoff.capacity() as u64;
+    map_memory_range(&mut bootinfo.free_memory, pml4, handoff_addr, handoff_size, data_flags);
 
     let bootinfo_addr = ref_to_addr(bootinfo);
     brint!(bootinfo.fb, "bootinfo_addr={:x}\n", bootinfo_addr);
-    for offset in 0..BOOTINFO_SIZE_PAGES {
-        let phys = bootinfo_addr + offset * 4096;
-        map_memory_page(&mut bootinfo.free_memory, paging, phys, PRESENT | WRITABLE | NX);
-    }
+    let bootinfo_size = BOOTINFO_SIZE_PAGES * 4096;
+    map_memory_range(&mut bootinfo.free_memory, pml4, bootinfo_addr, bootinfo_size, data_flags);
 
+    // A copy of the screen is of no use to the kernel
+    let has_linear_fb = bootinfo.fb.flush.is_none();
     let fb_addr = ref_to_addr(bootinfo.fb.base);
     let fb_memsize = bootinfo.fb.memsize as u64;
-    assert!(fb_memsize % 4096 == 0);
-    brint!(bootinfo.fb, "fb_addr={:X}\n", fb_addr);
-    let fb_pagesize = fb_memsize / 4096;
-    for offset in 0..fb_pagesize {
-        let phys = fb_addr + offset * 4096;
-        map_memory_page(&mut bootinfo.free_memory, paging, phys, PRESENT | WRITABLE | NX);
+    if has_linear_fb {
+        brint!(bootinfo.fb, "fb_addr={:X}\n", fb_addr);
+        // GOP doesn't round it to pages, the tag keeps the real size
+        let fb_mapsize = fb_memsize.next_multiple_of(PAGE_SIZE);
+        map_memory_range(&mut bootinfo.free_memory, pml4, fb_addr, fb_mapsize, data_flags);
     }
 
+    let stack = post_allocate_page(&mut bootinfo.free_memory, KERNEL_STACK_SIZE / 4096);
+    let stack_addr = stack.addr().get() as u64;
+    map_memory_range(&mut bootinfo.free_memory, pml4, stack_addr, KERNEL_STACK_SIZE, data_flags);
+
+    // The instructions right after `mov cr3` are fetched from the same
+    // addresses, so the trampoline has to be identity-mapped
+    let trampoline = ref_to_addr(jump_to_kernel as *const ()) & !(PAGE_SIZE - 1);
+    let trampoline_phys = PhysAddr::new(trampoline).unwrap();
+    mapper(&mut bootinfo.free_memory, pml4)
+        .map(VirtAddr::new(trampoline), trampoline_phys, 2 * PAGE_SIZE, Flags::new())
+        .unwrap();
+
     brint!(bootinfo.fb, "Mapping memory\n");
-    map_whole_memory(bootinfo, paging);
+    map_whole_memory(bootinfo, pml4, sorted_memory_map(&handoff));
+    let (runtime_map, rwx_pages) = map_runtime(bootinfo, pml4, sorted_memory_map(&handoff));
+    let cr3 = cpu::Cr3::from_addr(PhysAddr::new(ref_to_addr(pml4)).unwrap());
+    let stack_top = stack_addr + KERNEL_STACK_SIZE + VIRT_OFFSET;
+    brint!(bootinfo.fb, "stack_top={:x}\n", stack_top);
+
+    if has_linear_fb {
+        let fb = handoff::FramebufferInfo {
+            base:            fb_addr + VIRT_OFFSET,
+            memsize:         fb_memsize,
+            scanline_width:  bootinfo.fb.scanline_width as u32,
+            width:           bootinfo.fb.width as u32,
+            height:          bootinfo.fb.height as u32,
+            bytes_per_pixel: bootinfo.fb.format.bytes_per_pixel() as u32,
+            format:          bootinfo.fb.format,
+        };
+        handoff.framebuffer(&fb).unwrap();
+    }
+
+    // The firmware runs it on its own tables, so it has to happen before
+    // the switch to the kernel's
+    if let Some(runtime) = enter_virtual_mode(bootinfo, runtime_map, sorted_memory_map(&handoff), rwx_pages) {
+        handoff.uefi_runtime(&runtime).unwrap();
+    }
+
+    // Nothing is allocated after this point
+    handoff.free_memory(bootinfo.free_memory.as_slice()).unwrap();
+    let handoff_virt = ref_to_addr(handoff.finish().as_ptr()) + VIRT_OFFSET;
+
+    // Last, so that SetVirtualAddressMap runs with the firmware's own
+    // descriptor tables
     brint!(bootinfo.fb, "Setting up IDT and GDT\n");
     setup_gdt(bootinfo);
-    setup_idt(bootinfo, k_entry);
+    setup_idt(bootinfo);
 
-    let cr3 = cpu::Cr3(paging.addr().get() as u64);
-    let new_stack_ptr = ref_to_addr(&bootinfo.buf) + VIRT_OFFSET + 4096;
-    brint!(bootinfo.fb, "new_stack_ptr={:x}\n", new_stack_ptr);
     brint!(bootinfo.fb, "Jump!\n");
+    setup_control_registers();
+
+    // SAFETY: the kernel, its stack, the handoff and the trampoline are mapped
+    unsafe { jump_to_kernel(cr3.0, stack_top, entry, handoff_virt) };
+}
 
-    // SAFETY: no
+/// Puts CR0, CR4 and EFER into the state promised by the handoff protocol
+fn setup_control_registers() {
+    use cpu::{Cr0, Cr4, Efer};
+
+    let cr4 = Cr4::get();
+    assert!(!cr4.la57(), "5-level paging is not supported");
+    let cr4 = cr4
+        .set_physical_address_extension()
+        .set_page_global()
+        .clear_supervisormode_exec_prot()
+        .clear_supervisormode_access_prot();
+
+    let cr0 = Cr0::get()
+        .set_protection_enable()
+        .set_monitor_coprocessor()
+        .set_numeric_error()
+        .set_write_protect()
+        .set_paging()
+        .clear_emulation()
+        .clear_task_switched()
+        .clear_alignment_check()
+        .clear_not_write_through()
+        .clear_cache_disable();
+
+    let efer = Efer::get();
+    assert!(efer.long_mode_active());
+    let efer = efer.set_no_execute_enable().clear_syscall_enable();
+
+    // SAFETY: we stay in long mode with paging enabled, NXE has to be set
+    // before CR3 points to tables that use the NX bit
     unsafe {
-        core::arch::asm!(
-            "mov rsp, {new_stack}",
-            "mov cr3, {cr3}",
-            "ud2",
-            new_stack = in(reg) new_stack_ptr,
-            cr3 = in(reg) cr3.0,
-            options(nostack, noreturn),
-        );
+        Efer::set(efer);
+        Cr4::set(cr4);
+        Cr0::set(cr0);
     }
 }
 
+/// Switches to the kernel's page tables and stack, then jumps to `entry`
+/// with `handoff` in `rdi`. Has to be identity-mapped in the new tables.
+#[naked]
+unsafe extern "sysv64" fn jump_to_kernel(cr3: u64, stack_top: u64, entry: u64, handoff: u64) -> ! {
+    core::arch::asm!("
+        mov cr3, rdi
+        mov rsp, rsi
+        mov rdi, rcx
+
+        # Zero return address and frame pointer end the call chain
+        xor ebp, ebp
+        push rbp
+        jmp rdx
+        ",
+        options(noreturn),
+    )
+}
+
 fn setup_gdt(bootinfo: &mut Bootinfo) {
     bootinfo.gdt = cpu::segmentation::GlobalDescriptorTable::new();
     unsafe { cpu::segmentation::Gdtr::new(&bootinfo.gdt).apply() };
@@ -502,14 +1344,13 @@ fn setup_gdt(bootinfo: &mut Bootinfo) {
     }
 }
 
-fn setup_idt(bootinfo: &mut Bootinfo, entry_after_jump: u64) {
+/// Loads an IDT with no present entries, the kernel is expected to set up
+/// its own before enabling interrupts
+fn setup_idt(bootinfo: &mut Bootinfo) {
     bootinfo.idt.fill(cpu::interrupt::Entry::new());
-    let addr = entry_after_jump + VIRT_OFFSET;
-    let flags = cpu::interrupt::Flags::new_interrupt().set_present();
-    bootinfo.idt[0xE] = cpu::interrupt::Entry::with_handler_and_flags(addr, flags);
 
-    let bootinfo_asdf: *const cpu::interrupt::Table = &bootinfo.idt;
-    let bootinfo_asdf = bootinfo_asdf.map_addr(|p| (p + VIRT_OFFSET as usize));
-    let idtr = cpu::interrupt::TableRegister { limit: 16 * 256 - 1, base: bootinfo_asdf };
+    let base: *const cpu::interrupt::Table = &bootinfo.idt;
+    let base = base.map_addr(|p| (p + VIRT_OFFSET as usize));
+    let idtr = cpu::interrupt::TableRegister { limit: 16 * 256 - 1, base };
     unsafe { idtr.apply(); }
 }
@@ -15,13 +15,46 @@ macro_rules! brint {
 
 fn print_help() -> Return {
     print!("Use these commands for xtask:\n\n");
-    println!("build");
-    println!("run");
+    println!("build [--kaslr-seed N] [--resolution WIDTHxHEIGHT]");
+    println!("run [--kaslr-seed N] [--resolution WIDTHxHEIGHT]");
     println!("clean [all, kernel, uefi_wrapper]");
     Ok(())
 }
 
-fn build(mut current_dir: PathBuf) -> Return {
+#[derive(Default)]
+struct BuildOptions {
+    /// Makes the loader always pick the same kernel address, for reproducing
+    /// crashes. The seed is printed by the loader on every boot.
+    kaslr_seed: Option<u64>,
+
+    /// Graphics mode the loader picks when the firmware has it, instead of
+    /// the biggest one
+    resolution: Option<(u32, u32)>,
+}
+
+fn parse_build_options(args: &[String]) -> Result<BuildOptions, Box<dyn Error>> {
+    let mut options = BuildOptions::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--kaslr-seed" => {
+                let seed = args.next().ok_or("--kaslr-seed needs a value")?;
+                options.kaslr_seed = Some(seed.parse()?);
+            },
+            "--resolution" => {
+                let resolution = args.next().ok_or("--resolution needs a value")?;
+                let (width, height) = resolution.split_once('x').ok_or("--resolution should be WIDTHxHEIGHT")?;
+                options.resolution = Some((width.parse()?, height.parse()?));
+            },
+            _ => return Err(format!("unknown option {:?}", arg).into()),
+        }
+    }
+
+    return Ok(options);
+}
+
+fn build(mut current_dir: PathBuf, options: &BuildOptions) -> Return {
     assert!(current_dir.is_absolute());
     current_dir.push("kernel");
 
@@ -38,14 +71,37 @@ fn build(mut current_dir: PathBuf) -> Return {
 
     let mut kernel_path = current_dir.clone();
     kernel_path.push("kernel/target/amd64-kernel-none/release/kernel");
+    let kernel_path = compress_kernel(kernel_path)?;
     current_dir.push("uefi_wrapper");
 
     brint!("Building uefi_wrapper\n");
-    let status = Command::new("cargo")
+    let mut cargo = Command::new("cargo");
+    cargo
         .current_dir(&current_dir)
         .args(&["build", "--release"])
-        .env("SOVOS_KERNEL_PATH", &kernel_path)
-        .status()?;
+        .env("SOVOS_KERNEL_PATH", &kernel_path);
+
+    match options.kaslr_seed {
+        Some(seed) => {
+            brint!("Pinning KASLR seed to {}\n", seed);
+            cargo.env("SOVOS_KASLR_SEED", seed.to_string());
+        },
+        None => {
+            cargo.env_remove("SOVOS_KASLR_SEED");
+        },
+    }
+
+    match options.resolution {
+        Some((width, height)) => {
+            brint!("Preferring {}x{} graphics mode\n", width, height);
+            cargo.env("SOVOS_RESOLUTION", format!("{}x{}", width, height));
+        },
+        None => {
+            cargo.env_remove("SOVOS_RESOLUTION");
+        },
+    }
+
+    let status = cargo.status()?;
 
     brint!("Cargo finished with {}\n", status);
     assert!(status.success());
@@ -54,6 +110,20 @@ fn build(mut current_dir: PathBuf) -> Return {
     return build_run_directory(current_dir);
 }
 
+/// Writes an LZ4 compressed copy of the kernel next to it, the loader
+/// decompresses it
+fn compress_kernel(kernel_path: PathBuf) -> Result<PathBuf, Box<dyn Error>> {
+    let kernel = fs::read(&kernel_path)?;
+    let mut compressed = vec![0u8; lz4::compress_bound(kernel.len())];
+    let len = lz4::compress(&kernel, &mut compressed).map_err(|e| format!("{:?}", e))?;
+    compressed.truncate(len);
+
+    brint!("Compressed kernel from {} to {} bytes\n", kernel.len(), len);
+    let compressed_path = kernel_path.with_extension("lz4");
+    fs::write(&compressed_path, &compressed)?;
+    return Ok(compressed_path);
+}
+
 fn build_run_directory(current_dir: PathBuf) -> Return {
     brint!("Building FAT directory structure\n");
 
@@ -62,7 +132,7 @@ fn build_run_directory(current_dir: PathBuf) -> Return {
     fs::create_dir_all(&boot)?;
     boot.push("BOOTx64.EFI");
 
-    let mut image = current_dir;
+    let mut image = current_dir.clone();
     image.push("uefi_wrapper/target/x86_64-unknown-uefi/release/uefi_wrapper.efi");
     
     // When the file is a symlink, for some reason QEMU corrupts the data
@@ -71,11 +141,22 @@ fn build_run_directory(current_dir: PathBuf) -> Return {
     brint!("Copying {} to {}\n", image.display(), boot.display());
     std::fs::copy(&image, &boot)?;
 
+    // The wrapper prefers this one over the kernel embedded in it
+    let mut sovos = current_dir.clone();
+    sovos.push("fat/sovos");
+    fs::create_dir_all(&sovos)?;
+    sovos.push("kernel");
+
+    let mut kernel = current_dir;
+    kernel.push("kernel/target/amd64-kernel-none/release/kernel.lz4");
+    brint!("Copying {} to {}\n", kernel.display(), sovos.display());
+    std::fs::copy(&kernel, &sovos)?;
+
     Ok(())
 }
 
-fn run(current_dir: PathBuf) -> Return {
-    build(current_dir.clone())?;
+fn run(current_dir: PathBuf, options: &BuildOptions) -> Return {
+    build(current_dir.clone(), options)?;
 
     brint!("Running QEMU (execve)\n");
     let qemu_args = [
@@ -157,8 +238,8 @@ fn main() -> Return {
     };
 
     return match first_arg.as_str() {
-        "build" => build(current_dir),
-        "run" => run(current_dir),
+        "build" => build(current_dir, &parse_build_options(rest)?),
+        "run" => run(current_dir, &parse_build_options(rest)?),
         "clean" => clean(current_dir, rest.get(0).map(|s| s.as_str()).unwrap_or("")),
         _ => print_help(),
     };